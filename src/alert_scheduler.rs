@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use chrono::Duration;
+
+use parse::Presence::*;
+use record::Record;
+use method::{SetRequestArgs, SetResponseArgs};
+use types::Date;
+use calendar_event::*;
+use tz::{TimeZoneName, pin_floating};
+
+// works out when the alerts on a set of calendar events go off, keeping a
+// queue of upcoming firings that can be updated as events change
+
+
+// how far ahead we'll look for firings before giving up
+const LOOKAHEAD_DAYS: i64 = 366 * 10;
+
+// the first window we look in. it's doubled until there's enough firings.
+const FIRST_WINDOW_DAYS: i64 = 1;
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AlertFiring {
+    pub time:           Date,
+    pub event_id:       String,
+    pub recurrence_id:  LocalDate,
+    pub start:          Date,
+    pub minutes_before: i32,
+    pub typ:            AlertType,
+}
+
+// firings are ordered by time, then by event and occurrence, then by their
+// position in the alerts list
+type QueueKey = (i64, String, LocalDate, usize);
+
+pub struct AlertScheduler {
+    events:  BTreeMap<String,CalendarEvent>,
+    tz:      Option<TimeZoneName>,
+    after:   Date,
+    horizon: Date,
+    queue:   BTreeMap<QueueKey,AlertFiring>,
+}
+
+// the most any alert on the event or its overrides is away from its start
+fn max_offset(e: &CalendarEvent) -> Duration {
+    let mut minutes = 0;
+    let mut add = |v: &Vec<Alert>| for a in v.iter() {
+        minutes = ::std::cmp::max(minutes, (a.minutes_before as i64).abs());
+    };
+    if let Some(ref v) = e.alerts {
+        add(v);
+    }
+    if let Some(ref x) = e.exceptions {
+        for p in x.values().filter_map(|p| p.as_ref()) {
+            if let Present(Some(ref v)) = p.alerts {
+                add(v);
+            }
+        }
+    }
+    Duration::minutes(minutes)
+}
+
+// the firings for an event in [from, to), with floating events taken to be
+// in the zone tz
+fn firings(e: &CalendarEvent, tz: &Option<TimeZoneName>, from: &Date, to: &Date) -> Vec<(QueueKey,AlertFiring)> {
+    if e.alerts.is_none() && e.exceptions.is_none() {
+        return vec!();
+    }
+
+    // floating times can be most of a day either side of UTC
+    let slack = max_offset(e) + Duration::days(2);
+    let mut out = vec!();
+    for o in e.occurrences_between(&Date(**from - slack), &Date(**to + slack)) {
+        let alerts = match o.event.alerts {
+            Some(ref v) => v,
+            None        => continue,
+        };
+        let start = pin_floating(&o.event.start, &o.event.start_time_zone, tz);
+        for (i, a) in alerts.iter().enumerate() {
+            let time = Date(*start - Duration::minutes(a.minutes_before as i64));
+            if *time < **from || *time >= **to {
+                continue;
+            }
+            let key = (time.timestamp(), e.id.clone(), o.recurrence_id.clone(), i);
+            out.push((key, AlertFiring {
+                time:           time,
+                event_id:       e.id.clone(),
+                recurrence_id:  o.recurrence_id.clone(),
+                start:          start.clone(),
+                minutes_before: a.minutes_before,
+                typ:            a.typ,
+            }));
+        }
+    }
+    out
+}
+
+impl AlertScheduler {
+    // a scheduler for firings from after onwards. floating and all-day
+    // events are taken to be in the zone tz.
+    pub fn new(after: &Date, tz: &Option<TimeZoneName>) -> AlertScheduler {
+        AlertScheduler {
+            events:  BTreeMap::new(),
+            tz:      tz.clone(),
+            after:   after.clone(),
+            horizon: after.clone(),
+            queue:   BTreeMap::new(),
+        }
+    }
+
+    pub fn event(&self, id: &str) -> Option<&CalendarEvent> {
+        self.events.get(id)
+    }
+
+    // add or replace an event
+    pub fn insert(&mut self, e: CalendarEvent) {
+        let id = e.id();
+        self.unqueue(&id);
+        let (after, horizon) = (self.after.clone(), self.horizon.clone());
+        self.queue.extend(firings(&e, &self.tz, &after, &horizon));
+        self.events.insert(id, e);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<CalendarEvent> {
+        self.unqueue(id);
+        self.events.remove(id)
+    }
+
+    fn unqueue(&mut self, id: &str) {
+        let keys: Vec<QueueKey> = self.queue.keys().filter(|k| k.1 == id).cloned().collect();
+        for k in keys.iter() {
+            self.queue.remove(k);
+        }
+    }
+
+    // apply the changes a set made. only the ones the response says
+    // happened are applied, and created events get their ids from it.
+    pub fn apply_set(&mut self, args: &SetRequestArgs<CalendarEvent>, response: &SetResponseArgs<CalendarEvent>) {
+        if let Present(ref create) = args.create {
+            for (cid, p) in create.iter() {
+                if let Some(created) = response.created.get(cid) {
+                    self.insert(CalendarEvent::default().updated_with(p).updated_with(created));
+                }
+            }
+        }
+
+        if let Present(ref update) = args.update {
+            for id in response.updated.iter() {
+                let e = match (self.events.get(id), update.get(id)) {
+                    (Some(e), Some(p)) => e.updated_with(p),
+                    _                  => continue,
+                };
+                self.insert(e);
+            }
+        }
+
+        for id in response.destroyed.iter() {
+            self.remove(id);
+        }
+    }
+
+    // queue up all the firings before to
+    fn extend(&mut self, to: &Date) {
+        if **to <= *self.horizon {
+            return;
+        }
+        let from = self.horizon.clone();
+        for e in self.events.values() {
+            self.queue.extend(firings(e, &self.tz, &from, to));
+        }
+        self.horizon = to.clone();
+    }
+
+    // the next n firings, in time order. there may be fewer if there aren't
+    // that many in the lookahead.
+    pub fn upcoming(&mut self, n: usize) -> Vec<AlertFiring> {
+        let limit = *self.after + Duration::days(LOOKAHEAD_DAYS);
+        let mut window = Duration::days(FIRST_WINDOW_DAYS);
+        while self.queue.len() < n && *self.horizon < limit {
+            let to = ::std::cmp::min(*self.horizon + window, limit);
+            self.extend(&Date(to));
+            window = window + window;
+        }
+        self.queue.values().take(n).cloned().collect()
+    }
+
+    // take all the firings due before now, and move the scheduler on to now
+    pub fn advance(&mut self, now: &Date) -> Vec<AlertFiring> {
+        if **now <= *self.after {
+            return vec!();
+        }
+        self.extend(now);
+        let rest = self.queue.split_off(&(now.timestamp(), String::new(), LocalDate::default(), 0));
+        let due = ::std::mem::replace(&mut self.queue, rest);
+        self.after = now.clone();
+        due.into_iter().map(|(_, f)| f).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use chrono::NaiveDate;
+    use tz::from_local;
+    use super::*;
+
+    fn at(d: u32, h: u32, m: u32) -> Date {
+        from_local(&None, &NaiveDate::from_ymd(2017, 1, d).and_hms(h, m, 0))
+    }
+
+    fn event(id: &str, start: Date, minutes_before: &[i32]) -> CalendarEvent {
+        CalendarEvent::default()
+            .with_id(id.to_string())
+            .with_start(start.clone())
+            .with_end(Date(*start + Duration::hours(1)))
+            .with_alerts(Some(minutes_before.iter().map(|m| Alert::default().with_minutes_before(*m)).collect()))
+    }
+
+    fn firings(s: &mut AlertScheduler, n: usize) -> Vec<(String,Date)> {
+        s.upcoming(n).into_iter().map(|f| (f.event_id, f.time)).collect()
+    }
+
+    fn scheduler() -> AlertScheduler {
+        let mut s = AlertScheduler::new(&at(1, 0, 0), &None);
+        s.insert(event("e1", at(2, 9, 0), &[15, 60]));
+        s.insert(event("e2", at(2, 8, 30), &[0]));
+        s
+    }
+
+    #[test]
+    fn firings_in_time_order() {
+        let mut s = scheduler();
+        // one far off, to make the window grow
+        s.insert(event("e3", at(30, 9, 0), &[10]));
+        assert_eq!(firings(&mut s, 4), vec!(
+            ("e1".to_string(), at(2, 8, 0)),
+            ("e2".to_string(), at(2, 8, 30)),
+            ("e1".to_string(), at(2, 8, 45)),
+            ("e3".to_string(), at(30, 8, 50)),
+        ));
+        assert_eq!(firings(&mut s, 10).len(), 4);
+    }
+
+    #[test]
+    fn set_changes_applied() {
+        let mut s = scheduler();
+        let mut args = SetRequestArgs::<CalendarEvent>::default();
+        let mut create = BTreeMap::new();
+        create.insert("k1".to_string(), event("", at(3, 9, 0), &[5]).to_partial());
+        create.insert("k2".to_string(), event("", at(3, 9, 0), &[5]).to_partial());
+        args.create = Present(create);
+        let mut update = BTreeMap::new();
+        update.insert("e1".to_string(), PartialCalendarEvent::default().with_alerts(Some(vec!(Alert::default().with_minutes_before(30)))));
+        update.insert("e3".to_string(), PartialCalendarEvent::default());
+        args.update = Present(update);
+        args.destroy = Present(vec!("e2".to_string()));
+
+        // k2 and e3 failed, so only the others happen
+        let mut response = SetResponseArgs::<CalendarEvent>::default();
+        response.created.insert("k1".to_string(), PartialCalendarEvent::default().with_id("e9".to_string()));
+        response.updated = vec!("e1".to_string());
+        response.destroyed = vec!("e2".to_string());
+        s.apply_set(&args, &response);
+
+        assert!(s.event("e2").is_none());
+        assert!(s.event("e3").is_none());
+        assert_eq!(firings(&mut s, 10), vec!(
+            ("e1".to_string(), at(2, 8, 30)),
+            ("e9".to_string(), at(3, 8, 55)),
+        ));
+    }
+
+    #[test]
+    fn advance_takes_due_firings() {
+        let mut s = scheduler();
+        assert_eq!(s.advance(&at(1, 12, 0)), vec!());
+        let due: Vec<Date> = s.advance(&at(2, 8, 40)).into_iter().map(|f| f.time).collect();
+        assert_eq!(due, vec!(at(2, 8, 0), at(2, 8, 30)));
+        assert_eq!(s.advance(&at(2, 8, 40)), vec!());
+        assert_eq!(firings(&mut s, 10), vec!(("e1".to_string(), at(2, 8, 45))));
+
+        // events added later don't fire in the past
+        s.insert(event("e4", at(2, 8, 50), &[30, 0]));
+        assert_eq!(firings(&mut s, 10), vec!(("e1".to_string(), at(2, 8, 45)), ("e4".to_string(), at(2, 8, 50))));
+    }
+}
@@ -10,15 +10,15 @@ use record::{Record, PartialRecord};
 
 
 make_record_type!(Calendar, PartialCalendar, "Calendar",
-    name:               String => "name",
-    color:              String => "color", // XXX CSS colour type?
-    sort_order:         u64    => "sortOrder",
-    is_visible:         bool   => "isVisible",
-    may_read_free_busy: bool   => "mayReadFreeBusy",
-    may_read_items:     bool   => "mayReadItems",
-    may_add_items:      bool   => "mayAddItems",
-    may_modify_items:   bool   => "mayModifyItems",
-    may_remove_items:   bool   => "mayRemoveItems",
-    may_rename:         bool   => "mayRename",
-    may_delete:         bool   => "mayDelete"
+    name:               String => "name"            (set_name, with_name),
+    color:              String => "color"           (set_color, with_color), // XXX CSS colour type?
+    sort_order:         u64    => "sortOrder"       (set_sort_order, with_sort_order),
+    is_visible:         bool   => "isVisible"       (set_is_visible, with_is_visible),
+    may_read_free_busy: bool   => "mayReadFreeBusy" (set_may_read_free_busy, with_may_read_free_busy) [server],
+    may_read_items:     bool   => "mayReadItems"    (set_may_read_items, with_may_read_items)         [server],
+    may_add_items:      bool   => "mayAddItems"     (set_may_add_items, with_may_add_items)           [server],
+    may_modify_items:   bool   => "mayModifyItems"  (set_may_modify_items, with_may_modify_items)     [server],
+    may_remove_items:   bool   => "mayRemoveItems"  (set_may_remove_items, with_may_remove_items)     [server],
+    may_rename:         bool   => "mayRename"       (set_may_rename, with_may_rename)                 [server],
+    may_delete:         bool   => "mayDelete"       (set_may_delete, with_may_delete)                 [server]
 );
@@ -10,6 +10,7 @@ use parse::Presence::*;
 use record;
 use record::{Record, PartialRecord};
 use types::{File,Date};
+use tz::TimeZoneName;
 
 
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
@@ -28,9 +29,11 @@ impl Default for LocalDate {
     }
 }
 
+// LocalDate is a date and time with no zone. it used to be written as just
+// the date, which FromJson couldn't read back and which lost the time.
 impl ToString for LocalDate {
     fn to_string(&self) -> String {
-        self.format("%Y-%m-%d").to_string()
+        self.format("%Y-%m-%dT%H:%M:%S").to_string()
     }
 }
 
@@ -65,21 +68,21 @@ make_prop_enum_type!(Frequency, "Frequency", Secondly, // XXX don't really want
     Secondly => "secondly"
 );
 
-make_prop_type!(Recurrence, "Recurrence",
-    frequency:         Frequency        => "frequency",
-    interval:          Option<i32>      => "interval",
-    first_day_of_week: Option<i32>      => "firstDayOfWeek", // XXX how to represent value rules?
-    by_day:            Option<Vec<i32>> => "byDay",
-    by_date:           Option<Vec<i32>> => "byDate",
-    by_month:          Option<Vec<i32>> => "byMonth",
-    by_year_day:       Option<Vec<i32>> => "byYearDay",
-    by_week_no:        Option<Vec<i32>> => "byWeekNo",
-    by_hour:           Option<Vec<i32>> => "byHour",
-    by_minute:         Option<Vec<i32>> => "byMinute",
-    by_second:         Option<Vec<i32>> => "bySecond",
-    by_set_position:   Option<Vec<i32>> => "bySetPosition",
-    count:             Option<u64>      => "count",
-    until:             LocalDate        => "until"
+make_prop_type!(Recurrence, RecurrenceBuilder, "Recurrence", check: Recurrence::validate,
+    frequency:         Frequency        => "frequency"      (set_frequency, with_frequency),
+    interval:          Option<i32>      => "interval"       (set_interval, with_interval),
+    first_day_of_week: Option<i32>      => "firstDayOfWeek" (set_first_day_of_week, with_first_day_of_week), // XXX how to represent value rules?
+    by_day:            Option<Vec<i32>> => "byDay"          (set_by_day, with_by_day),
+    by_date:           Option<Vec<i32>> => "byDate"         (set_by_date, with_by_date),
+    by_month:          Option<Vec<i32>> => "byMonth"        (set_by_month, with_by_month),
+    by_year_day:       Option<Vec<i32>> => "byYearDay"      (set_by_year_day, with_by_year_day),
+    by_week_no:        Option<Vec<i32>> => "byWeekNo"       (set_by_week_no, with_by_week_no),
+    by_hour:           Option<Vec<i32>> => "byHour"         (set_by_hour, with_by_hour),
+    by_minute:         Option<Vec<i32>> => "byMinute"       (set_by_minute, with_by_minute),
+    by_second:         Option<Vec<i32>> => "bySecond"       (set_by_second, with_by_second),
+    by_set_position:   Option<Vec<i32>> => "bySetPosition"  (set_by_set_position, with_by_set_position),
+    count:             Option<u64>      => "count"          (set_count, with_count),
+    until:             LocalDate        => "until"          (set_until, with_until)
 );
 
 make_prop_enum_type!(AlertType, "AlertType", Alert,
@@ -87,9 +90,9 @@ make_prop_enum_type!(AlertType, "AlertType", Alert,
     Alert => "alert"
 );
 
-make_prop_type!(Alert, "Alert",
-    minutes_before: i32       => "minutesBefore",
-    typ:            AlertType => "type"
+make_prop_type!(Alert, AlertBuilder, "Alert",
+    minutes_before: i32       => "minutesBefore" (set_minutes_before, with_minutes_before),
+    typ:            AlertType => "type"          (set_typ, with_typ)
 );
 
 make_prop_enum_type!(Rsvp, "Rsvp", None,
@@ -99,11 +102,11 @@ make_prop_enum_type!(Rsvp, "Rsvp", None,
     No    => "no"
 );
 
-make_prop_type!(Participant, "Participant",
-    name:   String => "name",
-    email:  String => "email",
-    is_you: bool   => "isYou",
-    rsvp:   Rsvp   => "rsvp"
+make_prop_type!(Participant, ParticipantBuilder, "Participant",
+    name:   String => "name"  (set_name, with_name),
+    email:  String => "email" (set_email, with_email),
+    is_you: bool   => "isYou" (set_is_you, with_is_you),
+    rsvp:   Rsvp   => "rsvp"  (set_rsvp, with_rsvp)
 );
 
 #[derive(Clone, PartialEq, Debug)]
@@ -130,19 +133,19 @@ impl FromJson for ExceptionMap {
     fn from_json(json: &Json) -> Result<ExceptionMap,ParseError> {
         match *json {
             Json::Object(ref v) => {
+                let mut errors = FieldErrors::new();
                 let mut d = BTreeMap::<LocalDate,Option<PartialCalendarEvent>>::new();
                 for (k,v) in v.iter() {
-                    let date = try!(LocalDate::from_json(&Json::String(k.clone()))); // XXX awkward
+                    let date = errors.check(LocalDate::from_json(&Json::String(k.clone()))); // XXX awkward
                     let obj = match *v { // XXX prefer FromJson for Option<T> but meh, compiler
-                        Json::Null => None,
-                        _ => {
-                            let p = try!(PartialCalendarEvent::from_json(v));
-                            Some(p)
-                        },
+                        Json::Null => Some(None),
+                        _          => errors.check(PartialCalendarEvent::from_json(v)).map(Some),
                     };
-                    d.insert(date, obj);
-
+                    if let (Some(date), Some(obj)) = (date, obj) {
+                        d.insert(date, obj);
+                    }
                 }
+                try!(errors.finish());
                 Ok(ExceptionMap(d))
             },
             _ => Err(ParseError::InvalidJsonType("ExceptionMap".to_string()))
@@ -151,21 +154,58 @@ impl FromJson for ExceptionMap {
 }
 
 make_record_type!(CalendarEvent, PartialCalendarEvent, "CalendarEvent",
-    calendar_id:     String                   => "calendarId",
-    summary:         String                   => "summary",
-    description:     String                   => "description",
-    location:        String                   => "location",
-    show_as_free:    bool                     => "showAsFree",
-    is_all_day:      bool                     => "isAllDay",
-    start:           Date                     => "start",
-    end:             Date                     => "end",
-    start_time_zone: Option<String>           => "startTimeZone",
-    end_time_zone:   Option<String>           => "endTimeZone",
-    recurrence:      Option<Recurrence>       => "recurrence",
-    inclusions:      Option<Vec<LocalDate>>   => "inclusions",
-    exceptions:      Option<ExceptionMap>     => "exceptions",
-    alerts:          Option<Vec<Alert>>       => "alerts",
-    organizer:       Option<Participant>      => "organizer",
-    attendees:       Option<Vec<Participant>> => "attendees",
-    attachments:     Option<Vec<File>>        => "attachments"
+    calendar_id:     String                   => "calendarId"    (set_calendar_id, with_calendar_id),
+    summary:         String                   => "summary"       (set_summary, with_summary),
+    description:     String                   => "description"   (set_description, with_description),
+    location:        String                   => "location"      (set_location, with_location),
+    show_as_free:    bool                     => "showAsFree"    (set_show_as_free, with_show_as_free),
+    is_all_day:      bool                     => "isAllDay"      (set_is_all_day, with_is_all_day),
+    start:           Date                     => "start"         (set_start, with_start),
+    end:             Date                     => "end"           (set_end, with_end),
+    start_time_zone: Option<TimeZoneName>     => "startTimeZone" (set_start_time_zone, with_start_time_zone),
+    end_time_zone:   Option<TimeZoneName>     => "endTimeZone"   (set_end_time_zone, with_end_time_zone),
+    recurrence:      Option<Recurrence>       => "recurrence"    (set_recurrence, with_recurrence),
+    inclusions:      Option<Vec<LocalDate>>   => "inclusions"    (set_inclusions, with_inclusions),
+    exceptions:      Option<ExceptionMap>     => "exceptions"    (set_exceptions, with_exceptions),
+    alerts:          Option<Vec<Alert>>       => "alerts"        (set_alerts, with_alerts),
+    organizer:       Option<Participant>      => "organizer"     (set_organizer, with_organizer),
+    attendees:       Option<Vec<Participant>> => "attendees"     (set_attendees, with_attendees),
+    attachments:     Option<Vec<File>>        => "attachments"   (set_attachments, with_attachments)
 );
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::{Json,ToJson};
+    use chrono::NaiveDate;
+    use parse::FromJson;
+    use super::*;
+
+    #[test]
+    fn local_date_round_trip() {
+        let d = LocalDate(NaiveDate::from_ymd(2017, 1, 5).and_hms(9, 30, 0));
+        assert_eq!(d.to_json(), Json::String("2017-01-05T09:30:00".to_string()));
+        assert_eq!(LocalDate::from_json(&d.to_json()), Ok(d));
+        assert!(LocalDate::from_json(&Json::String("2017-01-05".to_string())).is_err());
+    }
+
+    #[test]
+    fn exception_map_keys_round_trip() {
+        let json = Json::from_str(r#"{"2017-01-05T09:30:00": null}"#).unwrap();
+        let x = ExceptionMap::from_json(&json).unwrap();
+        assert_eq!(x.to_json(), json);
+    }
+
+    #[test]
+    fn exception_map_strict() {
+        let json = Json::from_str(r#"{"2017-01-05": null, "2017-01-06T09:30:00": {"summary": "x", "bogus": 1}}"#).unwrap();
+        assert!(ExceptionMap::from_json(&json).is_err());
+        match from_json_strict::<ExceptionMap>(&json) {
+            Err(ParseError::Multiple(v)) => {
+                assert_eq!(v.len(), 2);
+                assert!(v.contains(&ParseError::UnknownField("bogus".to_string())));
+            },
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
+use method::{MethodError, ResponseBatch};
+use types::Date;
+
+make_prop_type!(CalendarEventFilterOperator, CalendarEventFilterOperatorBuilder, "CalendarEventFilterOperator",
+    operator:   String                   => "operator"   (set_operator, with_operator),
+    conditions: Vec<CalendarEventFilter> => "conditions" (set_conditions, with_conditions)
+);
+
+make_prop_type!(CalendarEventFilterCondition, CalendarEventFilterConditionBuilder, "CalendarEventFilterCondition",
+    in_calendars: Presence<Vec<String>> => "inCalendars" (set_in_calendars, with_in_calendars),
+    after:        Presence<Date>        => "after"       (set_after, with_after),
+    before:       Presence<Date>        => "before"      (set_before, with_before),
+    text:         Presence<String>      => "text"        (set_text, with_text),
+    participant:  Presence<String>      => "participant" (set_participant, with_participant)
+);
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CalendarEventFilter {
+    Operator(CalendarEventFilterOperator),
+    Condition(CalendarEventFilterCondition),
+}
+
+impl ToJson for CalendarEventFilter {
+    fn to_json(&self) -> Json {
+        match *self {
+            CalendarEventFilter::Operator(ref o)  => o.to_json(),
+            CalendarEventFilter::Condition(ref c) => c.to_json(),
+        }
+    }
+}
+
+impl FromJson for CalendarEventFilter {
+    fn from_json(json: &Json) -> Result<CalendarEventFilter,ParseError> {
+        match *json {
+            Json::Object(ref o) => {
+                match o.get("operator") {
+                    Some(_) => Ok(CalendarEventFilter::Operator(try!(CalendarEventFilterOperator::from_json(json)))),
+                    None    => Ok(CalendarEventFilter::Condition(try!(CalendarEventFilterCondition::from_json(json)))),
+                }
+            },
+            _ => Err(ParseError::InvalidJsonType("CalendarEventFilter".to_string())),
+        }
+    }
+}
+
+make_method_args_type!(GetCalendarEventListRequestArgs, "GetCalendarEventListRequestArgs",
+    account_id:    Presence<String>              => "accountId",
+    filter:        Presence<CalendarEventFilter> => "filter",
+    sort:          Presence<Vec<String>>         => "sort",
+    position:      Presence<u64>                 => "position",
+    anchor:        Presence<String>              => "anchor",
+    anchor_offset: Presence<i64>                 => "anchorOffset",
+    limit:         Presence<u64>                 => "limit"
+);
+
+make_method_args_type!(GetCalendarEventListResponseArgs, "GetCalendarEventListResponseArgs",
+    account_id:            String                      => "accountId",
+    filter:                Option<CalendarEventFilter> => "filter",
+    sort:                  Vec<String>                 => "sort",
+    state:                 String                      => "state",
+    can_calculate_updates: bool                        => "canCalculateUpdates",
+    position:              u64                         => "position",
+    total:                 u64                         => "total",
+    calendar_event_ids:    Vec<String>                 => "calendarEventIds"
+);
@@ -0,0 +1,306 @@
+use std::cmp::Ordering;
+use chrono::{DateTime, UTC, NaiveDate, Duration};
+
+use parse::Presence;
+use parse::Presence::*;
+use method::{MethodError, ErrorDescription};
+use types::Date;
+use calendar_event::{CalendarEvent, LocalDate, Participant};
+use calendar_event_list::*;
+use tz::{TimeZoneName, from_local, pin_floating};
+use query::*;
+
+
+// the most occurrences of one event looked at for a time range condition.
+// an event still recurring after this many is taken to match, so a frequent
+// rule isn't expanded all the way to an open-ended range's end of time.
+const MAX_OCCURRENCES: usize = 10000;
+
+
+// evaluates getCalendarEventList queries over a set of events. floating and
+// all-day events are taken to be in the zone tz.
+pub struct CalendarEventQuery<'a> {
+    events: Vec<&'a CalendarEvent>,
+    tz:     Option<TimeZoneName>,
+}
+
+fn text_condition_matches(e: &CalendarEvent, s: &str) -> bool {
+    text_matches(&e.summary, s) || text_matches(&e.description, s) || text_matches(&e.location, s)
+}
+
+fn participant_matches(e: &CalendarEvent, s: &str) -> bool {
+    let matches = |p: &Participant| text_matches(&p.email, s);
+    e.organizer.as_ref().map(&matches).unwrap_or(false) ||
+        e.attendees.as_ref().map(|v| v.iter().any(&matches)).unwrap_or(false)
+}
+
+// the ends of time, for open-ended ranges
+fn earliest() -> Date {
+    Date(DateTime::<UTC>::from_utc(NaiveDate::from_ymd(1, 1, 1).and_hms(0, 0, 0), UTC))
+}
+
+fn latest() -> Date {
+    Date(DateTime::<UTC>::from_utc(NaiveDate::from_ymd(9999, 12, 31).and_hms(23, 59, 59), UTC))
+}
+
+// check the filter is something we can evaluate before running it
+pub fn validate_filter(filter: &CalendarEventFilter) -> Result<(),MethodError> {
+    match *filter {
+        CalendarEventFilter::Operator(ref o) => {
+            match o.operator.as_ref() {
+                "AND" | "OR" | "NOT" => (),
+                op => return Err(MethodError::InvalidArguments(Present(ErrorDescription(format!("unknown filter operator \"{}\"", op))))),
+            }
+            for f in o.conditions.iter() {
+                try!(validate_filter(f));
+            }
+            Ok(())
+        },
+        CalendarEventFilter::Condition(ref c) => {
+            if let (&Present(ref a), &Present(ref b)) = (&c.after, &c.before) {
+                if **a > **b {
+                    return Err(MethodError::InvalidArguments(Present(ErrorDescription("after must not be later than before".to_string()))));
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+pub fn validate_sort(sort: &[SortComparator]) -> Result<(),MethodError> {
+    for c in sort.iter() {
+        match c.property.as_ref() {
+            "start" | "end" | "summary" => (),
+            _ => return Err(MethodError::UnsupportedSort),
+        }
+    }
+    Ok(())
+}
+
+impl<'a> CalendarEventQuery<'a> {
+    pub fn new<I>(events: I, tz: &Option<TimeZoneName>) -> CalendarEventQuery<'a> where I: IntoIterator<Item=&'a CalendarEvent> {
+        CalendarEventQuery {
+            events: events.into_iter().collect(),
+            tz:     tz.clone(),
+        }
+    }
+
+    fn start(&self, e: &CalendarEvent) -> Date {
+        pin_floating(&e.start, &e.start_time_zone, &self.tz)
+    }
+
+    fn end(&self, e: &CalendarEvent) -> Date {
+        pin_floating(&e.end, &e.end_time_zone.clone().or(e.start_time_zone.clone()), &self.tz)
+    }
+
+    // whether any occurrence of the event overlaps [after, before), looking
+    // at no more than MAX_OCCURRENCES of them
+    fn occurs_between(&self, e: &CalendarEvent, after: &Date, before: &Date) -> bool {
+        // floating times can be most of a day either side of UTC
+        let end_of_window = Date(**before + Duration::days(2));
+        let in_window = |recurrence_id: &LocalDate| *from_local(&e.start_time_zone, recurrence_id) < *end_of_window;
+
+        let mut occurrences = e.occurrences();
+        for o in occurrences.by_ref().take(MAX_OCCURRENCES) {
+            if !in_window(&o.recurrence_id) {
+                return false;
+            }
+            let (start, end) = (self.start(&o.event), self.end(&o.event));
+            let overlaps = match *start == *end {
+                true => *start >= **after && *start < **before,
+                _    => *start < **before && *end > **after,
+            };
+            if overlaps {
+                return true;
+            }
+        }
+        match occurrences.next() {
+            Some(o) => in_window(&o.recurrence_id),
+            None    => false,
+        }
+    }
+
+    fn condition_matches(&self, e: &CalendarEvent, c: &CalendarEventFilterCondition) -> bool {
+        if let Present(ref ids) = c.in_calendars {
+            if !ids.contains(&e.calendar_id) { return false }
+        }
+        if let Present(ref s) = c.text {
+            if !text_condition_matches(e, s) { return false }
+        }
+        if let Present(ref s) = c.participant {
+            if !participant_matches(e, s) { return false }
+        }
+        match (&c.after, &c.before) {
+            (&Absent, &Absent) => (),
+            (after, before) => {
+                let after = after.as_option().cloned().unwrap_or_else(earliest);
+                let before = before.as_option().cloned().unwrap_or_else(latest);
+                if !self.occurs_between(e, &after, &before) { return false }
+            },
+        }
+        true
+    }
+
+    pub fn matches(&self, e: &CalendarEvent, filter: &CalendarEventFilter) -> bool {
+        match *filter {
+            CalendarEventFilter::Operator(ref o) => match o.operator.as_ref() {
+                "AND" => o.conditions.iter().all(|f| self.matches(e, f)),
+                "OR"  => o.conditions.iter().any(|f| self.matches(e, f)),
+                "NOT" => !o.conditions.iter().any(|f| self.matches(e, f)),
+                _     => false,
+            },
+            CalendarEventFilter::Condition(ref c) => self.condition_matches(e, c),
+        }
+    }
+
+    fn compare_property(&self, a: &CalendarEvent, b: &CalendarEvent, property: &str) -> Ordering {
+        match property {
+            "start"   => self.start(a).cmp(&self.start(b)),
+            "end"     => self.end(a).cmp(&self.end(b)),
+            "summary" => a.summary.to_lowercase().cmp(&b.summary.to_lowercase()),
+            _         => Ordering::Equal,
+        }
+    }
+
+    // compare two events by each comparator in turn, falling back to the id
+    // so the order is always stable
+    pub fn compare(&self, a: &CalendarEvent, b: &CalendarEvent, sort: &[SortComparator]) -> Ordering {
+        for c in sort.iter() {
+            match c.direct(self.compare_property(a, b, &c.property)) {
+                Ordering::Equal => continue,
+                o               => return o,
+            }
+        }
+        a.id.cmp(&b.id)
+    }
+
+    // the full filtered and sorted list
+    pub fn list(&self, filter: &Presence<CalendarEventFilter>, sort: &[SortComparator]) -> Result<Vec<&'a CalendarEvent>,MethodError> {
+        if let Present(ref f) = *filter {
+            try!(validate_filter(f));
+        }
+        try!(validate_sort(sort));
+
+        let mut list: Vec<&'a CalendarEvent> = self.events.iter().cloned().filter(|e| match *filter {
+            Present(ref f) => self.matches(e, f),
+            Absent         => true,
+        }).collect();
+        list.sort_by(|a, b| self.compare(a, b, sort));
+        Ok(list)
+    }
+
+    pub fn get_calendar_event_list(&self, args: &GetCalendarEventListRequestArgs, state: &str) -> Result<GetCalendarEventListResponseArgs,MethodError> {
+        let sort_strings = match args.sort {
+            Present(ref s) => s.clone(),
+            Absent         => vec!("start".to_string()),
+        };
+        let sort = try!(SortComparator::parse_all(&sort_strings));
+
+        let list = try!(self.list(&args.filter, &sort));
+
+        let position = try!(start_position(&args.position, &args.anchor, &args.anchor_offset,
+            |id| list.iter().position(|e| e.id == id)));
+        let window = page(&list, position, &args.limit);
+
+        let mut response = GetCalendarEventListResponseArgs::default();
+        response.account_id = match args.account_id {
+            Present(ref a) => a.clone(),
+            Absent         => "".to_string(),
+        };
+        response.filter = args.filter.as_option().cloned();
+        response.sort = sort_strings;
+        response.state = state.to_string();
+        response.position = position as u64;
+        response.total = list.len() as u64;
+        response.calendar_event_ids = window.iter().map(|e| e.id.clone()).collect();
+        Ok(response)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use parse::FromJson;
+    use calendar_event::{Recurrence, Frequency, LocalDate};
+    use tz::from_local;
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> Date {
+        from_local(&None, &NaiveDate::from_ymd(y, m, d).and_hms(h, 0, 0))
+    }
+
+    fn event(id: &str, calendar_id: &str, summary: &str, start: Date) -> CalendarEvent {
+        CalendarEvent::default()
+            .with_id(id.to_string())
+            .with_calendar_id(calendar_id.to_string())
+            .with_summary(summary.to_string())
+            .with_start(start.clone())
+            .with_end(Date(*start + Duration::hours(1)))
+            .with_start_time_zone(TimeZoneName::new("Etc/UTC"))
+    }
+
+    fn every(frequency: Frequency) -> Option<Recurrence> {
+        Some(Recurrence::builder().with_frequency(frequency).with_until(LocalDate::default()).build().unwrap())
+    }
+
+    fn events() -> Vec<CalendarEvent> {
+        vec!(
+            event("e1", "c1", "Standup", at(2017, 1, 2, 9)),
+            // Thursdays from December
+            event("e2", "c1", "Planning", at(2016, 12, 1, 9)).with_recurrence(every(Frequency::Weekly)),
+            event("e3", "c2", "Review", at(2017, 1, 20, 15)),
+        )
+    }
+
+    fn ids(filter: &str) -> Result<Vec<String>,MethodError> {
+        let events = events();
+        let q = CalendarEventQuery::new(&events, &None);
+        let args = GetCalendarEventListRequestArgs::from_json(&Json::from_str(&format!(r#"{{"filter":{}}}"#, filter)).unwrap()).unwrap();
+        q.get_calendar_event_list(&args, "s1").map(|r| r.calendar_event_ids)
+    }
+
+    #[test]
+    fn recurring_event_overlaps_window() {
+        assert_eq!(ids(r#"{"after":"2017-01-05T00:00:00Z","before":"2017-01-06T00:00:00Z"}"#).unwrap(), vec!("e2"));
+        assert_eq!(ids(r#"{"after":"2017-01-06T00:00:00Z","before":"2017-01-12T00:00:00Z"}"#).unwrap(), Vec::<String>::new());
+        assert_eq!(ids(r#"{"after":"2017-01-06T00:00:00Z","before":"2017-01-12T09:30:00Z"}"#).unwrap(), vec!("e2"));
+        // the occurrence only has to overlap
+        assert_eq!(ids(r#"{"after":"2017-01-02T09:30:00Z","before":"2017-01-02T11:00:00Z"}"#).unwrap(), vec!("e1"));
+        assert_eq!(ids(r#"{"after":"2017-01-02T10:00:00Z","before":"2017-01-02T11:00:00Z"}"#).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(ids(r#"{"after":"2017-01-10T00:00:00Z"}"#).unwrap(), vec!("e2", "e3"));
+        assert_eq!(ids(r#"{"before":"2016-12-31T00:00:00Z"}"#).unwrap(), vec!("e2"));
+        assert_eq!(ids(r#"{"before":"2017-01-03T00:00:00Z"}"#).unwrap(), vec!("e2", "e1"));
+        assert!(ids(r#"{"after":"2017-01-03T00:00:00Z","before":"2017-01-02T00:00:00Z"}"#).is_err());
+    }
+
+    #[test]
+    fn frequent_rules_not_expanded_forever() {
+        let mut events = vec!(
+            event("e1", "c1", "Tick", at(1970, 1, 1, 0)).with_recurrence(every(Frequency::Secondly)),
+        );
+        let mut ended = event("e2", "c1", "Done", at(2016, 1, 1, 0)).with_recurrence(every(Frequency::Daily));
+        ended.recurrence.as_mut().unwrap().count = Some(30);
+        events.push(ended);
+
+        let q = CalendarEventQuery::new(&events, &None);
+        let filter = CalendarEventFilter::from_json(&Json::from_str(r#"{"after":"2017-01-01T00:00:00Z"}"#).unwrap()).unwrap();
+        let list: Vec<&str> = q.list(&Present(filter), &[]).unwrap().iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(list, vec!("e1"));
+
+        // but not past the end of the window
+        let filter = CalendarEventFilter::from_json(&Json::from_str(r#"{"before":"1969-12-01T00:00:00Z"}"#).unwrap()).unwrap();
+        assert!(q.list(&Present(filter), &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn calendar_and_text_conditions() {
+        assert_eq!(ids(r#"{"inCalendars":["c2"]}"#).unwrap(), vec!("e3"));
+        assert_eq!(ids(r#"{"text":"stand"}"#).unwrap(), vec!("e1"));
+        assert_eq!(ids(r#"{"operator":"AND","conditions":[{"inCalendars":["c1"]},{"after":"2017-01-10T00:00:00Z"}]}"#).unwrap(), vec!("e2"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+use method::*;
+use method::RequestMethod::*;
+use method::ResponseMethod::*;
+use reference::ResultReference;
+
+use calendar::Calendar;
+use calendar_event::CalendarEvent;
+use contact::Contact;
+use contact_group::ContactGroup;
+use mailbox::Mailbox;
+use message::Message;
+use thread::Thread;
+
+use message_list::*;
+use calendar_event_list::*;
+use contact_list::*;
+use message_import::*;
+use message_copy::*;
+use message_report::*;
+use search_snippet::*;
+
+
+// trait for request arguments that can be sent as a method call, tying them
+// to the response arguments they produce
+pub trait Call: Sized {
+    type Response;
+
+    fn to_request(self, client_id: String) -> RequestMethod;
+    fn from_response(response: &ResponseMethod) -> Option<Self::Response>;
+    fn response_name() -> &'static str;
+}
+
+make_calls!(
+    GetRequestArgs<Calendar>,             GetCalendars            => GetResponseArgs<Calendar>,             Calendars,            "calendars",
+    GetUpdatesRequestArgs<Calendar>,      GetCalendarUpdates      => GetUpdatesResponseArgs<Calendar>,      CalendarUpdates,      "calendarUpdates",
+    SetRequestArgs<Calendar>,             SetCalendars            => SetResponseArgs<Calendar>,             CalendarsSet,         "calendarsSet",
+
+    GetRequestArgs<CalendarEvent>,        GetCalendarEvents       => GetResponseArgs<CalendarEvent>,        CalendarEvents,       "calendarEvents",
+    GetUpdatesRequestArgs<CalendarEvent>, GetCalendarEventUpdates => GetUpdatesResponseArgs<CalendarEvent>, CalendarEventUpdates, "calendarEventUpdates",
+    SetRequestArgs<CalendarEvent>,        SetCalendarEvents       => SetResponseArgs<CalendarEvent>,        CalendarEventsSet,    "calendarEventsSet",
+
+    GetRequestArgs<Contact>,              GetContacts             => GetResponseArgs<Contact>,              Contacts,             "contacts",
+    GetUpdatesRequestArgs<Contact>,       GetContactUpdates       => GetUpdatesResponseArgs<Contact>,       ContactUpdates,       "contactUpdates",
+    SetRequestArgs<Contact>,              SetContacts             => SetResponseArgs<Contact>,              ContactsSet,          "contactsSet",
+
+    GetRequestArgs<ContactGroup>,         GetContactGroups        => GetResponseArgs<ContactGroup>,         ContactGroups,        "contactGroups",
+    GetUpdatesRequestArgs<ContactGroup>,  GetContactGroupUpdates  => GetUpdatesResponseArgs<ContactGroup>,  ContactGroupUpdates,  "contactGroupUpdates",
+    SetRequestArgs<ContactGroup>,         SetContactGroups        => SetResponseArgs<ContactGroup>,         ContactGroupsSet,     "contactGroupsSet",
+
+    GetRequestArgs<Mailbox>,              GetMailboxes            => GetResponseArgs<Mailbox>,              Mailboxes,            "mailboxes",
+    GetUpdatesRequestArgs<Mailbox>,       GetMailboxUpdates       => GetUpdatesResponseArgs<Mailbox>,       MailboxUpdates,       "mailboxUpdates",
+    SetRequestArgs<Mailbox>,              SetMailboxes            => SetResponseArgs<Mailbox>,              MailboxesSet,         "mailboxesSet",
+
+    GetRequestArgs<Message>,              GetMessages             => GetResponseArgs<Message>,              Messages,             "messages",
+    GetUpdatesRequestArgs<Message>,       GetMessageUpdates       => GetUpdatesResponseArgs<Message>,       MessageUpdates,       "messageUpdates",
+    SetRequestArgs<Message>,              SetMessages             => SetResponseArgs<Message>,              MessagesSet,          "messagesSet",
+
+    GetRequestArgs<Thread>,               GetThreads              => GetResponseArgs<Thread>,               Threads,              "threads",
+    GetUpdatesRequestArgs<Thread>,        GetThreadUpdates        => GetUpdatesResponseArgs<Thread>,        ThreadUpdates,        "threadUpdates",
+
+    ImportMessagesRequestArgs,            ImportMessages          => ImportMessagesResponseArgs,            MessagesImported,     "messagesImported",
+    CopyMessagesRequestArgs,              CopyMessages            => CopyMessagesResponseArgs,              MessagesCopied,       "messagesCopied",
+    ReportMessagesRequestArgs,            ReportMessages          => ReportMessagesResponseArgs,            MessagesReported,     "messagesReported",
+
+    GetMessageListRequestArgs,            GetMessageList          => GetMessageListResponseArgs,            MessageList,          "messageList",
+    GetMessageListUpdatesRequestArgs,     GetMessageListUpdates   => GetMessageListUpdatesResponseArgs,     MessageListUpdates,   "messageListUpdates",
+    GetSearchSnippetsRequestArgs,         GetSearchSnippets       => GetSearchSnippetsResponseArgs,         SearchSnippets,       "searchSnippets",
+
+    GetCalendarEventListRequestArgs,      GetCalendarEventList    => GetCalendarEventListResponseArgs,      CalendarEventList,    "calendarEventList",
+
+    GetContactListRequestArgs,            GetContactList          => GetContactListResponseArgs,            ContactList,          "contactList"
+);
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CallError {
+    MissingResponse(String),
+    UnexpectedResponse(String, String),
+    MethodError(MethodError),
+}
+
+impl Error for CallError {
+    fn description(&self) -> &str {
+        match *self {
+            CallError::MissingResponse(_)       => "no response for method call",
+            CallError::UnexpectedResponse(_, _) => "unexpected response type for method call",
+            CallError::MethodError(_)           => "method call returned an error",
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallError::MissingResponse(ref id)            => write!(f, "no response for method call \"{}\"", id),
+            CallError::UnexpectedResponse(ref id, ref m)  => write!(f, "unexpected response \"{}\" for method call \"{}\"", m, id),
+            CallError::MethodError(ref e)                 => write!(f, "method call returned an error: {}", e),
+        }
+    }
+}
+
+
+// a typed reference to a call added to a RequestBuilder
+#[derive(Clone, PartialEq, Debug)]
+pub struct Handle<T: Call> {
+    client_id: String,
+    _marker:   PhantomData<T>,
+}
+
+impl<T: Call> Handle<T> {
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    // find the response for this call. a call may produce several responses
+    // with the same client id; the first of the right type wins.
+    pub fn response(&self, responses: &ResponseBatch) -> Result<T::Response,CallError> {
+        let mut unexpected = None;
+        for r in responses.0.iter().filter(|r| r.client_id() == self.client_id) {
+            if let Some(args) = T::from_response(r) {
+                return Ok(args);
+            }
+            match *r {
+                ResponseError(ref e, _) => return Err(CallError::MethodError(e.clone())),
+                _ => if unexpected.is_none() { unexpected = Some(r.name()) },
+            }
+        }
+        match unexpected {
+            Some(name) => Err(CallError::UnexpectedResponse(self.client_id.clone(), name.to_string())),
+            None       => Err(CallError::MissingResponse(self.client_id.clone())),
+        }
+    }
+
+    // a reference to part of this call's result, for use as an argument to a
+    // later call in the same batch
+    pub fn reference(&self, path: &str) -> ResultReference {
+        ResultReference::new(&self.client_id, T::response_name(), path)
+    }
+}
+
+
+// builds a RequestBatch, allocating client ids and handing back a Handle for
+// each call
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RequestBuilder {
+    batch:   RequestBatch,
+    next_id: u64,
+}
+
+impl RequestBuilder {
+    pub fn new() -> RequestBuilder {
+        RequestBuilder::default()
+    }
+
+    pub fn call<T: Call>(&mut self, args: T) -> Handle<T> {
+        let client_id = format!("c{}", self.next_id);
+        self.next_id += 1;
+        self.batch.0.push(args.to_request(client_id.clone()));
+        Handle {
+            client_id: client_id,
+            _marker:   PhantomData,
+        }
+    }
+
+    pub fn batch(&self) -> &RequestBatch {
+        &self.batch
+    }
+
+    pub fn build(self) -> RequestBatch {
+        self.batch
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_matched_by_client_id_and_type() {
+        let mut b = RequestBuilder::new();
+        let mailboxes = b.call(GetRequestArgs::<Mailbox>::default());
+        let messages = b.call(GetRequestArgs::<Message>::default());
+        let set = b.call(SetRequestArgs::<Mailbox>::default());
+        let updates = b.call(GetUpdatesRequestArgs::<Message>::default());
+        assert_eq!(b.batch().0.iter().map(|r| r.client_id()).collect::<Vec<_>>(), vec!("c0", "c1", "c2", "c3"));
+
+        let mut got = GetResponseArgs::<Mailbox>::default();
+        got.state = "s1".to_string();
+        let responses = ResponseBatch(vec!(
+            MailboxUpdates(GetUpdatesResponseArgs::default(), "c0".to_string()),
+            Mailboxes(got.clone(), "c0".to_string()),
+            ResponseError(MethodError::StateMismatch, "c1".to_string()),
+            Threads(GetResponseArgs::default(), "c3".to_string()),
+        ));
+
+        assert_eq!(mailboxes.response(&responses), Ok(got));
+        assert_eq!(messages.response(&responses), Err(CallError::MethodError(MethodError::StateMismatch)));
+        assert_eq!(set.response(&responses), Err(CallError::MissingResponse("c2".to_string())));
+        assert_eq!(updates.response(&responses), Err(CallError::UnexpectedResponse("c3".to_string(), "threads".to_string())));
+    }
+
+    #[test]
+    fn references_name_the_response() {
+        let mut b = RequestBuilder::new();
+        let list = b.call(GetMessageListRequestArgs::default());
+        let r = list.reference("/messageIds");
+        assert_eq!(r.result_of(), "c0");
+        assert_eq!(r.name(), "messageList");
+        assert_eq!(r.path(), "/messageIds");
+    }
+}
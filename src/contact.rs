@@ -132,6 +132,7 @@ pub struct ContactInformation<T: ContactType> {
     pub value:      String,
     pub label:      Option<String>,
     pub is_default: bool,
+    pub(crate) _extra: BTreeMap<String,Json>,
 }
 
 impl<T> Default for ContactInformation<T> where T: ContactType {
@@ -141,13 +142,14 @@ impl<T> Default for ContactInformation<T> where T: ContactType {
             value:      "".to_string(),
             label:      None,
             is_default: false,
+            _extra:     BTreeMap::new(),
         }
     }
 }
 
 impl<T> ToJson for ContactInformation<T> where T: ContactType {
     fn to_json(&self) -> Json {
-        let mut d = BTreeMap::<String,Json>::new();
+        let mut d = self._extra.clone();
         self.typ.to_json_field(&mut d, "type");
         self.value.to_json_field(&mut d, "value");
         self.label.to_json_field(&mut d, "label");
@@ -160,11 +162,22 @@ impl<T> FromJson for ContactInformation<T> where T: ContactType {
     fn from_json(json: &Json) -> Result<ContactInformation<T>,ParseError> {
         match *json {
             Json::Object(ref o) => {
+                let mut errors = FieldErrors::new();
                 let mut ci = ContactInformation::<T>::default();
-                ci.typ        = try!(FromJsonField::from_json_field(o, "type"));
-                ci.value      = try!(FromJsonField::from_json_field(o, "value"));
-                ci.label      = try!(FromJsonField::from_json_field(o, "label"));
-                ci.is_default = try!(FromJsonField::from_json_field(o, "isDefault"));
+                if let Some(v) = errors.check(FromJsonField::from_json_field(o, "type")) {
+                    ci.typ = v;
+                }
+                if let Some(v) = errors.check(FromJsonField::from_json_field(o, "value")) {
+                    ci.value = v;
+                }
+                if let Some(v) = errors.check(FromJsonField::from_json_field(o, "label")) {
+                    ci.label = v;
+                }
+                if let Some(v) = errors.check(FromJsonField::from_json_field(o, "isDefault")) {
+                    ci.is_default = v;
+                }
+                ci._extra = errors.extra(o, &["type", "value", "label", "isDefault"]);
+                try!(errors.finish());
                 Ok(ci)
             },
             _ => Err(ParseError::InvalidJsonType("ContactInformation".to_string())),
@@ -172,35 +185,81 @@ impl<T> FromJson for ContactInformation<T> where T: ContactType {
     }
 }
 
+impl<T> ContactInformation<T> where T: ContactType {
+    pub fn extra(&self) -> &BTreeMap<String,Json> {
+        &self._extra
+    }
+
+    pub fn set_extra(&mut self, name: &str, v: Json) {
+        self._extra.insert(name.to_string(), v);
+    }
+
+    pub fn with_extra(mut self, name: &str, v: Json) -> ContactInformation<T> {
+        self._extra.insert(name.to_string(), v);
+        self
+    }
+}
+
 
-make_prop_type!(Address, "Address",
-    typ:        AddressType    => "type",
-    label:      Option<String> => "label",
-    street:     String         => "street",
-    locality:   String         => "locality",
-    region:     String         => "region",
-    postcode:   String         => "postcode",
-    country:    String         => "country",
-    is_default: String         => "isDefault"
+make_prop_type!(Address, AddressBuilder, "Address",
+    typ:        AddressType    => "type"      (set_typ, with_typ),
+    label:      Option<String> => "label"     (set_label, with_label),
+    street:     String         => "street"    (set_street, with_street),
+    locality:   String         => "locality"  (set_locality, with_locality),
+    region:     String         => "region"    (set_region, with_region),
+    postcode:   String         => "postcode"  (set_postcode, with_postcode),
+    country:    String         => "country"   (set_country, with_country),
+    is_default: String         => "isDefault" (set_is_default, with_is_default)
 );
 
 
 make_record_type!(Contact, PartialContact, "Contact",
-    is_flagged:          bool                                => "isFlagged",
-    avatar:              Option<File>                        => "avatar",
-    prefix:              String                              => "prefix",
-    first_name:          String                              => "firstName",
-    last_name:           String                              => "lastName",
-    suffix:              String                              => "suffix",
-    nickname:            String                              => "nickname",
-    birthday:            OptionDate                          => "birthday",
-    anniversary:         OptionDate                          => "anniversary",
-    company:             String                              => "company",
-    department:          String                              => "department",
-    job_title:           String                              => "jobTitle",
-    emails:              Vec<ContactInformation<EmailType>>  => "emails",
-    phones:              Vec<ContactInformation<PhoneType>>  => "phones",
-    online:              Vec<ContactInformation<OnlineType>> => "online",
-    addresses:           Vec<Address>                        => "addresses",
-    notes:               String                              => "notes"
+    is_flagged:  bool                                => "isFlagged"   (set_is_flagged, with_is_flagged),
+    avatar:      Option<File>                        => "avatar"      (set_avatar, with_avatar),
+    prefix:      String                              => "prefix"      (set_prefix, with_prefix),
+    first_name:  String                              => "firstName"   (set_first_name, with_first_name),
+    last_name:   String                              => "lastName"    (set_last_name, with_last_name),
+    suffix:      String                              => "suffix"      (set_suffix, with_suffix),
+    nickname:    String                              => "nickname"    (set_nickname, with_nickname),
+    birthday:    OptionDate                          => "birthday"    (set_birthday, with_birthday),
+    anniversary: OptionDate                          => "anniversary" (set_anniversary, with_anniversary),
+    company:     String                              => "company"     (set_company, with_company),
+    department:  String                              => "department"  (set_department, with_department),
+    job_title:   String                              => "jobTitle"    (set_job_title, with_job_title),
+    emails:      Vec<ContactInformation<EmailType>>  => "emails"      (set_emails, with_emails),
+    phones:      Vec<ContactInformation<PhoneType>>  => "phones"      (set_phones, with_phones),
+    online:      Vec<ContactInformation<OnlineType>> => "online"      (set_online, with_online),
+    addresses:   Vec<Address>                        => "addresses"   (set_addresses, with_addresses),
+    notes:       String                              => "notes"       (set_notes, with_notes)
 );
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::json::Json;
+
+    #[test]
+    fn contact_information_keeps_unknown_properties() {
+        let json = Json::from_str(r#"{"type":"work","value":"a@example.com","label":null,"isDefault":true,"x-pref":1}"#).unwrap();
+        let ci = ContactInformation::<EmailType>::from_json(&json).unwrap();
+        assert_eq!(ci.extra().get("x-pref"), Some(&Json::U64(1)));
+        assert_eq!(ci.to_json(), json);
+
+        let ci = ContactInformation::<EmailType>::default().with_extra("x-pref", Json::U64(2));
+        assert_eq!(ci.to_json().find("x-pref"), Some(&Json::U64(2)));
+    }
+
+    #[test]
+    fn contact_information_strict() {
+        let json = Json::from_str(r#"{"type":"work","value":1,"isDefault":"yes","x-pref":1}"#).unwrap();
+        match from_json_strict::<ContactInformation<EmailType>>(&json) {
+            Err(ParseError::Multiple(v)) => {
+                assert_eq!(v.len(), 3);
+                assert!(v.contains(&ParseError::UnknownField("x-pref".to_string())));
+            },
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(ContactInformation::<EmailType>::from_json(&json).is_err());
+    }
+}
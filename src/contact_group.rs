@@ -10,6 +10,6 @@ use record::{Record, PartialRecord};
 
 
 make_record_type!(ContactGroup, PartialContactGroup, "ContactGroup",
-    name:        String      => "name",
-    contact_ids: Vec<String> => "contactIds"
+    name:        String      => "name"       (set_name, with_name),
+    contact_ids: Vec<String> => "contactIds" (set_contact_ids, with_contact_ids)
 );
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
+use method::{MethodError, ResponseBatch};
+
+make_prop_type!(ContactFilterOperator, ContactFilterOperatorBuilder, "ContactFilterOperator",
+    operator:   String             => "operator"   (set_operator, with_operator),
+    conditions: Vec<ContactFilter> => "conditions" (set_conditions, with_conditions)
+);
+
+make_prop_type!(ContactFilterCondition, ContactFilterConditionBuilder, "ContactFilterCondition",
+    in_contact_group: Presence<Vec<String>> => "inContactGroup" (set_in_contact_group, with_in_contact_group),
+    is_flagged:       Presence<bool>        => "isFlagged"      (set_is_flagged, with_is_flagged),
+    text:             Presence<String>      => "text"           (set_text, with_text)
+);
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ContactFilter {
+    Operator(ContactFilterOperator),
+    Condition(ContactFilterCondition),
+}
+
+impl ToJson for ContactFilter {
+    fn to_json(&self) -> Json {
+        match *self {
+            ContactFilter::Operator(ref o)  => o.to_json(),
+            ContactFilter::Condition(ref c) => c.to_json(),
+        }
+    }
+}
+
+impl FromJson for ContactFilter {
+    fn from_json(json: &Json) -> Result<ContactFilter,ParseError> {
+        match *json {
+            Json::Object(ref o) => {
+                match o.get("operator") {
+                    Some(_) => Ok(ContactFilter::Operator(try!(ContactFilterOperator::from_json(json)))),
+                    None    => Ok(ContactFilter::Condition(try!(ContactFilterCondition::from_json(json)))),
+                }
+            },
+            _ => Err(ParseError::InvalidJsonType("ContactFilter".to_string())),
+        }
+    }
+}
+
+make_method_args_type!(GetContactListRequestArgs, "GetContactListRequestArgs",
+    account_id:    Presence<String>        => "accountId",
+    filter:        Presence<ContactFilter> => "filter",
+    sort:          Presence<Vec<String>>   => "sort",
+    position:      Presence<u64>           => "position",
+    anchor:        Presence<String>        => "anchor",
+    anchor_offset: Presence<i64>           => "anchorOffset",
+    limit:         Presence<u64>           => "limit"
+);
+
+make_method_args_type!(GetContactListResponseArgs, "GetContactListResponseArgs",
+    account_id:            String                => "accountId",
+    filter:                Option<ContactFilter> => "filter",
+    sort:                  Vec<String>           => "sort",
+    state:                 String                => "state",
+    can_calculate_updates: bool                  => "canCalculateUpdates",
+    position:              u64                   => "position",
+    total:                 u64                   => "total",
+    contact_ids:           Vec<String>           => "contactIds"
+);
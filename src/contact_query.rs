@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use parse::Presence;
+use parse::Presence::*;
+use method::{MethodError, ErrorDescription};
+use contact::Contact;
+use contact_group::ContactGroup;
+use contact_list::*;
+use query::*;
+
+
+// evaluates getContactList queries over a set of contacts, using the groups
+// for the inContactGroup condition
+pub struct ContactQuery<'a> {
+    contacts: Vec<&'a Contact>,
+    groups:   BTreeMap<&'a str,BTreeSet<&'a str>>,
+}
+
+fn text_condition_matches(c: &Contact, s: &str) -> bool {
+    let names = [&c.prefix, &c.first_name, &c.last_name, &c.suffix, &c.nickname, &c.company];
+    names.iter().any(|n| text_matches(n, s)) ||
+        text_matches(&format!("{} {}", c.first_name, c.last_name), s) ||
+        c.emails.iter().any(|e| text_matches(&e.value, s)) ||
+        c.phones.iter().any(|p| text_matches(&p.value, s))
+}
+
+// check the filter is something we can evaluate before running it
+pub fn validate_filter(filter: &ContactFilter) -> Result<(),MethodError> {
+    match *filter {
+        ContactFilter::Operator(ref o) => {
+            match o.operator.as_ref() {
+                "AND" | "OR" | "NOT" => (),
+                op => return Err(MethodError::InvalidArguments(Present(ErrorDescription(format!("unknown filter operator \"{}\"", op))))),
+            }
+            for f in o.conditions.iter() {
+                try!(validate_filter(f));
+            }
+            Ok(())
+        },
+        ContactFilter::Condition(_) => Ok(()),
+    }
+}
+
+pub fn validate_sort(sort: &[SortComparator]) -> Result<(),MethodError> {
+    for c in sort.iter() {
+        match c.property.as_ref() {
+            "lastName" | "firstName" | "company" => (),
+            _ => return Err(MethodError::UnsupportedSort),
+        }
+    }
+    Ok(())
+}
+
+impl<'a> ContactQuery<'a> {
+    pub fn new<I,G>(contacts: I, groups: G) -> ContactQuery<'a>
+        where I: IntoIterator<Item=&'a Contact>, G: IntoIterator<Item=&'a ContactGroup> {
+        let groups = groups.into_iter()
+            .map(|g| (g.id.as_str(), g.contact_ids.iter().map(|id| id.as_str()).collect()))
+            .collect();
+        ContactQuery {
+            contacts: contacts.into_iter().collect(),
+            groups:   groups,
+        }
+    }
+
+    fn in_group(&self, c: &Contact, group_id: &str) -> bool {
+        match self.groups.get(group_id) {
+            Some(ids) => ids.contains(c.id.as_str()),
+            None      => false,
+        }
+    }
+
+    fn condition_matches(&self, c: &Contact, cond: &ContactFilterCondition) -> bool {
+        if let Present(ref ids) = cond.in_contact_group {
+            if !ids.iter().any(|id| self.in_group(c, id)) { return false }
+        }
+        if let Present(f) = cond.is_flagged {
+            if f != c.is_flagged { return false }
+        }
+        if let Present(ref s) = cond.text {
+            if !text_condition_matches(c, s) { return false }
+        }
+        true
+    }
+
+    pub fn matches(&self, c: &Contact, filter: &ContactFilter) -> bool {
+        match *filter {
+            ContactFilter::Operator(ref o) => match o.operator.as_ref() {
+                "AND" => o.conditions.iter().all(|f| self.matches(c, f)),
+                "OR"  => o.conditions.iter().any(|f| self.matches(c, f)),
+                "NOT" => !o.conditions.iter().any(|f| self.matches(c, f)),
+                _     => false,
+            },
+            ContactFilter::Condition(ref cond) => self.condition_matches(c, cond),
+        }
+    }
+
+    fn compare_property(&self, a: &Contact, b: &Contact, property: &str) -> Ordering {
+        match property {
+            "lastName"  => a.last_name.to_lowercase().cmp(&b.last_name.to_lowercase()),
+            "firstName" => a.first_name.to_lowercase().cmp(&b.first_name.to_lowercase()),
+            "company"   => a.company.to_lowercase().cmp(&b.company.to_lowercase()),
+            _           => Ordering::Equal,
+        }
+    }
+
+    // compare two contacts by each comparator in turn, falling back to the id
+    // so the order is always stable
+    pub fn compare(&self, a: &Contact, b: &Contact, sort: &[SortComparator]) -> Ordering {
+        for c in sort.iter() {
+            match c.direct(self.compare_property(a, b, &c.property)) {
+                Ordering::Equal => continue,
+                o               => return o,
+            }
+        }
+        a.id.cmp(&b.id)
+    }
+
+    // the full filtered and sorted list
+    pub fn list(&self, filter: &Presence<ContactFilter>, sort: &[SortComparator]) -> Result<Vec<&'a Contact>,MethodError> {
+        if let Present(ref f) = *filter {
+            try!(validate_filter(f));
+        }
+        try!(validate_sort(sort));
+
+        let mut list: Vec<&'a Contact> = self.contacts.iter().cloned().filter(|c| match *filter {
+            Present(ref f) => self.matches(c, f),
+            Absent         => true,
+        }).collect();
+        list.sort_by(|a, b| self.compare(a, b, sort));
+        Ok(list)
+    }
+
+    pub fn get_contact_list(&self, args: &GetContactListRequestArgs, state: &str) -> Result<GetContactListResponseArgs,MethodError> {
+        let sort_strings = match args.sort {
+            Present(ref s) => s.clone(),
+            Absent         => vec!("lastName".to_string(), "firstName".to_string()),
+        };
+        let sort = try!(SortComparator::parse_all(&sort_strings));
+
+        let list = try!(self.list(&args.filter, &sort));
+
+        let position = try!(start_position(&args.position, &args.anchor, &args.anchor_offset,
+            |id| list.iter().position(|c| c.id == id)));
+        let window = page(&list, position, &args.limit);
+
+        let mut response = GetContactListResponseArgs::default();
+        response.account_id = match args.account_id {
+            Present(ref a) => a.clone(),
+            Absent         => "".to_string(),
+        };
+        response.filter = args.filter.as_option().cloned();
+        response.sort = sort_strings;
+        response.state = state.to_string();
+        response.position = position as u64;
+        response.total = list.len() as u64;
+        response.contact_ids = window.iter().map(|c| c.id.clone()).collect();
+        Ok(response)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use parse::FromJson;
+    use contact::{ContactInformation, EmailType};
+    use super::*;
+
+    fn contact(id: &str, first: &str, last: &str, company: &str) -> Contact {
+        Contact::default()
+            .with_id(id.to_string())
+            .with_first_name(first.to_string())
+            .with_last_name(last.to_string())
+            .with_company(company.to_string())
+    }
+
+    fn contacts() -> Vec<Contact> {
+        let mut email = ContactInformation::<EmailType>::default();
+        email.value = "ada@engines.example".to_string();
+        vec!(
+            contact("c1", "Ada", "Lovelace", "Engines").with_emails(vec!(email)),
+            contact("c2", "Charles", "Babbage", "Engines").with_is_flagged(true),
+            contact("c3", "Grace", "Hopper", "Navy").with_is_flagged(true),
+            contact("c4", "Alan", "Turing", ""),
+        )
+    }
+
+    fn ids(args: &str) -> Result<Vec<String>,MethodError> {
+        let contacts = contacts();
+        let groups = vec!(ContactGroup::default().with_id("g1".to_string()).with_contact_ids(vec!("c1".to_string(), "c4".to_string())));
+        let q = ContactQuery::new(&contacts, &groups);
+        let args = GetContactListRequestArgs::from_json(&Json::from_str(args).unwrap()).unwrap();
+        q.get_contact_list(&args, "s1").map(|r| r.contact_ids)
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(ids(r#"{"filter":{"text":"engines"}}"#).unwrap(), vec!("c2", "c1"));
+        assert_eq!(ids(r#"{"filter":{"text":"ada@"}}"#).unwrap(), vec!("c1"));
+        assert_eq!(ids(r#"{"filter":{"text":"grace hopper"}}"#).unwrap(), vec!("c3"));
+        assert_eq!(ids(r#"{"filter":{"inContactGroup":["g1","nope"]}}"#).unwrap(), vec!("c1", "c4"));
+        assert_eq!(ids(r#"{"filter":{"isFlagged":true}}"#).unwrap(), vec!("c2", "c3"));
+        assert_eq!(ids(r#"{"filter":{"operator":"NOT","conditions":[{"isFlagged":true},{"text":"alan"}]}}"#).unwrap(), vec!("c1"));
+        assert_eq!(ids(r#"{"filter":{"operator":"OR","conditions":[{"text":"navy"},{"inContactGroup":["g1"]}]}}"#).unwrap(), vec!("c3", "c1", "c4"));
+        assert!(ids(r#"{"filter":{"operator":"XOR","conditions":[]}}"#).is_err());
+    }
+
+    #[test]
+    fn sorting() {
+        assert_eq!(ids(r#"{}"#).unwrap(), vec!("c2", "c3", "c1", "c4"));
+        assert_eq!(ids(r#"{"sort":["company desc","firstName"]}"#).unwrap(), vec!("c3", "c1", "c2", "c4"));
+        assert_eq!(ids(r#"{"sort":["isFlagged"]}"#), Err(MethodError::UnsupportedSort));
+    }
+
+    #[test]
+    fn paging() {
+        assert_eq!(ids(r#"{"position":1,"limit":2}"#).unwrap(), vec!("c3", "c1"));
+        assert_eq!(ids(r#"{"anchor":"c1","anchorOffset":1,"limit":2}"#).unwrap(), vec!("c3", "c1"));
+        assert_eq!(ids(r#"{"anchor":"c1","anchorOffset":-1}"#).unwrap(), vec!("c4"));
+        assert_eq!(ids(r#"{"anchor":"c1","anchorOffset":9223372036854775807}"#).unwrap(), vec!("c2", "c3", "c1", "c4"));
+        assert_eq!(ids(r#"{"anchor":"c9"}"#), Err(MethodError::AnchorNotFound));
+    }
+}
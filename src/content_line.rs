@@ -0,0 +1,384 @@
+use parse::ParseError;
+
+// the content line format shared by iCalendar (RFC 5545) and vCard
+// (RFC 6350): unfolding, parameters, escaping and BEGIN/END components
+
+
+// longest line we'll write, in octets, not counting the CRLF
+const LINE_LENGTH: usize = 75;
+
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ContentLine {
+    pub group:  Option<String>,
+    pub name:   String,
+    pub params: Vec<(String,Vec<String>)>,
+    pub value:  String,
+}
+
+impl ContentLine {
+    pub fn new(name: &str, value: &str) -> ContentLine {
+        ContentLine {
+            group:  None,
+            name:   name.to_string(),
+            params: vec!(),
+            value:  value.to_string(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> ContentLine {
+        self.params.push((name.to_string(), vec!(value.to_string())));
+        self
+    }
+
+    // all the values of a parameter (names are case-insensitive)
+    pub fn param_values(&self, name: &str) -> Vec<&str> {
+        self.params.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|&(_, ref v)| v.iter().map(|s| s.as_ref()))
+            .collect()
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.param_values(name).into_iter().next()
+    }
+
+    // parse a single unfolded line. the name is uppercased.
+    pub fn parse(line: &str) -> Result<ContentLine,ParseError> {
+        let mut cl = ContentLine::default();
+
+        // the name runs up to the first ; or :
+        let name_end = match line.find(|c| c == ';' || c == ':') {
+            Some(i) => i,
+            None    => return Err(ParseError::InvalidStructure("ContentLine".to_string())),
+        };
+        let name = &line[..name_end];
+        match name.rfind('.') {
+            Some(i) => {
+                cl.group = Some(name[..i].to_string());
+                cl.name = name[i+1..].to_uppercase();
+            },
+            None => cl.name = name.to_uppercase(),
+        }
+        if cl.name.is_empty() {
+            return Err(ParseError::InvalidStructure("ContentLine".to_string()));
+        }
+
+        // then the parameters, whose values may be quoted, and then the value
+        let mut rest = &line[name_end..];
+        while rest.starts_with(';') {
+            rest = &rest[1..];
+            let eq = match rest.find(|c| c == '=' || c == ';' || c == ':') {
+                Some(i) => i,
+                None    => return Err(ParseError::InvalidStructure("ContentLine".to_string())),
+            };
+            let pname = rest[..eq].to_uppercase();
+            let mut values = vec!();
+            if rest[eq..].starts_with('=') {
+                rest = &rest[eq..];
+                while rest.starts_with('=') || rest.starts_with(',') {
+                    rest = &rest[1..];
+                    if rest.starts_with('"') {
+                        let close = match rest[1..].find('"') {
+                            Some(i) => i + 1,
+                            None    => return Err(ParseError::InvalidStructure("ContentLine".to_string())),
+                        };
+                        values.push(decode_param(&rest[1..close]));
+                        rest = &rest[close+1..];
+                    }
+                    else {
+                        let end = rest.find(|c| c == ',' || c == ';' || c == ':').unwrap_or(rest.len());
+                        values.push(decode_param(&rest[..end]));
+                        rest = &rest[end..];
+                    }
+                }
+            }
+            else {
+                // vCard 2.1 style bare parameter, eg TEL;WORK:...
+                values.push(pname.clone());
+                rest = &rest[eq..];
+                cl.params.push(("TYPE".to_string(), values));
+                continue;
+            }
+            cl.params.push((pname, values));
+        }
+
+        match rest.starts_with(':') {
+            true => cl.value = rest[1..].to_string(),
+            _    => return Err(ParseError::InvalidStructure("ContentLine".to_string())),
+        }
+        Ok(cl)
+    }
+
+    // the line as it should be written: folded, with a trailing CRLF
+    pub fn to_string(&self) -> String {
+        let mut line = String::new();
+        if let Some(ref g) = self.group {
+            line.push_str(g);
+            line.push('.');
+        }
+        line.push_str(&self.name);
+        for &(ref n, ref v) in self.params.iter() {
+            line.push(';');
+            line.push_str(n);
+            line.push('=');
+            let values: Vec<String> = v.iter().map(|s| {
+                let s = encode_param(s);
+                match s.contains(|c| c == ';' || c == ':' || c == ',') {
+                    true => format!("\"{}\"", s),
+                    _    => s,
+                }
+            }).collect();
+            line.push_str(&values.join(","));
+        }
+        line.push(':');
+        // text values have their line breaks escaped already. anything else
+        // can't have them, and letting them through would start a new line.
+        line.push_str(&self.value.replace(|c| c == '\r' || c == '\n', ""));
+        fold(&line)
+    }
+}
+
+
+// RFC 6868 parameter value encoding, for the characters that can't appear
+// in a parameter value otherwise: line breaks, double quotes and ^ itself
+pub fn encode_param(s: &str) -> String {
+    s.replace("^", "^^").replace("\r\n", "\n").replace(|c| c == '\r' || c == '\n', "^n").replace("\"", "^'")
+}
+
+pub fn decode_param(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let decoded = match (c, chars.peek()) {
+            ('^', Some(&'n'))  => Some('\n'),
+            ('^', Some(&'\'')) => Some('"'),
+            ('^', Some(&'^'))  => Some('^'),
+            _                  => None,
+        };
+        match decoded {
+            Some(d) => {
+                chars.next();
+                out.push(d);
+            },
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+// split a line at LINE_LENGTH octets, without breaking up characters
+pub fn fold(line: &str) -> String {
+    let mut out = String::with_capacity(line.len() + 2);
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > LINE_LENGTH {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+// join continuation lines back on to the lines they continue, and split
+// into lines. blank lines are dropped.
+pub fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec!();
+    for line in text.split('\n') {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+pub fn escape_text(s: &str) -> String {
+    s.replace("\\", "\\\\").replace(";", "\\;").replace(",", "\\,")
+     .replace("\r\n", "\n").replace("\n", "\\n")
+}
+
+pub fn unescape_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => out.push('\n'),
+                Some(c)               => out.push(c),
+                None                  => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// split a value on a separator (, for lists or ; for structured values),
+// ignoring escaped separators. the parts are left escaped.
+pub fn split_escaped(s: &str, sep: char) -> Vec<String> {
+    let mut parts = vec!();
+    let mut part = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                part.push(c);
+                if let Some(n) = chars.next() {
+                    part.push(n);
+                }
+            },
+            c if c == sep => parts.push(::std::mem::replace(&mut part, String::new())),
+            c => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+
+// a BEGIN/END delimited component with its properties and subcomponents
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Component {
+    pub name:       String,
+    pub properties: Vec<ContentLine>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    pub fn new(name: &str) -> Component {
+        Component {
+            name: name.to_string(),
+            ..Component::default()
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&ContentLine> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn properties<'a>(&'a self, name: &'a str) -> Vec<&'a ContentLine> {
+        self.properties.iter().filter(|p| p.name == name).collect()
+    }
+
+    // the unescaped text of a property, if it's there
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.property(name).map(|p| unescape_text(&p.value))
+    }
+
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.properties.push(ContentLine::new(name, value));
+    }
+
+    pub fn add_text(&mut self, name: &str, value: &str) {
+        self.properties.push(ContentLine::new(name, &escape_text(value)));
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut out = ContentLine::new("BEGIN", &self.name).to_string();
+        for p in self.properties.iter() {
+            out.push_str(&p.to_string());
+        }
+        for c in self.components.iter() {
+            out.push_str(&c.to_string());
+        }
+        out.push_str(&ContentLine::new("END", &self.name).to_string());
+        out
+    }
+}
+
+// parse all the top-level components in a document
+pub fn parse_components(text: &str) -> Result<Vec<Component>,ParseError> {
+    let mut stack: Vec<Component> = vec!();
+    let mut out = vec!();
+    for line in unfold(text).iter() {
+        let cl = try!(ContentLine::parse(line));
+        match cl.name.as_ref() {
+            "BEGIN" => stack.push(Component::new(&cl.value.to_uppercase())),
+            "END" => {
+                let c = match stack.pop() {
+                    Some(c) => c,
+                    None    => return Err(ParseError::InvalidStructure(cl.value.to_uppercase())),
+                };
+                if c.name != cl.value.to_uppercase() {
+                    return Err(ParseError::InvalidStructure(c.name));
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(c),
+                    None         => out.push(c),
+                }
+            },
+            _ => match stack.last_mut() {
+                Some(c) => c.properties.push(cl),
+                None    => return Err(ParseError::InvalidStructure("Component".to_string())),
+            },
+        }
+    }
+    match stack.pop() {
+        Some(c) => Err(ParseError::InvalidStructure(c.name)),
+        None    => Ok(out),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_and_unfolds() {
+        let value = "x".repeat(100) + "é";
+        let line = ContentLine::new("DESCRIPTION", &value).to_string();
+        assert!(line.split("\r\n").all(|l| l.len() <= LINE_LENGTH));
+        let lines = unfold(&line);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(ContentLine::parse(&lines[0]).unwrap().value, value);
+    }
+
+    #[test]
+    fn unfold_tab_continuation() {
+        assert_eq!(unfold("SUMMARY:a\r\n\tb\r\n c\r\nX:y\n"), vec!("SUMMARY:abc".to_string(), "X:y".to_string()));
+    }
+
+    #[test]
+    fn parses_parameters() {
+        let cl = ContentLine::parse("item1.TEL;TYPE=work,voice;X-A=\"a;b:c\":+1 555").unwrap();
+        assert_eq!(cl.group, Some("item1".to_string()));
+        assert_eq!(cl.name, "TEL");
+        assert_eq!(cl.param_values("type"), vec!("work", "voice"));
+        assert_eq!(cl.param("X-A"), Some("a;b:c"));
+        assert_eq!(cl.value, "+1 555");
+    }
+
+    #[test]
+    fn parameter_values_encoded() {
+        let cl = ContentLine::new("ATTENDEE", "mailto:a@example.com").with_param("CN", "Eve \"E\"\r\nATTENDEE:mailto:x^y");
+        let line = cl.to_string();
+        assert_eq!(line, "ATTENDEE;CN=\"Eve ^'E^'^nATTENDEE:mailto:x^^y\":mailto:a@example.com\r\n");
+        let parsed = ContentLine::parse(&unfold(&line)[0]).unwrap();
+        assert_eq!(parsed.param("CN"), Some("Eve \"E\"\nATTENDEE:mailto:x^y"));
+    }
+
+    #[test]
+    fn line_breaks_stripped_from_values() {
+        let line = ContentLine::new("ATTACH", "blob\r\nATTENDEE:mailto:evil").to_string();
+        assert_eq!(line, "ATTACH:blobATTENDEE:mailto:evil\r\n");
+    }
+
+    #[test]
+    fn text_escaping_round_trips() {
+        let s = "a, b; c\\d\nnext";
+        assert_eq!(escape_text(s), "a\\, b\\; c\\\\d\\nnext");
+        assert_eq!(unescape_text(&escape_text(s)), s);
+        assert_eq!(split_escaped("a\\,b,c", ','), vec!("a\\,b".to_string(), "c".to_string()));
+    }
+}
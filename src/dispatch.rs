@@ -0,0 +1,235 @@
+use std::default::Default;
+
+use parse::Presence::*;
+use record::Record;
+use reference::ResolveReferences;
+use method::*;
+use method::RequestMethod::*;
+use method::ResponseMethod::*;
+
+use calendar::Calendar;
+use calendar_event::CalendarEvent;
+use contact::Contact;
+use contact_group::ContactGroup;
+use mailbox::Mailbox;
+use message::Message;
+use thread::Thread;
+
+use message_list::*;
+use calendar_event_list::*;
+use contact_list::*;
+use message_import::*;
+use message_copy::*;
+use message_report::*;
+use search_snippet::*;
+
+
+// handler for the get/getUpdates/set methods of a single record type
+pub trait RecordHandler<R: Record> {
+    fn get(&mut self, args: &GetRequestArgs<R>) -> Result<GetResponseArgs<R>,MethodError>;
+    fn get_updates(&mut self, args: &GetUpdatesRequestArgs<R>) -> Result<GetUpdatesResponseArgs<R>,MethodError>;
+    fn set(&mut self, args: &SetRequestArgs<R>) -> Result<SetResponseArgs<R>,MethodError>;
+}
+
+// handler for the message methods that don't fit the record pattern
+pub trait MessageActionHandler {
+    fn import(&mut self, args: &ImportMessagesRequestArgs) -> Result<ImportMessagesResponseArgs,MethodError>;
+    fn copy(&mut self, args: &CopyMessagesRequestArgs) -> Result<CopyMessagesResponseArgs,MethodError>;
+    fn report(&mut self, args: &ReportMessagesRequestArgs) -> Result<ReportMessagesResponseArgs,MethodError>;
+}
+
+pub trait MessageListHandler {
+    fn get_message_list(&mut self, args: &GetMessageListRequestArgs) -> Result<GetMessageListResponseArgs,MethodError>;
+    fn get_message_list_updates(&mut self, args: &GetMessageListUpdatesRequestArgs) -> Result<GetMessageListUpdatesResponseArgs,MethodError>;
+    fn get_search_snippets(&mut self, args: &GetSearchSnippetsRequestArgs) -> Result<GetSearchSnippetsResponseArgs,MethodError>;
+}
+
+pub trait CalendarEventListHandler {
+    fn get_calendar_event_list(&mut self, args: &GetCalendarEventListRequestArgs) -> Result<GetCalendarEventListResponseArgs,MethodError>;
+}
+
+pub trait ContactListHandler {
+    fn get_contact_list(&mut self, args: &GetContactListRequestArgs) -> Result<GetContactListResponseArgs,MethodError>;
+}
+
+
+// turns a RequestBatch into a ResponseBatch by calling the handlers. methods
+// without a handler get an unknownMethod error, as do methods the parser
+// didn't recognise.
+pub struct Dispatcher<'a> {
+    pub calendars:           Option<Box<dyn RecordHandler<Calendar> + 'a>>,
+    pub calendar_events:     Option<Box<dyn RecordHandler<CalendarEvent> + 'a>>,
+    pub contacts:            Option<Box<dyn RecordHandler<Contact> + 'a>>,
+    pub contact_groups:      Option<Box<dyn RecordHandler<ContactGroup> + 'a>>,
+    pub mailboxes:           Option<Box<dyn RecordHandler<Mailbox> + 'a>>,
+    pub messages:            Option<Box<dyn RecordHandler<Message> + 'a>>,
+    pub threads:             Option<Box<dyn RecordHandler<Thread> + 'a>>,
+    pub message_actions:     Option<Box<dyn MessageActionHandler + 'a>>,
+    pub message_list:        Option<Box<dyn MessageListHandler + 'a>>,
+    pub calendar_event_list: Option<Box<dyn CalendarEventListHandler + 'a>>,
+    pub contact_list:        Option<Box<dyn ContactListHandler + 'a>>,
+}
+
+impl<'a> Default for Dispatcher<'a> {
+    fn default() -> Dispatcher<'a> {
+        Dispatcher {
+            calendars:           None,
+            calendar_events:     None,
+            contacts:            None,
+            contact_groups:      None,
+            mailboxes:           None,
+            messages:            None,
+            threads:             None,
+            message_actions:     None,
+            message_list:        None,
+            calendar_event_list: None,
+            contact_list:        None,
+        }
+    }
+}
+
+// wrap a handler result up as a response with the request's client id
+fn respond<T,F>(method: &RequestMethod, f: F, r: Option<Result<T,MethodError>>) -> ResponseMethod
+    where F: Fn(T,String) -> ResponseMethod {
+    let client_id = method.client_id();
+    match r {
+        Some(Ok(args)) => f(args, client_id),
+        Some(Err(e))   => ResponseError(e, client_id),
+        None           => ResponseError(MethodError::UnknownMethod(Present(ErrorDescription(method.name().to_string()))), client_id),
+    }
+}
+
+// a getUpdates call with fetchRecords set is followed by an implicit get of
+// the changed records, for fetchRecordProperties
+fn fetch_records<R: Record>(a: &GetUpdatesRequestArgs<R>, r: &GetUpdatesResponseArgs<R>) -> Option<GetRequestArgs<R>> {
+    match a.fetch_records {
+        Present(true) => {
+            let mut get = GetRequestArgs::<R>::default();
+            get.ids = Present(r.changed.clone());
+            get.properties = a.fetch_record_properties.clone();
+            Some(get)
+        },
+        _ => None,
+    }
+}
+
+fn implicit_get(request: &RequestMethod, response: &ResponseMethod) -> Option<RequestMethod> {
+    match (request, response) {
+        (&GetCalendarUpdates(ref a, ref id), &CalendarUpdates(ref r, _))           => fetch_records(a, r).map(|g| GetCalendars(g, id.clone())),
+        (&GetCalendarEventUpdates(ref a, ref id), &CalendarEventUpdates(ref r, _)) => fetch_records(a, r).map(|g| GetCalendarEvents(g, id.clone())),
+        (&GetContactUpdates(ref a, ref id), &ContactUpdates(ref r, _))             => fetch_records(a, r).map(|g| GetContacts(g, id.clone())),
+        (&GetContactGroupUpdates(ref a, ref id), &ContactGroupUpdates(ref r, _))   => fetch_records(a, r).map(|g| GetContactGroups(g, id.clone())),
+        (&GetMailboxUpdates(ref a, ref id), &MailboxUpdates(ref r, _))             => fetch_records(a, r).map(|g| GetMailboxes(g, id.clone())),
+        (&GetMessageUpdates(ref a, ref id), &MessageUpdates(ref r, _))             => fetch_records(a, r).map(|g| GetMessages(g, id.clone())),
+        (&GetThreadUpdates(ref a, ref id), &ThreadUpdates(ref r, _))               => fetch_records(a, r).map(|g| GetThreads(g, id.clone())),
+        _ => None,
+    }
+}
+
+impl<'a> Dispatcher<'a> {
+    pub fn dispatch_method(&mut self, m: &RequestMethod) -> ResponseMethod {
+        match *m {
+            GetCalendars(ref a, _)            => respond(m, Calendars,            self.calendars.as_mut().map(|h| h.get(a))),
+            GetCalendarUpdates(ref a, _)      => respond(m, CalendarUpdates,      self.calendars.as_mut().map(|h| h.get_updates(a))),
+            SetCalendars(ref a, _)            => respond(m, CalendarsSet,         self.calendars.as_mut().map(|h| h.set(a))),
+
+            GetCalendarEvents(ref a, _)       => respond(m, CalendarEvents,       self.calendar_events.as_mut().map(|h| h.get(a))),
+            GetCalendarEventUpdates(ref a, _) => respond(m, CalendarEventUpdates, self.calendar_events.as_mut().map(|h| h.get_updates(a))),
+            SetCalendarEvents(ref a, _)       => respond(m, CalendarEventsSet,    self.calendar_events.as_mut().map(|h| h.set(a))),
+
+            GetContacts(ref a, _)             => respond(m, Contacts,             self.contacts.as_mut().map(|h| h.get(a))),
+            GetContactUpdates(ref a, _)       => respond(m, ContactUpdates,       self.contacts.as_mut().map(|h| h.get_updates(a))),
+            SetContacts(ref a, _)             => respond(m, ContactsSet,          self.contacts.as_mut().map(|h| h.set(a))),
+
+            GetContactGroups(ref a, _)        => respond(m, ContactGroups,        self.contact_groups.as_mut().map(|h| h.get(a))),
+            GetContactGroupUpdates(ref a, _)  => respond(m, ContactGroupUpdates,  self.contact_groups.as_mut().map(|h| h.get_updates(a))),
+            SetContactGroups(ref a, _)        => respond(m, ContactGroupsSet,     self.contact_groups.as_mut().map(|h| h.set(a))),
+
+            GetMailboxes(ref a, _)            => respond(m, Mailboxes,            self.mailboxes.as_mut().map(|h| h.get(a))),
+            GetMailboxUpdates(ref a, _)       => respond(m, MailboxUpdates,       self.mailboxes.as_mut().map(|h| h.get_updates(a))),
+            SetMailboxes(ref a, _)            => respond(m, MailboxesSet,         self.mailboxes.as_mut().map(|h| h.set(a))),
+
+            GetMessages(ref a, _)             => respond(m, Messages,             self.messages.as_mut().map(|h| h.get(a))),
+            GetMessageUpdates(ref a, _)       => respond(m, MessageUpdates,       self.messages.as_mut().map(|h| h.get_updates(a))),
+            SetMessages(ref a, _)             => respond(m, MessagesSet,          self.messages.as_mut().map(|h| h.set(a))),
+
+            GetThreads(ref a, _)              => respond(m, Threads,              self.threads.as_mut().map(|h| h.get(a))),
+            GetThreadUpdates(ref a, _)        => respond(m, ThreadUpdates,        self.threads.as_mut().map(|h| h.get_updates(a))),
+
+            ImportMessages(ref a, _)          => respond(m, MessagesImported,     self.message_actions.as_mut().map(|h| h.import(a))),
+            CopyMessages(ref a, _)            => respond(m, MessagesCopied,       self.message_actions.as_mut().map(|h| h.copy(a))),
+            ReportMessages(ref a, _)          => respond(m, MessagesReported,     self.message_actions.as_mut().map(|h| h.report(a))),
+
+            GetMessageList(ref a, _)          => respond(m, MessageList,          self.message_list.as_mut().map(|h| h.get_message_list(a))),
+            GetMessageListUpdates(ref a, _)   => respond(m, MessageListUpdates,   self.message_list.as_mut().map(|h| h.get_message_list_updates(a))),
+            GetSearchSnippets(ref a, _)       => respond(m, SearchSnippets,       self.message_list.as_mut().map(|h| h.get_search_snippets(a))),
+
+            GetCalendarEventList(ref a, _)    => respond(m, CalendarEventList,    self.calendar_event_list.as_mut().map(|h| h.get_calendar_event_list(a))),
+
+            GetContactList(ref a, _)          => respond(m, ContactList,          self.contact_list.as_mut().map(|h| h.get_contact_list(a))),
+
+            RequestError(ref e, ref client_id) => ResponseError(e.clone(), client_id.clone()),
+        }
+    }
+
+    // run every method in the batch in order. result references in each
+    // method are resolved against the responses produced so far.
+    pub fn dispatch(&mut self, batch: &RequestBatch) -> ResponseBatch {
+        let mut responses = ResponseBatch::default();
+        for method in batch.0.iter() {
+            let method = match method.resolve_references(&responses) {
+                Ok(m)  => m,
+                Err(e) => {
+                    responses.0.push(ResponseError(e, method.client_id()));
+                    continue;
+                },
+            };
+            let response = self.dispatch_method(&method);
+            let get = implicit_get(&method, &response);
+            responses.0.push(response);
+            if let Some(get) = get {
+                let response = self.dispatch_method(&get);
+                responses.0.push(response);
+            }
+        }
+        responses
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use parse::FromJson;
+    use store::MemoryStore;
+    use super::*;
+
+    #[test]
+    fn fetch_records_adds_a_get() {
+        let mut d = Dispatcher::default();
+        d.mailboxes = Some(Box::new(MemoryStore::<Mailbox>::default()));
+
+        let batch = Json::from_str(r#"[
+            ["setMailboxes", {"create": {"c1": {"name": "Inbox"}}}, "0"],
+            ["getMailboxUpdates", {"sinceState": "0", "fetchRecords": true, "fetchRecordProperties": ["name"]}, "1"],
+            ["getMailboxUpdates", {"sinceState": "0"}, "2"]
+        ]"#).unwrap();
+        let responses = d.dispatch(&RequestBatch::from_json(&batch).unwrap()).0;
+        assert_eq!(responses.iter().map(|r| (r.name(), r.client_id())).collect::<Vec<_>>(), vec!(
+            ("mailboxesSet", "0".to_string()),
+            ("mailboxUpdates", "1".to_string()),
+            ("mailboxes", "1".to_string()),
+            ("mailboxUpdates", "2".to_string()),
+        ));
+
+        match responses[2] {
+            Mailboxes(ref r, _) => {
+                let list = r.list.as_ref().unwrap();
+                assert_eq!(list.len(), 1);
+                assert_eq!(*list[0].name(), Present("Inbox".to_string()));
+                assert_eq!(*list[0].sort_order(), Absent);
+            },
+            ref r => panic!("{:?}", r),
+        }
+    }
+}
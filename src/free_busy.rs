@@ -0,0 +1,177 @@
+use std::collections::BTreeSet;
+use chrono::Duration;
+
+use types::Date;
+use calendar::Calendar;
+use calendar_event::CalendarEvent;
+use tz::{TimeZoneName, from_local, pin_floating};
+
+// free/busy time across a set of calendars, worked out from the occurrences
+// of their events
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BusyPeriod {
+    pub start: Date,
+    pub end:   Date,
+}
+
+// the time an occurrence takes up. all-day events take up the whole of each
+// of their days, and other events that take no time aren't busy at all.
+fn busy_period(e: &CalendarEvent, tz: &Option<TimeZoneName>) -> Option<BusyPeriod> {
+    let p = match e.is_all_day {
+        true => {
+            let start = e.start.naive_utc().date();
+            let end = match e.end.naive_utc().date() {
+                end if end > start => end,
+                _                  => start + Duration::days(1),
+            };
+            BusyPeriod {
+                start: from_local(tz, &start.and_hms(0, 0, 0)),
+                end:   from_local(tz, &end.and_hms(0, 0, 0)),
+            }
+        },
+        _ => BusyPeriod {
+            start: pin_floating(&e.start, &e.start_time_zone, tz),
+            end:   pin_floating(&e.end, &e.end_time_zone.clone().or(e.start_time_zone.clone()), tz),
+        },
+    };
+    match *p.end > *p.start {
+        true => Some(p),
+        _    => None,
+    }
+}
+
+// the busy periods between after and before, merged and in order. only
+// calendars that allow free/busy to be read are looked at, events shown as
+// free are skipped, and floating and all-day events are taken to be in the
+// zone tz.
+pub fn busy_periods(calendars: &[Calendar], events: &[CalendarEvent], after: &Date, before: &Date, tz: &Option<TimeZoneName>) -> Vec<BusyPeriod> {
+    let readable: BTreeSet<&str> = calendars.iter()
+        .filter(|c| c.may_read_free_busy)
+        .map(|c| &c.id[..])
+        .collect();
+
+    // floating times can be most of a day either side of UTC, so look a
+    // little wider than asked and clip afterwards
+    let wide_after = Date(**after - Duration::days(2));
+    let wide_before = Date(**before + Duration::days(2));
+
+    let mut periods = vec!();
+    for e in events.iter().filter(|e| readable.contains(&e.calendar_id[..])) {
+        for o in e.occurrences_between(&wide_after, &wide_before) {
+            if o.event.show_as_free {
+                continue;
+            }
+            if let Some(mut p) = busy_period(&o.event, tz) {
+                if *p.start < **after {
+                    p.start = after.clone();
+                }
+                if *p.end > **before {
+                    p.end = before.clone();
+                }
+                if *p.end > *p.start {
+                    periods.push(p);
+                }
+            }
+        }
+    }
+
+    periods.sort_by(|a, b| a.start.cmp(&b.start));
+    let mut merged: Vec<BusyPeriod> = vec!();
+    for p in periods.into_iter() {
+        match merged.last_mut() {
+            Some(ref mut last) if *p.start <= *last.end => {
+                if *p.end > *last.end {
+                    last.end = p.end;
+                }
+                continue;
+            },
+            _ => (),
+        }
+        merged.push(p);
+    }
+    merged
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use calendar_event::{ExceptionMap, LocalDate, PartialCalendarEvent, Recurrence, Frequency};
+    use tz::TimeZoneName;
+    use super::*;
+
+    fn at(d: u32, h: u32) -> Date {
+        from_local(&None, &NaiveDate::from_ymd(2017, 1, d).and_hms(h, 0, 0))
+    }
+
+    fn event(calendar_id: &str, start: Date, end: Date) -> CalendarEvent {
+        CalendarEvent::default()
+            .with_calendar_id(calendar_id.to_string())
+            .with_start(start)
+            .with_end(end)
+            .with_start_time_zone(TimeZoneName::new("Etc/UTC"))
+    }
+
+    fn calendars() -> Vec<Calendar> {
+        vec!(
+            Calendar::default().with_id("c1".to_string()).with_may_read_free_busy(true),
+            Calendar::default().with_id("c2".to_string()).with_may_read_free_busy(false),
+        )
+    }
+
+    fn periods(events: &[CalendarEvent], tz: &Option<TimeZoneName>) -> Vec<(Date,Date)> {
+        busy_periods(&calendars(), events, &at(1, 0), &at(8, 0), tz).into_iter().map(|p| (p.start, p.end)).collect()
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_periods_merged() {
+        let events = vec!(
+            event("c1", at(2, 9), at(2, 11)),
+            event("c1", at(2, 10), at(2, 12)),
+            event("c1", at(2, 12), at(2, 13)),
+            event("c1", at(3, 9), at(3, 10)),
+            // clipped to the window
+            event("c1", at(7, 20), at(9, 0)),
+            // takes no time
+            event("c1", at(4, 9), at(4, 9)),
+        );
+        assert_eq!(periods(&events, &None), vec!((at(2, 9), at(2, 13)), (at(3, 9), at(3, 10)), (at(7, 20), at(8, 0))));
+    }
+
+    #[test]
+    fn free_and_unreadable_events_skipped() {
+        let events = vec!(
+            event("c1", at(2, 9), at(2, 10)).with_show_as_free(true),
+            event("c2", at(3, 9), at(3, 10)),
+            event("c3", at(4, 9), at(4, 10)),
+            event("c1", at(5, 9), at(5, 10)),
+        );
+        assert_eq!(periods(&events, &None), vec!((at(5, 9), at(5, 10))));
+    }
+
+    #[test]
+    fn all_day_events_take_whole_days_in_zone() {
+        let tz = TimeZoneName::new("America/New_York");
+        let events = vec!(
+            event("c1", at(3, 0), at(3, 0)).with_is_all_day(true).with_start_time_zone(None),
+            event("c1", at(5, 0), at(7, 0)).with_is_all_day(true).with_start_time_zone(None),
+        );
+        assert_eq!(periods(&events, &tz), vec!((at(3, 5), at(4, 5)), (at(5, 5), at(7, 5))));
+    }
+
+    #[test]
+    fn exception_moves_occurrence() {
+        let rule = Recurrence::builder()
+            .with_frequency(Frequency::Daily)
+            .with_count(Some(3))
+            .with_until(LocalDate::default())
+            .build().unwrap();
+        let mut exceptions = ExceptionMap(Default::default());
+        exceptions.0.insert(LocalDate(at(3, 9).naive_utc()), Some(PartialCalendarEvent::default().with_start(at(3, 14)).with_end(at(3, 15))));
+        exceptions.0.insert(LocalDate(at(4, 9).naive_utc()), None);
+        let e = event("c1", at(2, 9), at(2, 10)).with_recurrence(Some(rule)).with_exceptions(Some(exceptions));
+        assert_eq!(periods(&[e], &None), vec!((at(2, 9), at(2, 10)), (at(3, 14), at(3, 15))));
+    }
+}
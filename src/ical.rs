@@ -0,0 +1,854 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{UTC, NaiveDate, NaiveDateTime, NaiveTime, Duration};
+
+use parse::ParseError;
+use parse::Presence::*;
+use record;
+use record::Record;
+use types::{File, Date};
+use calendar::Calendar;
+use calendar_event::*;
+use free_busy::BusyPeriod;
+use tz::{TimeZone, TimeZoneName, LocalTimeType, RuleDay, to_local, from_local};
+use content_line::*;
+
+// iCalendar (RFC 5545) import and export for calendars and their events
+
+
+const PRODID: &'static str = "-//jmap-rs//EN";
+
+// longest duration we'll accept, in seconds. well inside what Duration and
+// NaiveDateTime can represent, so arithmetic with it can't overflow them.
+const MAX_DURATION: i64 = 10000 * 366 * 86400;
+
+
+fn is_utc(tz: &Option<TimeZoneName>) -> bool {
+    tz.as_ref().map(|tz| tz.is_utc()).unwrap_or(false)
+}
+
+fn invalid(what: &str) -> ParseError {
+    ParseError::InvalidStructure(what.to_string())
+}
+
+// a DATE or DATE-TIME value, and whether it was a date and whether it was UTC
+fn parse_date_time(v: &str) -> Result<(NaiveDateTime,bool,bool),ParseError> {
+    let v = v.trim();
+    match v.len() {
+        8 => match NaiveDate::parse_from_str(v, "%Y%m%d") {
+            Ok(d) => Ok((d.and_hms(0, 0, 0), true, false)),
+            _     => Err(invalid("DATE")),
+        },
+        _ => {
+            let (v, utc) = match v.ends_with('Z') || v.ends_with('z') {
+                true => (&v[..v.len()-1], true),
+                _    => (v, false),
+            };
+            match NaiveDateTime::parse_from_str(v, "%Y%m%dT%H%M%S") {
+                Ok(dt) => Ok((dt, false, utc)),
+                _      => Err(invalid("DATE-TIME")),
+            }
+        },
+    }
+}
+
+// the Olson zone for a TZID, allowing for prefixes like
+// /mozilla.org/20050126_1/America/New_York
+fn resolve_tzid(tzid: &str) -> Option<TimeZoneName> {
+    let parts: Vec<&str> = tzid.trim().trim_start_matches('/').split('/').collect();
+    (0..parts.len()).filter_map(|i| TimeZoneName::new(&parts[i..].join("/"))).next()
+}
+
+// the zone a DATE-TIME value is in: UTC, its TZID, or floating. TZIDs that
+// aren't Olson names are treated as floating.
+fn value_zone(cl: &ContentLine, utc: bool) -> Option<TimeZoneName> {
+    match utc {
+        true => Some(TimeZoneName::utc()),
+        _    => cl.param("TZID").and_then(resolve_tzid),
+    }
+}
+
+// a DATE or DATE-TIME value as a local time in the zone tz. floating values
+// are taken to be in tz already, and dates get the time of day from time.
+fn parse_local(cl: &ContentLine, v: &str, tz: &Option<TimeZoneName>, time: &NaiveTime) -> Result<NaiveDateTime,ParseError> {
+    let (dt, is_date, utc) = try!(parse_date_time(v));
+    if is_date {
+        return Ok(dt.date().and_time(time.clone()));
+    }
+    match value_zone(cl, utc) {
+        Some(ref vtz) if Some(vtz) != tz.as_ref() => Ok(to_local(tz, &from_local(&Some(vtz.clone()), &dt))),
+        _ => Ok(dt),
+    }
+}
+
+// an RFC 5545 duration, eg -P1DT2H or P2W
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (neg, s) = match s.chars().next() {
+        Some('-') => (true, &s[1..]),
+        Some('+') => (false, &s[1..]),
+        _         => (false, s),
+    };
+    if !s.starts_with('P') {
+        return None;
+    }
+
+    let mut secs = 0i64;
+    let mut n = String::new();
+    let mut in_time = false;
+    for c in s[1..].chars() {
+        match c {
+            '0'..='9' => n.push(c),
+            'T' => in_time = true,
+            _ => {
+                let v: i64 = match n.parse() {
+                    Ok(v) => v,
+                    _     => return None,
+                };
+                n.clear();
+                let unit = match (c, in_time) {
+                    ('W', false) => 7 * 86400,
+                    ('D', false) => 86400,
+                    ('H', true)  => 3600,
+                    ('M', true)  => 60,
+                    ('S', true)  => 1,
+                    _            => return None,
+                };
+                secs = match v.checked_mul(unit).and_then(|v| v.checked_add(secs)) {
+                    Some(s) if s <= MAX_DURATION => s,
+                    _                            => return None,
+                };
+            },
+        }
+    }
+    if !n.is_empty() {
+        return None;
+    }
+    Some(Duration::seconds(match neg { true => -secs, _ => secs }))
+}
+
+fn format_duration(minutes: i64) -> String {
+    match minutes < 0 {
+        true => format!("-PT{}M", -minutes),
+        _    => format!("PT{}M", minutes),
+    }
+}
+
+
+const WEEKDAYS: [&'static str; 7] = ["SU", "MO", "TU", "WE", "TH", "FR", "SA"];
+
+const FREQUENCIES: [(Frequency, &'static str); 7] = [
+    (Frequency::Yearly, "YEARLY"), (Frequency::Monthly, "MONTHLY"),
+    (Frequency::Weekly, "WEEKLY"), (Frequency::Daily, "DAILY"),
+    (Frequency::Hourly, "HOURLY"), (Frequency::Minutely, "MINUTELY"),
+    (Frequency::Secondly, "SECONDLY"),
+];
+
+// a BYDAY entry like 1MO or -1SU, as day + 7 * n
+fn parse_by_day(s: &str) -> Result<i32,ParseError> {
+    let s = s.trim().to_uppercase();
+    if s.len() < 2 {
+        return Err(invalid("RRULE"));
+    }
+    let (n, day) = s.split_at(s.len() - 2);
+    let day = match WEEKDAYS.iter().position(|&d| d == day) {
+        Some(d) => d as i32,
+        None    => return Err(invalid("RRULE")),
+    };
+    let n = match n.trim_start_matches('+') {
+        "" => 0,
+        n  => match n.parse::<i32>() {
+            Ok(n) => n,
+            _     => return Err(invalid("RRULE")),
+        },
+    };
+    Ok(day + 7 * n)
+}
+
+fn format_by_day(v: i32) -> String {
+    let day = ((v % 7) + 7) % 7;
+    match (v - day) / 7 {
+        0 => WEEKDAYS[day as usize].to_string(),
+        n => format!("{}{}", n, WEEKDAYS[day as usize]),
+    }
+}
+
+fn parse_numbers(v: &str) -> Result<Vec<i32>,ParseError> {
+    let mut out = vec!();
+    for n in v.split(',') {
+        match n.trim().trim_start_matches('+').parse::<i32>() {
+            Ok(n) => out.push(n),
+            _     => return Err(invalid("RRULE")),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_rrule(cl: &ContentLine, tz: &Option<TimeZoneName>, start: &NaiveDateTime) -> Result<Recurrence,ParseError> {
+    let mut r = Recurrence::default();
+    let mut has_freq = false;
+    for part in cl.value.split(';').filter(|p| !p.is_empty()) {
+        let (k, v) = match part.find('=') {
+            Some(i) => (part[..i].to_uppercase(), &part[i+1..]),
+            None    => return Err(invalid("RRULE")),
+        };
+        match k.as_ref() {
+            "FREQ" => {
+                r.frequency = match FREQUENCIES.iter().find(|&&(_, n)| n.eq_ignore_ascii_case(v)) {
+                    Some(&(f, _)) => f,
+                    None          => return Err(invalid("RRULE")),
+                };
+                has_freq = true;
+            },
+            "INTERVAL" => r.interval = Some(try!(v.parse::<i32>().map_err(|_| invalid("RRULE")))),
+            "COUNT"    => r.count = Some(try!(v.parse::<u64>().map_err(|_| invalid("RRULE")))),
+            "UNTIL"    => r.until = LocalDate(try!(parse_local(cl, v, tz, &start.time()))),
+            "WKST"     => r.first_day_of_week = Some(try!(parse_by_day(v))),
+            "BYDAY"    => {
+                let mut days = vec!();
+                for d in v.split(',') {
+                    days.push(try!(parse_by_day(d)));
+                }
+                r.by_day = Some(days);
+            },
+            "BYMONTHDAY" => r.by_date = Some(try!(parse_numbers(v))),
+            "BYMONTH"    => r.by_month = Some(try!(parse_numbers(v))),
+            "BYYEARDAY"  => r.by_year_day = Some(try!(parse_numbers(v))),
+            "BYWEEKNO"   => r.by_week_no = Some(try!(parse_numbers(v))),
+            "BYHOUR"     => r.by_hour = Some(try!(parse_numbers(v))),
+            "BYMINUTE"   => r.by_minute = Some(try!(parse_numbers(v))),
+            "BYSECOND"   => r.by_second = Some(try!(parse_numbers(v))),
+            "BYSETPOS"   => r.by_set_position = Some(try!(parse_numbers(v))),
+            _ => (),
+        }
+    }
+    if !has_freq {
+        return Err(ParseError::MissingField("FREQ".to_string()));
+    }
+    try!(r.validate().map_err(|_| invalid("RRULE")));
+    Ok(r)
+}
+
+fn parse_participant(cl: &ContentLine) -> Participant {
+    let mut p = Participant::default();
+    p.name = cl.param("CN").unwrap_or("").to_string();
+    p.email = match cl.value.get(..7) {
+        Some(m) if m.eq_ignore_ascii_case("mailto:") => cl.value[7..].to_string(),
+        _ => cl.value.clone(),
+    };
+    p.rsvp = match cl.param("PARTSTAT").map(|s| s.to_uppercase()) {
+        Some(ref s) if s == "ACCEPTED"  => Rsvp::Yes,
+        Some(ref s) if s == "TENTATIVE" => Rsvp::Maybe,
+        Some(ref s) if s == "DECLINED"  => Rsvp::No,
+        _                               => Rsvp::None,
+    };
+    p
+}
+
+fn parse_alarm(c: &Component, start: &Date, end: &Date) -> Option<Alert> {
+    let trigger = match c.property("TRIGGER") {
+        Some(t) => t,
+        None    => return None,
+    };
+
+    // work out when it fires relative to the start
+    let before = match trigger.param("VALUE").map(|v| v.to_uppercase()) {
+        Some(ref v) if v == "DATE-TIME" => match parse_date_time(&trigger.value) {
+            Ok((dt, _, _)) => **start - *from_local(&Some(TimeZoneName::utc()), &dt),
+            _              => return None,
+        },
+        _ => {
+            let d = match parse_duration(&trigger.value) {
+                Some(d) => d,
+                None    => return None,
+            };
+            match trigger.param("RELATED").map(|v| v.to_uppercase()) {
+                Some(ref r) if r == "END" => (**start - **end) - d,
+                _                         => -d,
+            }
+        },
+    };
+
+    let mut a = Alert::default();
+    a.minutes_before = before.num_minutes() as i32;
+    a.typ = match c.property("ACTION").map(|a| a.value.to_uppercase()) {
+        Some(ref a) if a == "EMAIL" => AlertType::Email,
+        _                           => AlertType::Alert,
+    };
+    Some(a)
+}
+
+fn parse_attachment(cl: &ContentLine) -> Option<File> {
+    // inline data would need to go into a blob store; only links are kept
+    let binary = cl.param("VALUE").map(|v| v.eq_ignore_ascii_case("BINARY")).unwrap_or(false)
+              || cl.param("ENCODING").map(|v| v.eq_ignore_ascii_case("BASE64")).unwrap_or(false);
+    if binary {
+        return None;
+    }
+
+    let mut f = File::default();
+    f.blob_id = cl.value.clone();
+    f.typ = cl.param("FMTTYPE").map(|v| v.to_string());
+    f.name = cl.param("FILENAME").or(cl.param("X-FILENAME")).or(cl.param("X-APPLE-FILENAME")).map(|v| v.to_string());
+    f.size = cl.param("SIZE").and_then(|v| v.parse().ok());
+    Some(f)
+}
+
+fn parse_event(c: &Component, calendar_id: &str) -> Result<CalendarEvent,ParseError> {
+    let mut e = CalendarEvent::default();
+    e.id = c.text("UID").unwrap_or_else(record::new_id);
+    e.calendar_id = calendar_id.to_string();
+    e.summary = c.text("SUMMARY").unwrap_or("".to_string());
+    e.description = c.text("DESCRIPTION").unwrap_or("".to_string());
+    e.location = c.text("LOCATION").unwrap_or("".to_string());
+    e.show_as_free = c.property("TRANSP").map(|t| t.value.eq_ignore_ascii_case("TRANSPARENT")).unwrap_or(false);
+
+    let dtstart = match c.property("DTSTART") {
+        Some(p) => p,
+        None    => return Err(ParseError::MissingField("DTSTART".to_string())),
+    };
+    let (start, is_date, utc) = try!(parse_date_time(&dtstart.value));
+    e.is_all_day = is_date;
+    e.start_time_zone = match is_date {
+        true => None,
+        _    => value_zone(dtstart, utc),
+    };
+    e.start = from_local(&e.start_time_zone, &start);
+
+    e.end = match (c.property("DTEND"), c.property("DURATION")) {
+        (Some(dtend), _) => {
+            let (end, _, utc) = try!(parse_date_time(&dtend.value));
+            let tz = match is_date {
+                true => None,
+                _    => value_zone(dtend, utc),
+            };
+            if tz != e.start_time_zone {
+                e.end_time_zone = tz.clone();
+            }
+            from_local(&tz, &end)
+        },
+        (None, Some(d)) => match parse_duration(&d.value) {
+            Some(d) => match e.start.checked_add(d) {
+                Some(end) => Date(end),
+                None      => return Err(invalid("DURATION")),
+            },
+            None    => return Err(invalid("DURATION")),
+        },
+        (None, None) if is_date => Date(*e.start + Duration::days(1)),
+        (None, None)            => e.start.clone(),
+    };
+
+    if let Some(rrule) = c.property("RRULE") {
+        e.recurrence = Some(try!(parse_rrule(rrule, &e.start_time_zone, &start)));
+    }
+
+    let mut inclusions = vec!();
+    for rdate in c.properties("RDATE") {
+        for v in rdate.value.split(',') {
+            // periods are start/end or start/duration; the start is enough
+            let v = v.split('/').next().unwrap_or(v);
+            inclusions.push(LocalDate(try!(parse_local(rdate, v, &e.start_time_zone, &start.time()))));
+        }
+    }
+    if !inclusions.is_empty() {
+        e.inclusions = Some(inclusions);
+    }
+
+    let mut exceptions = BTreeMap::new();
+    for exdate in c.properties("EXDATE") {
+        for v in exdate.value.split(',') {
+            exceptions.insert(LocalDate(try!(parse_local(exdate, v, &e.start_time_zone, &start.time()))), None);
+        }
+    }
+    if !exceptions.is_empty() {
+        e.exceptions = Some(ExceptionMap(exceptions));
+    }
+
+    let alerts: Vec<Alert> = c.components.iter()
+        .filter(|a| a.name == "VALARM")
+        .filter_map(|a| parse_alarm(a, &e.start, &e.end))
+        .collect();
+    if !alerts.is_empty() {
+        e.alerts = Some(alerts);
+    }
+
+    e.organizer = c.property("ORGANIZER").map(parse_participant);
+    let attendees: Vec<Participant> = c.properties("ATTENDEE").into_iter().map(parse_participant).collect();
+    if !attendees.is_empty() {
+        e.attendees = Some(attendees);
+    }
+
+    let attachments: Vec<File> = c.properties("ATTACH").into_iter().filter_map(parse_attachment).collect();
+    if !attachments.is_empty() {
+        e.attachments = Some(attachments);
+    }
+
+    Ok(e)
+}
+
+// the changes an override VEVENT makes to the occurrence it replaces
+fn override_partial(master: &CalendarEvent, rid: &LocalDate, o: &CalendarEvent) -> PartialCalendarEvent {
+    let base = match master.occurrence(rid) {
+        Some(occurrence) => occurrence.event,
+        None             => master.clone(),
+    };
+    // overrides can't move between calendars or change the recurrence
+    let (mut p, _) = base.diff(o);
+    p.id = Absent;
+    p.calendar_id = Absent;
+    p.recurrence = Absent;
+    p.inclusions = Absent;
+    p.exceptions = Absent;
+    p
+}
+
+// read a calendar and its events from an iCalendar document. the calendar
+// gets the id calendar_id and the events are put in it. overrides
+// (VEVENTs with a RECURRENCE-ID) become exceptions on their master event.
+pub fn parse_ical(text: &str, calendar_id: &str) -> Result<(Calendar,Vec<CalendarEvent>),ParseError> {
+    let components = try!(parse_components(text));
+    let vcal = match components.iter().find(|c| c.name == "VCALENDAR") {
+        Some(c) => c,
+        None    => return Err(invalid("VCALENDAR")),
+    };
+
+    let mut calendar = Calendar::default();
+    calendar.id = calendar_id.to_string();
+    calendar.name = vcal.text("X-WR-CALNAME").or(vcal.text("NAME")).unwrap_or("".to_string());
+    calendar.color = vcal.text("X-APPLE-CALENDAR-COLOR").or(vcal.text("COLOR")).unwrap_or("".to_string());
+    calendar.is_visible = true;
+    calendar.may_read_free_busy = true;
+    calendar.may_read_items = true;
+    calendar.may_add_items = true;
+    calendar.may_modify_items = true;
+    calendar.may_remove_items = true;
+    calendar.may_rename = true;
+    calendar.may_delete = true;
+
+    let mut events: Vec<CalendarEvent> = vec!();
+    let mut overrides = vec!();
+    for c in vcal.components.iter().filter(|c| c.name == "VEVENT") {
+        let e = try!(parse_event(c, calendar_id));
+        match c.property("RECURRENCE-ID") {
+            Some(rid) => overrides.push((rid, e)),
+            None      => events.push(e),
+        }
+    }
+
+    for (rid, o) in overrides.into_iter() {
+        match events.iter_mut().find(|e| e.id == o.id) {
+            Some(master) => {
+                let local = try!(parse_local(rid, &rid.value, &master.start_time_zone, &to_local(&master.start_time_zone, &master.start).time()));
+                let rid = LocalDate(local);
+                let p = override_partial(master, &rid, &o);
+                let mut exceptions = master.exceptions.take().map(|x| x.0).unwrap_or(BTreeMap::new());
+                exceptions.insert(rid, Some(p));
+                master.exceptions = Some(ExceptionMap(exceptions));
+            },
+            // an override without its master is just a single event
+            None => events.push(o),
+        }
+    }
+
+    Ok((calendar, events))
+}
+
+
+// a DATE or DATE-TIME property for a local time in the zone tz
+fn date_line(name: &str, local: &NaiveDateTime, tz: &Option<TimeZoneName>, all_day: bool) -> ContentLine {
+    match (all_day, tz) {
+        (true, _) => ContentLine::new(name, &local.format("%Y%m%d").to_string()).with_param("VALUE", "DATE"),
+        (_, &Some(_)) if is_utc(tz) => ContentLine::new(name, &local.format("%Y%m%dT%H%M%SZ").to_string()),
+        (_, &Some(ref tz)) => ContentLine::new(name, &local.format("%Y%m%dT%H%M%S").to_string()).with_param("TZID", tz),
+        (_, &None) => ContentLine::new(name, &local.format("%Y%m%dT%H%M%S").to_string()),
+    }
+}
+
+fn format_numbers(v: &[i32]) -> String {
+    v.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn rrule_line(r: &Recurrence, tz: &Option<TimeZoneName>, all_day: bool) -> ContentLine {
+    let mut parts = vec!();
+    let freq = FREQUENCIES.iter().find(|&&(f, _)| f == r.frequency).map(|&(_, n)| n).unwrap_or("DAILY");
+    parts.push(format!("FREQ={}", freq));
+    if let Some(i) = r.interval {
+        parts.push(format!("INTERVAL={}", i));
+    }
+    if let Some(c) = r.count {
+        parts.push(format!("COUNT={}", c));
+    }
+    if r.until != LocalDate::default() {
+        // UNTIL has to be in UTC when the start has a zone
+        let until = match (all_day, tz) {
+            (true, _)      => r.until.format("%Y%m%d").to_string(),
+            (_, &Some(_))  => from_local(tz, &r.until).format("%Y%m%dT%H%M%SZ").to_string(),
+            (_, &None)     => r.until.format("%Y%m%dT%H%M%S").to_string(),
+        };
+        parts.push(format!("UNTIL={}", until));
+    }
+    if let Some(w) = r.first_day_of_week {
+        parts.push(format!("WKST={}", WEEKDAYS[(((w % 7) + 7) % 7) as usize]));
+    }
+    if let Some(ref d) = r.by_day {
+        parts.push(format!("BYDAY={}", d.iter().map(|&v| format_by_day(v)).collect::<Vec<_>>().join(",")));
+    }
+    let numbers = [
+        ("BYMONTHDAY", &r.by_date), ("BYMONTH", &r.by_month), ("BYYEARDAY", &r.by_year_day),
+        ("BYWEEKNO", &r.by_week_no), ("BYHOUR", &r.by_hour), ("BYMINUTE", &r.by_minute),
+        ("BYSECOND", &r.by_second), ("BYSETPOS", &r.by_set_position),
+    ];
+    for &(name, v) in numbers.iter() {
+        if let Some(ref v) = *v {
+            parts.push(format!("{}={}", name, format_numbers(v)));
+        }
+    }
+    ContentLine::new("RRULE", &parts.join(";"))
+}
+
+fn participant_line(name: &str, p: &Participant) -> ContentLine {
+    let mut cl = ContentLine::new(name, &format!("mailto:{}", p.email));
+    if !p.name.is_empty() {
+        cl = cl.with_param("CN", &p.name);
+    }
+    if name == "ATTENDEE" {
+        cl = cl.with_param("PARTSTAT", match p.rsvp {
+            Rsvp::Yes   => "ACCEPTED",
+            Rsvp::Maybe => "TENTATIVE",
+            Rsvp::No    => "DECLINED",
+            Rsvp::None  => "NEEDS-ACTION",
+        });
+    }
+    cl
+}
+
+fn alarm_component(a: &Alert, e: &CalendarEvent) -> Component {
+    let mut c = Component::new("VALARM");
+    c.add("TRIGGER", &format_duration(-a.minutes_before as i64));
+    match a.typ {
+        AlertType::Email => {
+            c.add("ACTION", "EMAIL");
+            c.add_text("SUMMARY", &e.summary);
+            c.add_text("DESCRIPTION", &e.summary);
+            if let Some(ref o) = e.organizer {
+                c.add("ATTENDEE", &format!("mailto:{}", o.email));
+            }
+        },
+        AlertType::Alert => {
+            c.add("ACTION", "DISPLAY");
+            c.add_text("DESCRIPTION", &e.summary);
+        },
+    }
+    c
+}
+
+// the VEVENT for e. for overrides, recurrence_id is set and e is the
+// overridden occurrence.
+fn event_component(e: &CalendarEvent, recurrence_id: Option<(&LocalDate, &Option<TimeZoneName>)>, stamp: &str) -> Component {
+    let mut c = Component::new("VEVENT");
+    c.add_text("UID", &e.id);
+    c.add("DTSTAMP", stamp);
+    if let Some((rid, tz)) = recurrence_id {
+        c.properties.push(date_line("RECURRENCE-ID", rid, tz, e.is_all_day));
+    }
+
+    let start_tz = e.start_time_zone.clone();
+    let end_tz = e.end_time_zone.clone().or(start_tz.clone());
+    c.properties.push(date_line("DTSTART", &to_local(&start_tz, &e.start), &start_tz, e.is_all_day));
+    c.properties.push(date_line("DTEND", &to_local(&end_tz, &e.end), &end_tz, e.is_all_day));
+
+    c.add_text("SUMMARY", &e.summary);
+    if !e.description.is_empty() {
+        c.add_text("DESCRIPTION", &e.description);
+    }
+    if !e.location.is_empty() {
+        c.add_text("LOCATION", &e.location);
+    }
+    c.add("TRANSP", match e.show_as_free {
+        true => "TRANSPARENT",
+        _    => "OPAQUE",
+    });
+
+    if let Some(ref r) = e.recurrence {
+        c.properties.push(rrule_line(r, &start_tz, e.is_all_day));
+    }
+    if let Some(ref v) = e.inclusions {
+        for d in v.iter() {
+            c.properties.push(date_line("RDATE", d, &start_tz, e.is_all_day));
+        }
+    }
+    if let Some(ref x) = e.exceptions {
+        for (d, _) in x.iter().filter(|&(_, p)| p.is_none()) {
+            c.properties.push(date_line("EXDATE", d, &start_tz, e.is_all_day));
+        }
+    }
+
+    if let Some(ref o) = e.organizer {
+        c.properties.push(participant_line("ORGANIZER", o));
+    }
+    if let Some(ref v) = e.attendees {
+        for a in v.iter() {
+            c.properties.push(participant_line("ATTENDEE", a));
+        }
+    }
+
+    if let Some(ref v) = e.attachments {
+        for f in v.iter() {
+            let mut cl = ContentLine::new("ATTACH", &f.blob_id);
+            if let Some(ref t) = f.typ {
+                cl = cl.with_param("FMTTYPE", t);
+            }
+            if let Some(ref n) = f.name {
+                cl = cl.with_param("FILENAME", n);
+            }
+            if let Some(s) = f.size {
+                cl = cl.with_param("SIZE", &s.to_string());
+            }
+            c.properties.push(cl);
+        }
+    }
+
+    if let Some(ref v) = e.alerts {
+        for a in v.iter() {
+            c.components.push(alarm_component(a, e));
+        }
+    }
+
+    c
+}
+
+fn format_offset(secs: i64) -> String {
+    let sign = match secs < 0 { true => '-', _ => '+' };
+    let secs = secs.abs();
+    match secs % 60 {
+        0 => format!("{}{:02}{:02}", sign, secs / 3600, secs / 60 % 60),
+        s => format!("{}{:02}{:02}{:02}", sign, secs / 3600, secs / 60 % 60, s),
+    }
+}
+
+fn observance(name: &str, from: &LocalTimeType, to: &LocalTimeType, dtstart: &NaiveDateTime) -> Component {
+    let mut c = Component::new(name);
+    c.add("DTSTART", &dtstart.format("%Y%m%dT%H%M%S").to_string());
+    c.add("TZOFFSETFROM", &format_offset(from.offset));
+    c.add("TZOFFSETTO", &format_offset(to.offset));
+    if !to.abbrev.is_empty() {
+        c.add_text("TZNAME", &to.abbrev);
+    }
+    c
+}
+
+// a yearly observance for a transition in the zone's current rule. only
+// month/week/day rules within the day can be written as an RRULE.
+fn rule_observance(name: &str, from: &LocalTimeType, to: &LocalTimeType, day: &RuleDay, time: i64) -> Option<Component> {
+    let (month, week, weekday) = match *day {
+        RuleDay::MonthWeekDay(m, w, d) if time >= 0 && time < 86400 => (m, w as i32, d as i32),
+        _ => return None,
+    };
+    let mut c = observance(name, from, to, &(day.date(1970).and_hms(0, 0, 0) + Duration::seconds(time)));
+    let week = match week { 5 => -1, w => w };
+    c.add("RRULE", &format!("FREQ=YEARLY;BYMONTH={};BYDAY={}", month, format_by_day(weekday + 7 * week)));
+    Some(c)
+}
+
+// a VTIMEZONE describing the zone's current rules
+fn timezone_component(tz: &TimeZone) -> Component {
+    let mut c = Component::new("VTIMEZONE");
+    c.add_text("TZID", tz.name());
+
+    let observances = tz.rule.as_ref().and_then(|r| r.dst.as_ref().and_then(|dst| {
+        let standard = rule_observance("STANDARD", dst, &r.std, &r.end, r.end_time);
+        let daylight = rule_observance("DAYLIGHT", &r.std, dst, &r.start, r.start_time);
+        standard.and_then(|s| daylight.map(|d| vec!(s, d)))
+    }));
+    match observances {
+        Some(o) => c.components.extend(o),
+        None => {
+            // no DST, or none we can describe, so just the offset in force now
+            let now = tz.local_type(UTC::now().timestamp());
+            c.components.push(observance("STANDARD", now, now, &NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0)));
+        },
+    }
+    c
+}
+
+// write a calendar and its events as an iCalendar document
+pub fn to_ical(calendar: &Calendar, events: &[CalendarEvent]) -> String {
+    let stamp = UTC::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut vcal = Component::new("VCALENDAR");
+    vcal.add("VERSION", "2.0");
+    vcal.add("PRODID", PRODID);
+    vcal.add("CALSCALE", "GREGORIAN");
+    if !calendar.name.is_empty() {
+        vcal.add_text("NAME", &calendar.name);
+        vcal.add_text("X-WR-CALNAME", &calendar.name);
+    }
+    if !calendar.color.is_empty() {
+        vcal.add_text("X-APPLE-CALENDAR-COLOR", &calendar.color);
+    }
+
+    // every zone the events use needs a VTIMEZONE, except UTC
+    let zones: BTreeSet<&TimeZoneName> = events.iter()
+        .flat_map(|e| e.start_time_zone.iter().chain(e.end_time_zone.iter()))
+        .filter(|tz| !tz.is_utc())
+        .collect();
+    for tz in zones.into_iter() {
+        vcal.components.push(timezone_component(&tz.zone()));
+    }
+
+    for e in events.iter() {
+        vcal.components.push(event_component(e, None, &stamp));
+
+        // overrides go out as their own VEVENTs
+        if let Some(ref x) = e.exceptions {
+            for (rid, _) in x.iter().filter(|&(_, p)| p.is_some()) {
+                if let Some(o) = e.occurrence(rid) {
+                    vcal.components.push(event_component(&o.event, Some((rid, &e.start_time_zone)), &stamp));
+                }
+            }
+        }
+    }
+
+    vcal.to_string()
+}
+
+// a VFREEBUSY listing the busy periods between after and before
+pub fn to_vfreebusy(periods: &[BusyPeriod], after: &Date, before: &Date) -> String {
+    let utc = |d: &Date| d.format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut vcal = Component::new("VCALENDAR");
+    vcal.add("VERSION", "2.0");
+    vcal.add("PRODID", PRODID);
+    vcal.add("METHOD", "PUBLISH");
+
+    let mut fb = Component::new("VFREEBUSY");
+    fb.add_text("UID", &record::new_id());
+    fb.add("DTSTAMP", &UTC::now().format("%Y%m%dT%H%M%SZ").to_string());
+    fb.add("DTSTART", &utc(after));
+    fb.add("DTEND", &utc(before));
+    for p in periods.iter() {
+        fb.properties.push(ContentLine::new("FREEBUSY", &format!("{}/{}", utc(&p.start), utc(&p.end))).with_param("FBTYPE", "BUSY"));
+    }
+    vcal.components.push(fb);
+
+    vcal.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calendar::Calendar;
+
+    fn event(props: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:e1\r\nDTSTART:20170105T100000\r\n{}END:VEVENT\r\nEND:VCALENDAR\r\n", props)
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("P1W2D"), Some(Duration::days(9)));
+        assert_eq!(parse_duration("-PT1H30M"), Some(Duration::minutes(-90)));
+        assert_eq!(parse_duration("PT15"), None);
+        assert_eq!(parse_duration("P1H"), None);
+    }
+
+    #[test]
+    fn huge_durations_rejected() {
+        assert_eq!(parse_duration("PT9999999999999999S"), None);
+        assert_eq!(parse_duration("P999999999999W"), None);
+        assert_eq!(parse_duration("P99999999999999999999D"), None);
+        assert!(parse_ical(&event("DURATION:P999999999999W\r\n"), "c1").is_err());
+        assert!(parse_ical(&event("DURATION:P52000000W\r\n"), "c1").is_err());
+    }
+
+    #[test]
+    fn duration_sets_end() {
+        let (_, events) = parse_ical(&event("DURATION:PT1H30M\r\n"), "c1").unwrap();
+        assert_eq!(events[0].end.format("%Y%m%dT%H%M%S").to_string(), "20170105T113000");
+    }
+
+    #[test]
+    fn folded_lines() {
+        let (_, events) = parse_ical(&event("SUMMARY:a long\r\n  summary\\, folded\r\nDTEND:20170105T110000\r\n"), "c1").unwrap();
+        assert_eq!(events[0].summary, "a long summary, folded");
+    }
+
+    #[test]
+    fn rrule_round_trip() {
+        let rrule = "RRULE:FREQ=MONTHLY;COUNT=6;BYDAY=-1SU,2MO;BYSETPOS=-1\r\n";
+        let (_, events) = parse_ical(&event(&format!("DTEND:20170105T110000\r\n{}", rrule)), "c1").unwrap();
+        let r = events[0].recurrence.as_ref().unwrap();
+        assert_eq!(r.by_set_position, Some(vec!(-1)));
+        assert_eq!(r.by_day.as_ref().map(|d| d.len()), Some(2));
+
+        let out = to_ical(&Calendar::default(), &events);
+        assert!(out.contains(rrule));
+        let (_, again) = parse_ical(&out, "c1").unwrap();
+        assert_eq!(again[0].recurrence, events[0].recurrence);
+        assert_eq!(again[0].start, events[0].start);
+        assert_eq!(again[0].end, events[0].end);
+    }
+
+    #[test]
+    fn zoned_round_trip_with_overrides() {
+        let text = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nX-WR-CALNAME:Work\r\n\
+                    BEGIN:VEVENT\r\nUID:e1\r\nSUMMARY:Standup\r\nLOCATION:Room 1\\; upstairs\r\n\
+                    DTSTART;TZID=Europe/London:20170320T093000\r\nDTEND;TZID=Europe/London:20170320T094500\r\n\
+                    RRULE:FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20170405T083000Z\r\n\
+                    EXDATE;TZID=Europe/London:20170322T093000\r\n\
+                    ATTENDEE;CN=\"Bob ^'B^'\";PARTSTAT=ACCEPTED:mailto:bob@example.com\r\n\
+                    END:VEVENT\r\n\
+                    BEGIN:VEVENT\r\nUID:e1\r\nRECURRENCE-ID;TZID=Europe/London:20170329T093000\r\nSUMMARY:Long standup\r\n\
+                    DTSTART;TZID=Europe/London:20170329T093000\r\nDTEND;TZID=Europe/London:20170329T103000\r\n\
+                    END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let (cal, events) = parse_ical(text, "c1").unwrap();
+        assert_eq!(cal.name, "Work");
+        assert_eq!(events.len(), 1);
+        let e = &events[0];
+        assert_eq!(e.location, "Room 1; upstairs");
+        assert_eq!(e.attendees.as_ref().unwrap()[0].name, "Bob \"B\"");
+
+        // 20 Mar is GMT, and BST starts on the 26th
+        let occurrences: Vec<(String,String)> = e.occurrences()
+            .map(|o| (o.event.start.format("%d %H:%M").to_string(), o.event.summary.clone()))
+            .collect();
+        assert_eq!(occurrences, vec!(
+            ("20 09:30".to_string(), "Standup".to_string()),
+            ("27 08:30".to_string(), "Standup".to_string()),
+            ("29 08:30".to_string(), "Long standup".to_string()),
+            ("03 08:30".to_string(), "Standup".to_string()),
+            ("05 08:30".to_string(), "Standup".to_string()),
+        ));
+
+        let out = to_ical(&cal, &events);
+        assert!(out.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/London\r\n"));
+        let (cal2, again) = parse_ical(&out, "c1").unwrap();
+        assert_eq!(cal2.name, cal.name);
+        assert_eq!(again, events);
+    }
+
+    #[test]
+    fn participant_names_cannot_inject_lines() {
+        let (_, mut events) = parse_ical(&event("DTEND:20170105T110000\r\nORGANIZER;CN=Me:mailto:me@example.com\r\n"), "c1").unwrap();
+        events[0].organizer.as_mut().unwrap().name = "Eve\r\nATTENDEE:mailto:injected@example.com".to_string();
+        let out = to_ical(&Calendar::default(), &events);
+        assert!(!out.contains("\r\nATTENDEE:mailto:injected"));
+        let (_, again) = parse_ical(&out, "c1").unwrap();
+        assert_eq!(again[0].organizer.as_ref().unwrap().name, "Eve\nATTENDEE:mailto:injected@example.com");
+        assert!(again[0].attendees.as_ref().map(|a| a.is_empty()).unwrap_or(true));
+    }
+
+    #[test]
+    fn free_busy_periods() {
+        let at = |d: u32, h: u32| from_local(&None, &NaiveDate::from_ymd(2017, 1, d).and_hms(h, 0, 0));
+        let periods = vec!(
+            BusyPeriod { start: at(2, 9), end: at(2, 10) },
+            BusyPeriod { start: at(3, 14), end: at(3, 15) },
+        );
+        let out = to_vfreebusy(&periods, &at(1, 0), &at(8, 0));
+        assert!(out.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(out.contains("METHOD:PUBLISH\r\nBEGIN:VFREEBUSY\r\n"));
+        assert!(out.contains("\r\nDTSTART:20170101T000000Z\r\nDTEND:20170108T000000Z\r\n"));
+        assert!(out.contains("\r\nFREEBUSY;FBTYPE=BUSY:20170102T090000Z/20170102T100000Z\r\nFREEBUSY;FBTYPE=BUSY:20170103T140000Z/20170103T150000Z\r\nEND:VFREEBUSY\r\n"));
+        assert!(out.ends_with("END:VCALENDAR\r\n"));
+    }
+}
@@ -7,10 +7,16 @@ extern crate chrono;
 pub mod parse;
 pub mod method;
 pub mod record;
+pub mod reference;
+pub mod dispatch;
+pub mod store;
+pub mod client;
+pub mod query;
 pub mod types;
 
 pub use self::mailbox::Mailbox;
 pub use self::message::Message;
+pub use self::thread::Thread;
 pub use self::calendar::Calendar;
 pub use self::calendar_event::CalendarEvent;
 pub use self::contact::Contact;
@@ -19,10 +25,26 @@ pub use self::contact_group::ContactGroup;
 pub mod mailbox;
 pub mod message;
 pub mod message_list;
+pub mod message_query;
+pub mod search_snippet;
+pub mod mime;
+pub mod mime_render;
 pub mod message_import;
 pub mod message_copy;
 pub mod message_report;
+pub mod thread;
 pub mod calendar;
 pub mod calendar_event;
+pub mod calendar_event_list;
+pub mod calendar_event_query;
+pub mod tz;
+pub mod recurrence;
+pub mod free_busy;
+pub mod alert_scheduler;
+pub mod content_line;
+pub mod ical;
 pub mod contact;
 pub mod contact_group;
+pub mod contact_list;
+pub mod contact_query;
+pub mod vcard;
@@ -38,25 +38,36 @@ macro_rules! make_prop_enum_type {
     }
 }
 
+// fields are "name: type => jsonName (set_name, with_name)", giving the names
+// of the setter and the chaining setter. the builder type is named too.
+// props can be given a check, run on every parsed or built value to reject
+// ones that are the right shape but don't make sense
 macro_rules! make_prop_type {
-    ($prop: ident, $propname: expr,
-     $($field: ident: $ty: ty => $jprop: expr),*) => {
+    ($prop: ident, $builder: ident, $propname: expr,
+     $($field: ident: $ty: ty => $jprop: literal ($set: ident, $with: ident)),*) => {
+        make_prop_type!($prop, $builder, $propname, check: |_: &$prop| -> Result<(),ParseError> { Ok(()) },
+            $($field: $ty => $jprop ($set, $with)),*);
+    };
+    ($prop: ident, $builder: ident, $propname: expr, check: $check: expr,
+     $($field: ident: $ty: ty => $jprop: literal ($set: ident, $with: ident)),*) => {
         #[derive(Clone, PartialEq, Debug)]
         pub struct $prop {
-            $($field: $ty,)*
+            $(pub(crate) $field: $ty,)*
+            pub(crate) _extra: BTreeMap<String,Json>,
         }
 
         impl Default for $prop {
             fn default() -> $prop {
                 $prop {
-                    $($field: Default::default()),*
+                    $($field: Default::default(),)*
+                    _extra: BTreeMap::new(),
                 }
             }
         }
 
         impl ToJson for $prop {
             fn to_json(&self) -> Json {
-                let mut d = BTreeMap::<String,Json>::new();
+                let mut d = self._extra.clone();
                 $(self.$field.to_json_field(&mut d, $jprop);)*
                 Json::Object(d)
             }
@@ -66,38 +77,117 @@ macro_rules! make_prop_type {
             fn from_json(json: &Json) -> Result<$prop,ParseError> {
                 match *json {
                     Json::Object(ref o) => {
+                        let mut errors = FieldErrors::new();
                         let mut prop = $prop::default();
-                        $(prop.$field = try!(FromJsonField::from_json_field(o, $jprop));)*
+                        $(if let Some(v) = errors.check(FromJsonField::from_json_field(o, $jprop)) {
+                            prop.$field = v;
+                        })*
+                        prop._extra = errors.extra(o, &[$($jprop),*]);
+                        try!(errors.finish());
+                        try!(($check)(&prop));
                         Ok(prop)
                     },
                     _ => Err(ParseError::InvalidJsonType($propname.to_string())),
                 }
             }
         }
+
+        impl $prop {
+            $(pub fn $field(&self) -> &$ty {
+                &self.$field
+            }
+
+            pub fn $set(&mut self, v: $ty) {
+                self.$field = v;
+            }
+
+            pub fn $with(mut self, v: $ty) -> $prop {
+                self.$field = v;
+                self
+            })*
+
+            pub fn extra(&self) -> &BTreeMap<String,Json> {
+                &self._extra
+            }
+
+            pub fn set_extra(&mut self, name: &str, v: Json) {
+                self._extra.insert(name.to_string(), v);
+            }
+
+            pub fn with_extra(mut self, name: &str, v: Json) -> $prop {
+                self._extra.insert(name.to_string(), v);
+                self
+            }
+
+            pub fn builder() -> $builder {
+                $builder::default()
+            }
+        }
+
+        // collects properties for a $prop, and checks the required ones
+        // are all there when it's built
+        #[derive(Clone, PartialEq, Debug, Default)]
+        pub struct $builder {
+            $($field: Presence<$ty>,)*
+            _extra: BTreeMap<String,Json>,
+        }
+
+        impl $builder {
+            $(pub fn $with(mut self, v: $ty) -> $builder {
+                self.$field = Presence::Present(v);
+                self
+            })*
+
+            pub fn with_extra(mut self, name: &str, v: Json) -> $builder {
+                self._extra.insert(name.to_string(), v);
+                self
+            }
+
+            pub fn build(self) -> Result<$prop,ParseError> {
+                let prop = $prop {
+                    $($field: try!(BuildField::build_field(self.$field, $jprop)),)*
+                    _extra: self._extra,
+                };
+                try!(($check)(&prop));
+                Ok(prop)
+            }
+        }
     }
 }
 
+// the access for a record property, from its optional tag in make_record_type!
+macro_rules! property_access {
+    ()          => { record::PropertyAccess::ClientWritable };
+    (server)    => { record::PropertyAccess::ServerSet };
+    (immutable) => { record::PropertyAccess::Immutable };
+}
+
+// fields are "name: type => jsonName (set_name, with_name)", as for props,
+// optionally followed by [server] for properties only the server sets, or
+// [immutable] for ones that can only be given on create
 macro_rules! make_record_type {
     ($record: ident, $partialrecord: ident, $recname: expr,
-     $($field: ident: $ty: ty => $jprop: expr),*) => {
+     $($field: ident: $ty: ty => $jprop: literal ($set: ident, $with: ident) $([$access: ident])?),*) => {
         #[derive(Clone, PartialEq, Debug)]
         pub struct $record {
-            id: String,
-            $($field: $ty),*
+            pub(crate) id: String,
+            $(pub(crate) $field: $ty,)*
+            pub(crate) _extra: BTreeMap<String,Json>,
         }
 
         impl Default for $record {
             fn default() -> $record {
                 $record {
                     id: record::new_id(),
-                    $($field: Default::default()),*
+                    $($field: Default::default(),)*
+                    _extra: BTreeMap::new(),
                 }
             }
         }
 
         impl ToJson for $record {
             fn to_json(&self) -> Json {
-                let mut d = BTreeMap::<String,Json>::new();
+                let mut d = self._extra.clone();
                 self.id.to_json_field(&mut d, "id");
                 $(self.$field.to_json_field(&mut d, $jprop);)*
                 Json::Object(d)
@@ -108,9 +198,16 @@ macro_rules! make_record_type {
             fn from_json(json: &Json) -> Result<$record,ParseError> {
                 match *json {
                     Json::Object(ref o) => {
+                        let mut errors = FieldErrors::new();
                         let mut r = $record::default();
-                        r.id = try!(FromJsonField::from_json_field(o, "id"));
-                        $(r.$field = try!(FromJsonField::from_json_field(o, $jprop));)*
+                        if let Some(id) = errors.check(FromJsonField::from_json_field(o, "id")) {
+                            r.id = id;
+                        }
+                        $(if let Some(v) = errors.check(FromJsonField::from_json_field(o, $jprop)) {
+                            r.$field = v;
+                        })*
+                        r._extra = errors.extra(o, &["id", $($jprop),*]);
+                        try!(errors.finish());
                         Ok(r)
                     }
                     _ => Err(ParseError::InvalidJsonType($recname.to_string())),
@@ -121,8 +218,12 @@ macro_rules! make_record_type {
 
         #[derive(Clone, PartialEq, Debug)]
         pub struct $partialrecord {
-            id: Presence<String>,
-            $($field: Presence<$ty>),*
+            pub(crate) id: Presence<String>,
+            $(pub(crate) $field: Presence<$ty>,)*
+            pub(crate) _extra: BTreeMap<String,Json>,
+            // properties given as null that can't be null. these are left
+            // absent, and make the partial invalid in a set call.
+            pub(crate) _null: Vec<String>,
         }
 
         impl PartialRecord for $partialrecord {
@@ -135,14 +236,16 @@ macro_rules! make_record_type {
             fn default() -> $partialrecord {
                 $partialrecord {
                     id: Absent,
-                    $($field: Absent),*
+                    $($field: Absent,)*
+                    _extra: BTreeMap::new(),
+                    _null: vec!(),
                 }
             }
         }
 
         impl ToJson for $partialrecord {
             fn to_json(&self) -> Json {
-                let mut d = BTreeMap::<String,Json>::new();
+                let mut d = self._extra.clone();
                 self.id.to_json_field(&mut d, "id");
                 $(self.$field.to_json_field(&mut d, $jprop);)*
                 Json::Object(d)
@@ -153,9 +256,18 @@ macro_rules! make_record_type {
             fn from_json(json: &Json) -> Result<$partialrecord,ParseError> {
                 match *json {
                     Json::Object(ref o) => {
+                        let mut errors = FieldErrors::new();
                         let mut r = $partialrecord::default();
-                        r.id = try!(FromJsonField::from_json_field(o, "id"));
-                        $(r.$field = try!(FromJsonField::from_json_field(o, $jprop));)*
+                        if let Some(id) = errors.check(FromJsonField::from_json_field(o, "id")) {
+                            r.id = id;
+                        }
+                        $(match FromPartialField::from_partial_field(o, $jprop) {
+                            Ok(v) => r.$field = v,
+                            Err(_) if o.get($jprop) == Some(&Json::Null) => r._null.push($jprop.to_string()),
+                            Err(e) => { errors.check::<()>(Err(e)); },
+                        })*
+                        r._extra = errors.extra(o, &["id", $($jprop),*]);
+                        try!(errors.finish());
                         Ok(r)
                     }
                     _ => Err(ParseError::InvalidJsonType($recname.to_string())),
@@ -176,6 +288,7 @@ macro_rules! make_record_type {
                 let u = p.clone();
                 if let Present(v) = u.id { r.id = v };
                 $(if let Present(v) = u.$field { r.$field = v };)*
+                update_extra_properties(&mut r._extra, &u._extra);
                 r
             }
 
@@ -183,6 +296,8 @@ macro_rules! make_record_type {
                 $partialrecord {
                     id: Present(self.id.clone()),
                     $($field: Present(self.$field.clone()),)*
+                    _extra: self._extra.clone(),
+                    _null: vec!(),
                 }
             }
 
@@ -192,11 +307,158 @@ macro_rules! make_record_type {
                 for prop in properties.iter() {
                     match prop.as_ref() {
                         $($jprop => p.$field = Present(self.$field.clone()),)*
-                        _ => ()
+                        _ => if let Some(v) = self._extra.get(prop) {
+                            p._extra.insert(prop.clone(), v.clone());
+                        },
                     }
                 }
                 p
             }
+
+            fn diff(&self, other: &$record) -> ($partialrecord, Vec<String>) {
+                let mut p = $partialrecord::default();
+                let mut changed = vec!();
+                p.id = Present(other.id.clone());
+                if self.id != other.id {
+                    changed.push("id".to_string());
+                }
+                $(if self.$field != other.$field {
+                    p.$field = Present(other.$field.clone());
+                    changed.push($jprop.to_string());
+                })*
+                p._extra = diff_extra_properties(&self._extra, &other._extra);
+                changed.extend(p._extra.keys().cloned());
+                (p, changed)
+            }
+
+            fn property_access(property: &str) -> Option<record::PropertyAccess> {
+                match property {
+                    "id" => Some(record::PropertyAccess::ServerSet),
+                    $($jprop => Some(property_access!($($access)?)),)*
+                    _ => None,
+                }
+            }
+
+            fn invalid_create_properties(p: &$partialrecord) -> Vec<String> {
+                let mut invalid = p._null.clone();
+                if let Present(_) = p.id {
+                    invalid.push("id".to_string());
+                }
+                $(if let (&Present(_), record::PropertyAccess::ServerSet) = (&p.$field, property_access!($($access)?)) {
+                    invalid.push($jprop.to_string());
+                })*
+                invalid
+            }
+
+            fn invalid_update_properties(&self, p: &$partialrecord) -> Vec<String> {
+                let mut invalid = p._null.clone();
+                if let Present(ref id) = p.id {
+                    if *id != self.id {
+                        invalid.push("id".to_string());
+                    }
+                }
+                $(if let Present(ref v) = p.$field {
+                    if property_access!($($access)?) != record::PropertyAccess::ClientWritable && *v != self.$field {
+                        invalid.push($jprop.to_string());
+                    }
+                })*
+                invalid
+            }
+        }
+
+
+        impl $record {
+            pub fn set_id(&mut self, id: String) {
+                self.id = id;
+            }
+
+            pub fn with_id(mut self, id: String) -> $record {
+                self.id = id;
+                self
+            }
+
+            $(pub fn $field(&self) -> &$ty {
+                &self.$field
+            }
+
+            pub fn $set(&mut self, v: $ty) {
+                self.$field = v;
+            }
+
+            pub fn $with(mut self, v: $ty) -> $record {
+                self.$field = v;
+                self
+            })*
+
+            pub fn extra(&self) -> &BTreeMap<String,Json> {
+                &self._extra
+            }
+
+            pub fn set_extra(&mut self, name: &str, v: Json) {
+                self._extra.insert(name.to_string(), v);
+            }
+
+            pub fn with_extra(mut self, name: &str, v: Json) -> $record {
+                self._extra.insert(name.to_string(), v);
+                self
+            }
+
+            // records are built up as partials, see $partialrecord::build
+            pub fn builder() -> $partialrecord {
+                $partialrecord::default()
+            }
+        }
+
+        impl $partialrecord {
+            pub fn set_id(&mut self, id: String) {
+                self.id = Present(id);
+            }
+
+            pub fn with_id(mut self, id: String) -> $partialrecord {
+                self.id = Present(id);
+                self
+            }
+
+            $(pub fn $field(&self) -> &Presence<$ty> {
+                &self.$field
+            }
+
+            pub fn $set(&mut self, v: $ty) {
+                self.$field = Present(v);
+            }
+
+            pub fn $with(mut self, v: $ty) -> $partialrecord {
+                self.$field = Present(v);
+                self
+            })*
+
+            // a null extra property clears it when the partial is applied
+            pub fn extra(&self) -> &BTreeMap<String,Json> {
+                &self._extra
+            }
+
+            pub fn set_extra(&mut self, name: &str, v: Json) {
+                self._extra.insert(name.to_string(), v);
+            }
+
+            pub fn with_extra(mut self, name: &str, v: Json) -> $partialrecord {
+                self._extra.insert(name.to_string(), v);
+                self
+            }
+
+            // a full record from this partial. all the required
+            // properties must be present; a missing id gets a new one.
+            pub fn build(&self) -> Result<$record,ParseError> {
+                let p = self.clone();
+                Ok($record {
+                    id: match p.id {
+                        Present(id) => id,
+                        Absent      => record::new_id(),
+                    },
+                    $($field: try!(BuildField::build_field(p.$field, $jprop)),)*
+                    _extra: p._extra.into_iter().filter(|&(_, ref v)| *v != Json::Null).collect(),
+                })
+            }
         }
     }
 }
@@ -206,12 +468,14 @@ macro_rules! make_method_args_type {
      $($field: ident: $ty: ty => $jprop: expr),*) => {
         #[derive(Clone, PartialEq, Debug)]
         pub struct $args {
+            pub _references: BTreeMap<String,ResultReference>,
             $(pub $field: $ty),*
         }
 
         impl Default for $args {
             fn default() -> $args {
                 $args {
+                    _references: BTreeMap::new(),
                     $($field: Default::default()),*
                 }
             }
@@ -220,7 +484,10 @@ macro_rules! make_method_args_type {
         impl ToJson for $args {
             fn to_json(&self) -> Json {
                 let mut d = BTreeMap::<String,Json>::new();
-                $(self.$field.to_json_field(&mut d, $jprop);)*
+                $(if !self._references.contains_key($jprop) {
+                    self.$field.to_json_field(&mut d, $jprop);
+                })*
+                reference::references_to_json(&self._references, &mut d);
                 Json::Object(d)
             }
         }
@@ -229,14 +496,37 @@ macro_rules! make_method_args_type {
             fn from_json(json: &Json) -> Result<$args,ParseError> {
                 match *json {
                     Json::Object(ref o) => {
+                        let mut errors = FieldErrors::new();
                         let mut args = <$args>::default();
-                        $(args.$field = try!(FromJsonField::from_json_field(o, $jprop));)*
+                        args._references = try!(reference::references_from_json(o, &[$($jprop),*], $argsname));
+                        $(if !args._references.contains_key($jprop) {
+                            if let Some(v) = errors.check(FromJsonField::from_json_field(o, $jprop)) {
+                                args.$field = v;
+                            }
+                        })*
+                        errors.check_unknown(o, &[$($jprop,)* $(concat!("#", $jprop)),*]);
+                        try!(errors.finish());
                         Ok(args)
                     },
                     _ => Err(ParseError::InvalidJsonType($argsname.to_string())),
                 }
             }
         }
+
+        impl ResolveReferences for $args {
+            fn resolve_references(&self, responses: &ResponseBatch) -> Result<$args,MethodError> {
+                let mut args = self.clone();
+                if self._references.is_empty() {
+                    return Ok(args);
+                }
+                let values = try!(reference::resolve(&self._references, responses));
+                $(if values.contains_key($jprop) {
+                    args.$field = try!(reference::resolved_field(&values, $jprop));
+                })*
+                args._references.clear();
+                Ok(args)
+            }
+        }
     }
 }
 
@@ -246,6 +536,7 @@ macro_rules! make_record_method_args_type {
         #[derive(Clone, PartialEq, Debug)]
         pub struct $args<R> where R: Record {
             pub _marker: PhantomData<R>,
+            pub _references: BTreeMap<String,ResultReference>,
             $(pub $field: $ty),*
         }
 
@@ -253,6 +544,7 @@ macro_rules! make_record_method_args_type {
             fn default() -> $args<R> {
                 $args::<R> {
                     _marker: PhantomData,
+                    _references: BTreeMap::new(),
                     $($field: Default::default()),*
                 }
             }
@@ -261,7 +553,10 @@ macro_rules! make_record_method_args_type {
         impl<R: Record> ToJson for $args<R> {
             fn to_json(&self) -> Json {
                 let mut d = BTreeMap::<String,Json>::new();
-                $(self.$field.to_json_field(&mut d, $jprop);)*
+                $(if !self._references.contains_key($jprop) {
+                    self.$field.to_json_field(&mut d, $jprop);
+                })*
+                reference::references_to_json(&self._references, &mut d);
                 Json::Object(d)
             }
         }
@@ -270,14 +565,37 @@ macro_rules! make_record_method_args_type {
             fn from_json(json: &Json) -> Result<$args<R>,ParseError> {
                 match *json {
                     Json::Object(ref o) => {
+                        let mut errors = FieldErrors::new();
                         let mut args = <$args<R>>::default();
-                        $(args.$field = try!(FromJsonField::from_json_field(o, $jprop));)*
+                        args._references = try!(reference::references_from_json(o, &[$($jprop),*], $argsname));
+                        $(if !args._references.contains_key($jprop) {
+                            if let Some(v) = errors.check(FromJsonField::from_json_field(o, $jprop)) {
+                                args.$field = v;
+                            }
+                        })*
+                        errors.check_unknown(o, &[$($jprop,)* $(concat!("#", $jprop)),*]);
+                        try!(errors.finish());
                         Ok(args)
                     },
                     _ => Err(ParseError::InvalidJsonType($argsname.to_string())),
                 }
             }
         }
+
+        impl<R: Record> ResolveReferences for $args<R> {
+            fn resolve_references(&self, responses: &ResponseBatch) -> Result<$args<R>,MethodError> {
+                let mut args = self.clone();
+                if self._references.is_empty() {
+                    return Ok(args);
+                }
+                let values = try!(reference::resolve(&self._references, responses));
+                $(if values.contains_key($jprop) {
+                    args.$field = try!(reference::resolved_field(&values, $jprop));
+                })*
+                args._references.clear();
+                Ok(args)
+            }
+        }
     }
 }
 
@@ -353,5 +671,53 @@ macro_rules! make_methods {
                 }.clone()
             }
         }
+
+        impl ResolveReferences for $set {
+            fn resolve_references(&self, responses: &ResponseBatch) -> Result<$set,MethodError> {
+                match *self {
+                    $($method(ref args, ref id) =>
+                        Ok($method(try!(args.resolve_references(responses)), id.clone())),)*
+                }
+            }
+        }
+
+        impl $set {
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $($method(..) => $methodname,)*
+                }
+            }
+
+            pub fn args_to_json(&self) -> Json {
+                match *self {
+                    $($method(ref args, _) => args.to_json(),)*
+                }
+            }
+        }
+    }
+}
+
+macro_rules! make_calls {
+    ($($args: ty, $method: ident => $response: ty, $rmethod: ident, $rname: expr),*) => {
+        $(
+            impl Call for $args {
+                type Response = $response;
+
+                fn to_request(self, client_id: String) -> RequestMethod {
+                    $method(self, client_id)
+                }
+
+                fn from_response(response: &ResponseMethod) -> Option<$response> {
+                    match *response {
+                        $rmethod(ref args, _) => Some(args.clone()),
+                        _ => None,
+                    }
+                }
+
+                fn response_name() -> &'static str {
+                    $rname
+                }
+            }
+        )*
     }
 }
@@ -70,19 +70,75 @@ impl FromJson for MailboxRole {
 
 
 make_record_type!(Mailbox, PartialMailbox, "Mailbox",
-    name:                 String              => "name",
-    parent_id:            Option<String>      => "parentId",
-    role:                 Option<MailboxRole> => "role",
-    sort_order:           u64                 => "sortOrder",
-    must_be_only_mailbox: bool                => "mustBeOnlyMailbox",
-    may_read_items:       bool                => "mayReadItems",
-    may_add_items:        bool                => "mayAddItems",
-    may_remove_items:     bool                => "mayRemoveItems",
-    may_create_child:     bool                => "mayCreateChild",
-    may_rename:           bool                => "mayRename",
-    may_delete:           bool                => "mayDelete",
-    total_messages:       u64                 => "totalMessages",
-    unread_messages:      u64                 => "unreadMessages",
-    total_threads:        u64                 => "totalThreads",
-    unread_threads:       u64                 => "unreadThreads"
+    name:                 String              => "name"              (set_name, with_name),
+    parent_id:            Option<String>      => "parentId"          (set_parent_id, with_parent_id),
+    role:                 Option<MailboxRole> => "role"              (set_role, with_role),
+    sort_order:           u64                 => "sortOrder"         (set_sort_order, with_sort_order),
+    must_be_only_mailbox: bool                => "mustBeOnlyMailbox" (set_must_be_only_mailbox, with_must_be_only_mailbox) [server],
+    may_read_items:       bool                => "mayReadItems"      (set_may_read_items, with_may_read_items)             [server],
+    may_add_items:        bool                => "mayAddItems"       (set_may_add_items, with_may_add_items)               [server],
+    may_remove_items:     bool                => "mayRemoveItems"    (set_may_remove_items, with_may_remove_items)         [server],
+    may_create_child:     bool                => "mayCreateChild"    (set_may_create_child, with_may_create_child)         [server],
+    may_rename:           bool                => "mayRename"         (set_may_rename, with_may_rename)                     [server],
+    may_delete:           bool                => "mayDelete"         (set_may_delete, with_may_delete)                     [server],
+    total_messages:       u64                 => "totalMessages"     (set_total_messages, with_total_messages)             [server],
+    unread_messages:      u64                 => "unreadMessages"    (set_unread_messages, with_unread_messages)           [server],
+    total_threads:        u64                 => "totalThreads"      (set_total_threads, with_total_threads)               [server],
+    unread_threads:       u64                 => "unreadThreads"     (set_unread_threads, with_unread_threads)             [server]
 );
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use super::*;
+
+    #[test]
+    fn null_partial_clears_parent_id() {
+        let m = Mailbox::default().with_parent_id(Some("p1".to_string()));
+        let p = PartialMailbox::from_json(&Json::from_str(r#"{"parentId":null}"#).unwrap()).unwrap();
+        assert_eq!(p.parent_id(), &Present(None));
+        assert_eq!(m.updated_with(&p).parent_id(), &None);
+        assert_eq!(m.updated_with(&PartialMailbox::default()).parent_id(), &Some("p1".to_string()));
+    }
+
+    #[test]
+    fn diff_has_only_changed_properties() {
+        let a = Mailbox::default().with_name("Inbox".to_string()).with_parent_id(Some("p1".to_string())).with_extra("x-a", Json::U64(1));
+        let mut b = a.clone().with_name("Old".to_string()).with_parent_id(None).with_sort_order(3).with_extra("x-b", Json::U64(2));
+        b._extra.remove("x-a");
+
+        let (p, changed) = a.diff(&b);
+        assert_eq!(changed, vec!("name", "parentId", "sortOrder", "x-a", "x-b"));
+        assert_eq!(p.id(), Present(a.id()));
+        assert_eq!(p.name(), &Present("Old".to_string()));
+        assert_eq!(p.parent_id(), &Present(None));
+        assert_eq!(p.role(), &Absent);
+        assert_eq!(p.extra().get("x-a"), Some(&Json::Null));
+        assert_eq!(a.updated_with(&p), b);
+
+        let (p, changed) = a.diff(&a);
+        assert!(changed.is_empty());
+        assert_eq!(p.to_json(), Json::from_str(&format!(r#"{{"id":"{}"}}"#, a.id())).unwrap());
+    }
+
+    #[test]
+    fn unknown_properties_kept() {
+        let json = Json::from_str(r#"{"id":"m1","name":"Inbox","parentId":null,"role":"inbox","sortOrder":1,
+            "mustBeOnlyMailbox":false,"mayReadItems":true,"mayAddItems":true,"mayRemoveItems":true,
+            "mayCreateChild":true,"mayRename":false,"mayDelete":false,"totalMessages":0,"unreadMessages":0,
+            "totalThreads":0,"unreadThreads":0,"x-colour":"red","x-icon":"box"}"#).unwrap();
+        let m = Mailbox::from_json(&json).unwrap();
+        assert_eq!(m.extra().get("x-colour"), Some(&Json::String("red".to_string())));
+        assert_eq!(m.to_json(), json);
+
+        // partials set and clear them
+        let p = PartialMailbox::from_json(&Json::from_str(r#"{"x-colour":"blue","x-icon":null,"x-new":1}"#).unwrap()).unwrap();
+        assert_eq!(p.to_json(), Json::from_str(r#"{"x-colour":"blue","x-icon":null,"x-new":1}"#).unwrap());
+        let m = m.updated_with(&p);
+        assert_eq!(m.extra().get("x-colour"), Some(&Json::String("blue".to_string())));
+        assert_eq!(m.extra().get("x-icon"), None);
+        assert_eq!(m.extra().get("x-new"), Some(&Json::U64(1)));
+        assert_eq!(m.name(), "Inbox");
+    }
+}
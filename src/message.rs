@@ -10,46 +10,98 @@ use record::{Record, PartialRecord};
 use types::Date;
 
 
-make_prop_type!(Emailer, "Emailer",
-    name:  String => "name",
-    email: String => "email"
+make_prop_type!(Emailer, EmailerBuilder, "Emailer",
+    name:  String => "name"  (set_name, with_name),
+    email: String => "email" (set_email, with_email)
 );
 
-make_prop_type!(Attachment, "Attachment",
-    blob_id:   String         => "blobId",
-    typ:       String         => "type",
-    name:      String         => "name",
-    size:      u64            => "size",
-    cid:       Option<String> => "cid",
-    is_inline: bool           => "isInline",
-    width:     Option<u64>    => "width",
-    height:    Option<u64>    => "height"
+make_prop_type!(Attachment, AttachmentBuilder, "Attachment",
+    blob_id:   String         => "blobId"   (set_blob_id, with_blob_id),
+    typ:       String         => "type"     (set_typ, with_typ),
+    name:      String         => "name"     (set_name, with_name),
+    size:      u64            => "size"     (set_size, with_size),
+    cid:       Option<String> => "cid"      (set_cid, with_cid),
+    is_inline: bool           => "isInline" (set_is_inline, with_is_inline),
+    width:     Option<u64>    => "width"    (set_width, with_width),
+    height:    Option<u64>    => "height"   (set_height, with_height)
 );
 
 
 make_record_type!(Message, PartialMessage, "Message",
-    blob_id:                String                              => "blobId",
-    thread_id:              String                              => "threadId",
-    mailbox_ids:            Vec<String>                         => "mailboxIds",
-    in_reply_to_message_id: Option<String>                      => "inReplyToMessageId",
-    is_unread:              bool                                => "isUnread",
-    is_flagged:             bool                                => "isFlagged",
-    is_answered:            bool                                => "isAnswered",
-    is_draft:               bool                                => "isDraft",
-    has_attachment:         bool                                => "hasAttachment",
-    headers:                BTreeMap<String,String>             => "headers",
-    sender:                 Option<Emailer>                     => "sender",
-    from:                   Option<Vec<Emailer>>                => "from",
-    to:                     Option<Vec<Emailer>>                => "to",
-    cc:                     Option<Vec<Emailer>>                => "cc",
-    bcc:                    Option<Vec<Emailer>>                => "bcc",
-    reply_to:               Option<Vec<Emailer>>                => "replyTo",
-    subject:                String                              => "subject",
-    date:                   Date                                => "date",
-    size:                   u64                                 => "size",
-    preview:                String                              => "preview",
-    text_body:              Option<String>                      => "textBody",
-    html_body:              Option<String>                      => "htmlBody",
-    attachments:            Option<Vec<Attachment>>             => "attachments",
-    attached_messages:      Option<BTreeMap<String,Message>>    => "attachedMessages"
+    blob_id:                String                           => "blobId"             (set_blob_id, with_blob_id)                               [server],
+    thread_id:              String                           => "threadId"           (set_thread_id, with_thread_id)                           [server],
+    mailbox_ids:            Vec<String>                      => "mailboxIds"         (set_mailbox_ids, with_mailbox_ids),
+    in_reply_to_message_id: Option<String>                   => "inReplyToMessageId" (set_in_reply_to_message_id, with_in_reply_to_message_id) [immutable],
+    is_unread:              bool                             => "isUnread"           (set_is_unread, with_is_unread),
+    is_flagged:             bool                             => "isFlagged"          (set_is_flagged, with_is_flagged),
+    is_answered:            bool                             => "isAnswered"         (set_is_answered, with_is_answered),
+    is_draft:               bool                             => "isDraft"            (set_is_draft, with_is_draft)                             [immutable],
+    has_attachment:         bool                             => "hasAttachment"      (set_has_attachment, with_has_attachment)                 [server],
+    headers:                BTreeMap<String,String>          => "headers"            (set_headers, with_headers)                               [immutable],
+    sender:                 Option<Emailer>                  => "sender"             (set_sender, with_sender)                                 [immutable],
+    from:                   Option<Vec<Emailer>>             => "from"               (set_from, with_from)                                     [immutable],
+    to:                     Option<Vec<Emailer>>             => "to"                 (set_to, with_to)                                         [immutable],
+    cc:                     Option<Vec<Emailer>>             => "cc"                 (set_cc, with_cc)                                         [immutable],
+    bcc:                    Option<Vec<Emailer>>             => "bcc"                (set_bcc, with_bcc)                                       [immutable],
+    reply_to:               Option<Vec<Emailer>>             => "replyTo"            (set_reply_to, with_reply_to)                             [immutable],
+    subject:                String                           => "subject"            (set_subject, with_subject)                               [immutable],
+    date:                   Date                             => "date"               (set_date, with_date)                                     [immutable],
+    size:                   u64                              => "size"               (set_size, with_size)                                     [server],
+    preview:                String                           => "preview"            (set_preview, with_preview)                               [server],
+    text_body:              Option<String>                   => "textBody"           (set_text_body, with_text_body)                           [immutable],
+    html_body:              Option<String>                   => "htmlBody"           (set_html_body, with_html_body)                           [immutable],
+    attachments:            Option<Vec<Attachment>>          => "attachments"        (set_attachments, with_attachments)                       [immutable],
+    attached_messages:      Option<BTreeMap<String,Message>> => "attachedMessages"   (set_attached_messages, with_attached_messages)           [server]
 );
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use record::PropertyAccess;
+    use method::SetError;
+    use super::*;
+
+    #[test]
+    fn null_partial_clears_in_reply_to() {
+        let m = Message::default().with_in_reply_to_message_id(Some("m1".to_string()));
+        let p = PartialMessage::from_json(&Json::from_str(r#"{"inReplyToMessageId":null}"#).unwrap()).unwrap();
+        assert_eq!(p.in_reply_to_message_id(), &Present(None));
+        assert_eq!(m.updated_with(&p).in_reply_to_message_id(), &None);
+    }
+
+    #[test]
+    fn builders_need_required_properties() {
+        assert_eq!(Emailer::builder().with_name("Bob".to_string()).build(), Err(ParseError::MissingField("email".to_string())));
+        let e = Emailer::builder().with_email("bob@example.com".to_string()).with_name("".to_string()).build().unwrap();
+        assert_eq!(e.email(), "bob@example.com");
+
+        // optional properties default to null
+        let a = Attachment::builder().with_blob_id("b1".to_string()).with_typ("text/plain".to_string()).with_name("a.txt".to_string()).with_size(3).with_is_inline(false).build().unwrap();
+        assert_eq!(a.cid(), &None);
+
+        let p = PartialMessage::default().with_subject("hi".to_string());
+        assert!(p.build().is_err());
+        let m = Message::default().with_subject("hi".to_string());
+        assert_eq!(m.to_partial().build(), Ok(m));
+    }
+
+    #[test]
+    fn server_set_and_immutable_properties() {
+        assert_eq!(Message::property_access("size"), Some(PropertyAccess::ServerSet));
+        assert_eq!(Message::property_access("subject"), Some(PropertyAccess::Immutable));
+        assert_eq!(Message::property_access("isUnread"), Some(PropertyAccess::ClientWritable));
+        assert_eq!(Message::property_access("bogus"), None);
+
+        let p = PartialMessage::default().with_size(10).with_subject("hi".to_string()).with_is_unread(true);
+        assert_eq!(record::validate_create::<Message>(&p), Err(SetError::invalid_properties(vec!("size".to_string()))));
+        assert_eq!(record::validate_create::<Message>(&p.clone().with_id("m1".to_string())),
+                   Err(SetError::invalid_properties(vec!("id".to_string(), "size".to_string()))));
+
+        let m = Message::default().with_size(10).with_subject("hi".to_string());
+        // unchanged values are fine, changed ones aren't
+        assert_eq!(record::validate_update(&m, &p), Ok(()));
+        let p = p.with_size(11).with_subject("bye".to_string());
+        assert_eq!(record::validate_update(&m, &p), Err(SetError::invalid_properties(vec!("subject".to_string(), "size".to_string()))));
+    }
+}
@@ -2,18 +2,20 @@ use std::collections::BTreeMap;
 use rustc_serialize::json::{Json,ToJson};
 
 use parse::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
 use record::Record;
 
 use message::Message;
-use method::SetError;
+use method::{SetError, MethodError, ResponseBatch};
 
-make_prop_type!(MessageImport, "MessageImport",
-    blob_id:     String      => "blobId",
-    mailbox_ids: Vec<String> => "mailboxIds",
-    is_unread:   bool        => "isUnread",
-    is_flagged:  bool        => "isFlagged",
-    is_answered: bool        => "isAnswered",
-    is_draft:    bool        => "isDraft"
+make_prop_type!(MessageImport, MessageImportBuilder, "MessageImport",
+    blob_id:     String      => "blobId"     (set_blob_id, with_blob_id),
+    mailbox_ids: Vec<String> => "mailboxIds" (set_mailbox_ids, with_mailbox_ids),
+    is_unread:   bool        => "isUnread"   (set_is_unread, with_is_unread),
+    is_flagged:  bool        => "isFlagged"  (set_is_flagged, with_is_flagged),
+    is_answered: bool        => "isAnswered" (set_is_answered, with_is_answered),
+    is_draft:    bool        => "isDraft"    (set_is_draft, with_is_draft)
 );
 
 make_method_args_type!(ImportMessagesRequestArgs, "ImportMessagesRequestArgs",
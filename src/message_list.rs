@@ -2,35 +2,38 @@ use std::collections::BTreeMap;
 use rustc_serialize::json::{Json,ToJson};
 
 use parse::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
+use method::{MethodError, ResponseBatch};
 use types::Date;
 
-make_prop_type!(FilterOperator, "FilterOperator",
-    operator:   String      => "operator",
-    conditions: Vec<Filter> => "conditions"
+make_prop_type!(FilterOperator, FilterOperatorBuilder, "FilterOperator",
+    operator:   String      => "operator"   (set_operator, with_operator),
+    conditions: Vec<Filter> => "conditions" (set_conditions, with_conditions)
 );
 
-make_prop_type!(FilterCondition, "FilterCondition",
-    in_mailboxes:      Presence<Vec<String>> => "inMailboxes",
-    not_in_mailboxes:  Presence<Vec<String>> => "notInnMailboxes",
-    before:            Presence<Date>        => "before",
-    after:             Presence<Date>        => "after",
-    min_size:          Presence<u64>         => "minSize",
-    max_size:          Presence<u64>         => "maxSize",
-    thread_is_flagged: Presence<bool>        => "threadIsFlagged",
-    thread_is_unread:  Presence<bool>        => "threadIsUnread",
-    is_flagged:        Presence<bool>        => "isFlagged",
-    is_unread:         Presence<bool>        => "isUnread",
-    is_answered:       Presence<bool>        => "isAnswered",
-    is_draft:          Presence<bool>        => "isDraft",
-    has_attachment:    Presence<bool>        => "hasAttachment",
-    text:              Presence<String>      => "text",
-    from:              Presence<String>      => "from",
-    to:                Presence<String>      => "to",
-    cc:                Presence<String>      => "cc",
-    bcc:               Presence<String>      => "bcc",
-    subject:           Presence<String>      => "subject",
-    body:              Presence<String>      => "body",
-    header:            Presence<Vec<String>> => "header"
+make_prop_type!(FilterCondition, FilterConditionBuilder, "FilterCondition",
+    in_mailboxes:      Presence<Vec<String>> => "inMailboxes"     (set_in_mailboxes, with_in_mailboxes),
+    not_in_mailboxes:  Presence<Vec<String>> => "notInMailboxes"  (set_not_in_mailboxes, with_not_in_mailboxes),
+    before:            Presence<Date>        => "before"          (set_before, with_before),
+    after:             Presence<Date>        => "after"           (set_after, with_after),
+    min_size:          Presence<u64>         => "minSize"         (set_min_size, with_min_size),
+    max_size:          Presence<u64>         => "maxSize"         (set_max_size, with_max_size),
+    thread_is_flagged: Presence<bool>        => "threadIsFlagged" (set_thread_is_flagged, with_thread_is_flagged),
+    thread_is_unread:  Presence<bool>        => "threadIsUnread"  (set_thread_is_unread, with_thread_is_unread),
+    is_flagged:        Presence<bool>        => "isFlagged"       (set_is_flagged, with_is_flagged),
+    is_unread:         Presence<bool>        => "isUnread"        (set_is_unread, with_is_unread),
+    is_answered:       Presence<bool>        => "isAnswered"      (set_is_answered, with_is_answered),
+    is_draft:          Presence<bool>        => "isDraft"         (set_is_draft, with_is_draft),
+    has_attachment:    Presence<bool>        => "hasAttachment"   (set_has_attachment, with_has_attachment),
+    text:              Presence<String>      => "text"            (set_text, with_text),
+    from:              Presence<String>      => "from"            (set_from, with_from),
+    to:                Presence<String>      => "to"              (set_to, with_to),
+    cc:                Presence<String>      => "cc"              (set_cc, with_cc),
+    bcc:               Presence<String>      => "bcc"             (set_bcc, with_bcc),
+    subject:           Presence<String>      => "subject"         (set_subject, with_subject),
+    body:              Presence<String>      => "body"            (set_body, with_body),
+    header:            Presence<Vec<String>> => "header"          (set_header, with_header)
 );
 
 #[derive(Clone, PartialEq, Debug)]
@@ -52,9 +55,17 @@ impl FromJson for Filter {
     fn from_json(json: &Json) -> Result<Filter,ParseError> {
         match *json {
             Json::Object(ref o) => {
-                match o.get("operator") {
-                    Some(_) => Ok(Filter::Operator(try!(FilterOperator::from_json(json)))),
-                    None    => Ok(Filter::Condition(try!(FilterCondition::from_json(json)))),
+                match (o.get("operator"), o.get("notInnMailboxes")) {
+                    (Some(_), _) => Ok(Filter::Operator(try!(FilterOperator::from_json(json)))),
+                    // notInMailboxes used to be misspelt notInnMailboxes.
+                    // take the old name from clients that still send it.
+                    (None, Some(v)) if !o.contains_key("notInMailboxes") => {
+                        let mut o = o.clone();
+                        o.remove("notInnMailboxes");
+                        o.insert("notInMailboxes".to_string(), v.clone());
+                        Ok(Filter::Condition(try!(FilterCondition::from_json(&Json::Object(o)))))
+                    },
+                    (None, _) => Ok(Filter::Condition(try!(FilterCondition::from_json(json)))),
                 }
             },
             _ => Err(ParseError::InvalidJsonType("Filter".to_string())),
@@ -62,20 +73,20 @@ impl FromJson for Filter {
     }
 }
 
-make_prop_type!(RemovedItem, "RemovedItem",
-    message_id: String => "messageId",
-    thread_id:  String => "threadId"
+make_prop_type!(RemovedItem, RemovedItemBuilder, "RemovedItem",
+    message_id: String => "messageId" (set_message_id, with_message_id),
+    thread_id:  String => "threadId"  (set_thread_id, with_thread_id)
 );
 
-make_prop_type!(AddedItem, "AddedItem",
-    message_id: String => "messageId",
-    thread_id:  String => "threadId",
-    index:      u64    => "index"
+make_prop_type!(AddedItem, AddedItemBuilder, "AddedItem",
+    message_id: String => "messageId" (set_message_id, with_message_id),
+    thread_id:  String => "threadId"  (set_thread_id, with_thread_id),
+    index:      u64    => "index"     (set_index, with_index)
 );
 
 make_method_args_type!(GetMessageListRequestArgs, "GetMessageListRequestArgs",
     account_id:               Presence<String>      => "accountId",
-    //filter:
+    filter:                   Presence<Filter>      => "filter",
     sort:                     Presence<Vec<String>> => "sort",
     collapse_threads:         Presence<bool>        => "collapseThreads",
     position:                 Presence<u64>         => "position",
@@ -89,21 +100,21 @@ make_method_args_type!(GetMessageListRequestArgs, "GetMessageListRequestArgs",
 );
 
 make_method_args_type!(GetMessageListResponseArgs, "GetMessageListResponseArgs",
-    account_id:            String      => "accountId",
-    //filter:
-    sort:                  Vec<String> => "sort",
-    collapse_threads:      bool        => "collapseThreads",
-    state:                 String      => "state",
-    can_calculate_updates: bool        => "can_calculate_updates",
-    position:              u64         => "position",
-    total:                 u64         => "total",
-    thread_ids:            Vec<String> => "thread_ids",
-    message_ids:           Vec<String> => "message_ids"
+    account_id:            String         => "accountId",
+    filter:                Option<Filter> => "filter",
+    sort:                  Vec<String>    => "sort",
+    collapse_threads:      bool           => "collapseThreads",
+    state:                 String         => "state",
+    can_calculate_updates: bool           => "canCalculateUpdates",
+    position:              u64            => "position",
+    total:                 u64            => "total",
+    thread_ids:            Vec<String>    => "threadIds",
+    message_ids:           Vec<String>    => "messageIds"
 );
 
 make_method_args_type!(GetMessageListUpdatesRequestArgs, "GetMessageListRequestArgs",
     account_id:       Presence<String>      => "accountId",
-    //filter:
+    filter:           Presence<Filter>      => "filter",
     sort:             Presence<Vec<String>> => "sort",
     collapse_threads: Presence<bool>        => "collapseThreads",
     since_state:      String                => "sinceState",
@@ -112,8 +123,8 @@ make_method_args_type!(GetMessageListUpdatesRequestArgs, "GetMessageListRequestA
 );
 
 make_method_args_type!(GetMessageListUpdatesResponseArgs, "GetMessageListResponseArgs",
-    account_id:       String => "accountId",
-    //filter:
+    account_id:       String           => "accountId",
+    filter:           Option<Filter>   => "filter",
     sort:             Vec<String>      => "sort",
     collapse_threads: bool             => "collapseThreads",
     old_state:        String           => "oldState",
@@ -123,3 +134,54 @@ make_method_args_type!(GetMessageListUpdatesResponseArgs, "GetMessageListRespons
     removed:          Vec<RemovedItem> => "removed",
     added:            Vec<AddedItem>   => "added"
 );
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::{Json,ToJson};
+    use method::ResponseMethod;
+    use parse::{FromJson, from_json_strict};
+    use parse::Presence::*;
+    use super::*;
+
+    // these were snake_case, and a messageList response was read as the
+    // arguments of a getMessageListUpdates request
+    #[test]
+    fn message_list_response_wire_format() {
+        let mut args = GetMessageListResponseArgs::default();
+        args.can_calculate_updates = true;
+        args.thread_ids = vec!("t1".to_string());
+        args.message_ids = vec!("m1".to_string());
+
+        let json = args.to_json();
+        let o = json.as_object().unwrap();
+        assert_eq!(o.get("canCalculateUpdates"), Some(&Json::Boolean(true)));
+        assert_eq!(o.get("threadIds"), Some(&args.thread_ids.to_json()));
+        assert_eq!(o.get("messageIds"), Some(&args.message_ids.to_json()));
+        assert!(o.get("can_calculate_updates").is_none());
+
+        let response = Json::Array(vec!("messageList".to_json(), json, "c1".to_json()));
+        assert_eq!(ResponseMethod::from_json(&response), Ok(ResponseMethod::MessageList(args, "c1".to_string())));
+    }
+
+    #[test]
+    fn old_not_in_mailboxes_spelling_accepted() {
+        let json = Json::from_str(r#"{"notInnMailboxes": ["trash"]}"#).unwrap();
+        for f in vec!(Filter::from_json(&json), from_json_strict::<Filter>(&json)) {
+            match f {
+                Ok(Filter::Condition(ref c)) => assert_eq!(c.not_in_mailboxes, Present(vec!("trash".to_string()))),
+                _ => panic!("{:?}", f),
+            }
+        }
+
+        let f = Filter::from_json(&json).unwrap().to_json();
+        assert_eq!(f, Json::from_str(r#"{"notInMailboxes": ["trash"]}"#).unwrap());
+
+        // the right name wins if both are given
+        let json = Json::from_str(r#"{"notInMailboxes": ["a"], "notInnMailboxes": ["b"]}"#).unwrap();
+        match Filter::from_json(&json) {
+            Ok(Filter::Condition(ref c)) => assert_eq!(c.not_in_mailboxes, Present(vec!("a".to_string()))),
+            f => panic!("{:?}", f),
+        }
+    }
+}
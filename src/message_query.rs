@@ -0,0 +1,543 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use parse::Presence;
+use parse::Presence::*;
+use method::{MethodError, ErrorDescription};
+use message::{Message, Emailer};
+use message_list::*;
+use query::*;
+
+
+// flags rolled up across all the messages in a thread
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+struct ThreadFlags {
+    is_flagged: bool,
+    is_unread:  bool,
+}
+
+// evaluates getMessageList queries over a set of messages
+pub struct MessageQuery<'a> {
+    messages: Vec<&'a Message>,
+    threads:  BTreeMap<&'a str,ThreadFlags>,
+}
+
+fn emailers_match(emailers: &Option<Vec<Emailer>>, s: &str) -> bool {
+    match *emailers {
+        Some(ref v) => v.iter().any(|e| text_matches(&e.name, s) || text_matches(&e.email, s)),
+        None        => false,
+    }
+}
+
+fn option_matches(v: &Option<String>, s: &str) -> bool {
+    match *v {
+        Some(ref v) => text_matches(v, s),
+        None        => false,
+    }
+}
+
+fn body_matches(m: &Message, s: &str) -> bool {
+    option_matches(&m.text_body, s) || option_matches(&m.html_body, s)
+}
+
+fn text_condition_matches(m: &Message, s: &str) -> bool {
+    let sender = match m.sender {
+        Some(ref e) => text_matches(&e.name, s) || text_matches(&e.email, s),
+        None        => false,
+    };
+    let attachments = match m.attachments {
+        Some(ref v) => v.iter().any(|a| text_matches(&a.name, s)),
+        None        => false,
+    };
+    sender || attachments ||
+        emailers_match(&m.from, s) || emailers_match(&m.to, s) ||
+        emailers_match(&m.cc, s) || emailers_match(&m.bcc, s) ||
+        text_matches(&m.subject, s) || body_matches(m, s)
+}
+
+// sort key for an address list: the first name, or the email if no name
+fn emailers_key(emailers: &Option<Vec<Emailer>>) -> String {
+    match *emailers {
+        Some(ref v) if !v.is_empty() => match v[0].name.is_empty() {
+            true => v[0].email.to_lowercase(),
+            _    => v[0].name.to_lowercase(),
+        },
+        _ => "".to_string(),
+    }
+}
+
+// check the filter is something we can evaluate before running it
+pub fn validate_filter(filter: &Filter) -> Result<(),MethodError> {
+    match *filter {
+        Filter::Operator(ref o) => {
+            match o.operator.as_ref() {
+                "AND" | "OR" | "NOT" => (),
+                op => return Err(MethodError::InvalidArguments(Present(ErrorDescription(format!("unknown filter operator \"{}\"", op))))),
+            }
+            for f in o.conditions.iter() {
+                try!(validate_filter(f));
+            }
+            Ok(())
+        },
+        Filter::Condition(ref c) => {
+            if let Present(ref h) = c.header {
+                if h.is_empty() || h.len() > 2 {
+                    return Err(MethodError::InvalidArguments(Present(ErrorDescription("header condition must have one or two items".to_string()))));
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+pub fn validate_sort(sort: &[SortComparator]) -> Result<(),MethodError> {
+    for c in sort.iter() {
+        match c.property.as_ref() {
+            "id" | "date" | "size" | "from" | "to" | "subject" |
+            "isFlagged" | "isUnread" | "threadIsFlagged" | "threadIsUnread" => (),
+            _ => return Err(MethodError::UnsupportedSort),
+        }
+    }
+    Ok(())
+}
+
+impl<'a> MessageQuery<'a> {
+    pub fn new<I>(messages: I) -> MessageQuery<'a> where I: IntoIterator<Item=&'a Message> {
+        let messages: Vec<&'a Message> = messages.into_iter().collect();
+        let mut threads = BTreeMap::<&'a str,ThreadFlags>::new();
+        for m in messages.iter() {
+            let t = threads.entry(&m.thread_id).or_insert(ThreadFlags::default());
+            t.is_flagged = t.is_flagged || m.is_flagged;
+            t.is_unread = t.is_unread || m.is_unread;
+        }
+        MessageQuery {
+            messages: messages,
+            threads:  threads,
+        }
+    }
+
+    fn thread_flags(&self, m: &Message) -> ThreadFlags {
+        match self.threads.get(m.thread_id.as_str()) {
+            Some(t) => *t,
+            None    => ThreadFlags::default(),
+        }
+    }
+
+    fn condition_matches(&self, m: &Message, c: &FilterCondition) -> bool {
+        if let Present(ref ids) = c.in_mailboxes {
+            if !ids.iter().all(|id| m.mailbox_ids.contains(id)) { return false }
+        }
+        if let Present(ref ids) = c.not_in_mailboxes {
+            if ids.iter().any(|id| m.mailbox_ids.contains(id)) { return false }
+        }
+        if let Present(ref d) = c.before {
+            if *m.date >= **d { return false }
+        }
+        if let Present(ref d) = c.after {
+            if *m.date < **d { return false }
+        }
+        if let Present(n) = c.min_size {
+            if m.size < n { return false }
+        }
+        if let Present(n) = c.max_size {
+            if m.size >= n { return false }
+        }
+
+        let thread = self.thread_flags(m);
+        let flags = [
+            (&c.thread_is_flagged, thread.is_flagged),
+            (&c.thread_is_unread,  thread.is_unread),
+            (&c.is_flagged,        m.is_flagged),
+            (&c.is_unread,         m.is_unread),
+            (&c.is_answered,       m.is_answered),
+            (&c.is_draft,          m.is_draft),
+            (&c.has_attachment,    m.has_attachment),
+        ];
+        for &(want, have) in flags.iter() {
+            if let Present(w) = *want {
+                if w != have { return false }
+            }
+        }
+
+        if let Present(ref s) = c.text {
+            if !text_condition_matches(m, s) { return false }
+        }
+        if let Present(ref s) = c.from {
+            if !emailers_match(&m.from, s) { return false }
+        }
+        if let Present(ref s) = c.to {
+            if !emailers_match(&m.to, s) { return false }
+        }
+        if let Present(ref s) = c.cc {
+            if !emailers_match(&m.cc, s) { return false }
+        }
+        if let Present(ref s) = c.bcc {
+            if !emailers_match(&m.bcc, s) { return false }
+        }
+        if let Present(ref s) = c.subject {
+            if !text_matches(&m.subject, s) { return false }
+        }
+        if let Present(ref s) = c.body {
+            if !body_matches(m, s) { return false }
+        }
+        if let Present(ref h) = c.header {
+            let name = h[0].to_lowercase();
+            let value = m.headers.iter().find(|&(k, _)| k.to_lowercase() == name).map(|(_, v)| v);
+            match (value, h.get(1)) {
+                (None, _)          => return false,
+                (Some(v), Some(s)) => if !text_matches(v, s) { return false },
+                (Some(_), None)    => (),
+            }
+        }
+
+        true
+    }
+
+    pub fn matches(&self, m: &Message, filter: &Filter) -> bool {
+        match *filter {
+            Filter::Operator(ref o) => match o.operator.as_ref() {
+                "AND" => o.conditions.iter().all(|f| self.matches(m, f)),
+                "OR"  => o.conditions.iter().any(|f| self.matches(m, f)),
+                "NOT" => !o.conditions.iter().any(|f| self.matches(m, f)),
+                _     => false,
+            },
+            Filter::Condition(ref c) => self.condition_matches(m, c),
+        }
+    }
+
+    fn compare_property(&self, a: &Message, b: &Message, property: &str) -> Ordering {
+        match property {
+            "id"              => a.id.cmp(&b.id),
+            "date"            => a.date.cmp(&b.date),
+            "size"            => a.size.cmp(&b.size),
+            "from"            => emailers_key(&a.from).cmp(&emailers_key(&b.from)),
+            "to"              => emailers_key(&a.to).cmp(&emailers_key(&b.to)),
+            "subject"         => a.subject.to_lowercase().cmp(&b.subject.to_lowercase()),
+            "isFlagged"       => a.is_flagged.cmp(&b.is_flagged),
+            "isUnread"        => a.is_unread.cmp(&b.is_unread),
+            "threadIsFlagged" => self.thread_flags(a).is_flagged.cmp(&self.thread_flags(b).is_flagged),
+            "threadIsUnread"  => self.thread_flags(a).is_unread.cmp(&self.thread_flags(b).is_unread),
+            _                 => Ordering::Equal,
+        }
+    }
+
+    // compare two messages by each comparator in turn, falling back to the id
+    // so the order is always stable
+    pub fn compare(&self, a: &Message, b: &Message, sort: &[SortComparator]) -> Ordering {
+        for c in sort.iter() {
+            match c.direct(self.compare_property(a, b, &c.property)) {
+                Ordering::Equal => continue,
+                o               => return o,
+            }
+        }
+        a.id.cmp(&b.id)
+    }
+
+    // the full filtered and sorted list, with only the first message of each
+    // thread if collapsing
+    pub fn list(&self, filter: &Presence<Filter>, sort: &[SortComparator], collapse_threads: bool) -> Result<Vec<&'a Message>,MethodError> {
+        if let Present(ref f) = *filter {
+            try!(validate_filter(f));
+        }
+        try!(validate_sort(sort));
+
+        let mut list: Vec<&'a Message> = self.messages.iter().cloned().filter(|m| match *filter {
+            Present(ref f) => self.matches(m, f),
+            Absent         => true,
+        }).collect();
+        list.sort_by(|a, b| self.compare(a, b, sort));
+
+        if collapse_threads {
+            let mut seen = BTreeMap::<&str,()>::new();
+            list.retain(|m| seen.insert(&m.thread_id, ()).is_none());
+        }
+
+        Ok(list)
+    }
+
+    pub fn get_message_list(&self, args: &GetMessageListRequestArgs, state: &str) -> Result<GetMessageListResponseArgs,MethodError> {
+        let sort_strings = match args.sort {
+            Present(ref s) => s.clone(),
+            Absent         => vec!("date desc".to_string()),
+        };
+        let sort = try!(SortComparator::parse_all(&sort_strings));
+        let collapse_threads = match args.collapse_threads {
+            Present(c) => c,
+            Absent     => false,
+        };
+
+        let list = try!(self.list(&args.filter, &sort, collapse_threads));
+
+        let position = try!(start_position(&args.position, &args.anchor, &args.anchor_offset,
+            |id| list.iter().position(|m| m.id == id)));
+        let window = page(&list, position, &args.limit);
+
+        let mut response = GetMessageListResponseArgs::default();
+        response.account_id = match args.account_id {
+            Present(ref a) => a.clone(),
+            Absent         => "".to_string(),
+        };
+        response.filter = args.filter.as_option().cloned();
+        response.sort = sort_strings;
+        response.collapse_threads = collapse_threads;
+        response.state = state.to_string();
+        response.position = position as u64;
+        response.total = list.len() as u64;
+        response.thread_ids = window.iter().map(|m| m.thread_id.clone()).collect();
+        response.message_ids = window.iter().map(|m| m.id.clone()).collect();
+        Ok(response)
+    }
+}
+
+
+// (message id, thread id) pairs, in list order, for the updates calculation
+pub fn list_items(list: &[&Message]) -> Vec<(String,String)> {
+    list.iter().map(|m| (m.id.clone(), m.thread_id.clone())).collect()
+}
+
+// the items of seq (new index, old index) that form the longest run with
+// increasing old indexes, ie the messages that can stay where they are
+fn longest_increasing(seq: &[(usize,usize)]) -> Vec<usize> {
+    // tails[k] is the index into seq of the smallest tail of a run of length k+1
+    let mut tails: Vec<usize> = vec!();
+    let mut prev: Vec<Option<usize>> = vec!(None; seq.len());
+    for (i, &(_, old)) in seq.iter().enumerate() {
+        let k = match tails.binary_search_by(|&t| seq[t].1.cmp(&old)) {
+            Ok(k)  => k,
+            Err(k) => k,
+        };
+        if k > 0 {
+            prev[i] = Some(tails[k-1]);
+        }
+        match k == tails.len() {
+            true => tails.push(i),
+            _    => tails[k] = i,
+        }
+    }
+
+    let mut out = vec!();
+    let mut next = tails.last().cloned();
+    while let Some(i) = next {
+        out.push(seq[i].0);
+        next = prev[i];
+    }
+    out.reverse();
+    out
+}
+
+// the part of a list up to and including upto, or all of it if upto isn't there
+fn upto_prefix<'a>(list: &'a [(String,String)], upto: &Presence<String>) -> &'a [(String,String)] {
+    match *upto {
+        Present(ref id) => match list.iter().position(|&(ref m, _)| m == id) {
+            Some(i) => &list[..i+1],
+            None    => list,
+        },
+        Absent => list,
+    }
+}
+
+// work out the changes between two versions of a message list. both lists
+// should be the full filtered and sorted (and if requested, collapsed) lists
+// as returned by MessageQuery::list. when threads are collapsed, a thread
+// whose representative message changed shows up as the old message removed
+// and the new one added.
+pub fn get_message_list_updates(old: &[(String,String)], new: &[(String,String)], args: &GetMessageListUpdatesRequestArgs, new_state: &str) -> Result<GetMessageListUpdatesResponseArgs,MethodError> {
+    let old_prefix = upto_prefix(old, &args.upto_message_id);
+    let new_prefix = upto_prefix(new, &args.upto_message_id);
+
+    let old_index: BTreeMap<&str,usize> = old_prefix.iter().enumerate().map(|(i, &(ref m, _))| (m.as_str(), i)).collect();
+    let seq: Vec<(usize,usize)> = new_prefix.iter().enumerate().filter_map(|(i, &(ref m, _))| old_index.get(m.as_str()).map(|&o| (i, o))).collect();
+    let kept: BTreeMap<&str,()> = longest_increasing(&seq).into_iter().map(|i| (new_prefix[i].0.as_str(), ())).collect();
+
+    let mut response = GetMessageListUpdatesResponseArgs::default();
+
+    for &(ref m, ref t) in old_prefix.iter().filter(|&&(ref m, _)| !kept.contains_key(m.as_str())) {
+        let mut r = RemovedItem::default();
+        r.message_id = m.clone();
+        r.thread_id = t.clone();
+        response.removed.push(r);
+    }
+    for (i, &(ref m, ref t)) in new_prefix.iter().enumerate().filter(|&(_, &(ref m, _))| !kept.contains_key(m.as_str())) {
+        let mut a = AddedItem::default();
+        a.message_id = m.clone();
+        a.thread_id = t.clone();
+        a.index = i as u64;
+        response.added.push(a);
+    }
+
+    if let Present(max) = args.max_changes {
+        if (response.removed.len() + response.added.len()) as u64 > max {
+            return Err(MethodError::TooManyChanges);
+        }
+    }
+
+    response.account_id = match args.account_id {
+        Present(ref a) => a.clone(),
+        Absent         => "".to_string(),
+    };
+    response.filter = args.filter.as_option().cloned();
+    response.sort = match args.sort {
+        Present(ref s) => s.clone(),
+        Absent         => vec!("date desc".to_string()),
+    };
+    response.collapse_threads = match args.collapse_threads {
+        Present(c) => c,
+        Absent     => false,
+    };
+    response.old_state = args.since_state.clone();
+    response.new_state = new_state.to_string();
+    response.upto_message_id = args.upto_message_id.clone();
+    response.total = new.len() as u64;
+    Ok(response)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::Json;
+    use chrono::NaiveDate;
+    use parse::FromJson;
+    use tz::from_local;
+    use super::*;
+
+    fn message(id: &str, thread_id: &str, mailbox_ids: &[&str], day: u32, subject: &str, size: u64) -> Message {
+        Message::default()
+            .with_id(id.to_string())
+            .with_thread_id(thread_id.to_string())
+            .with_mailbox_ids(mailbox_ids.iter().map(|s| s.to_string()).collect())
+            .with_date(from_local(&None, &NaiveDate::from_ymd(2017, 1, day).and_hms(9, 0, 0)))
+            .with_subject(subject.to_string())
+            .with_size(size)
+    }
+
+    fn emailer(name: &str, email: &str) -> Option<Vec<Emailer>> {
+        Some(vec!(Emailer::default().with_name(name.to_string()).with_email(email.to_string())))
+    }
+
+    fn messages() -> Vec<Message> {
+        let mut headers = BTreeMap::new();
+        headers.insert("X-Tag".to_string(), "urgent".to_string());
+        vec!(
+            message("m1", "t1", &["inbox"], 1, "Hello", 100).with_from(emailer("Ada", "ada@example.com")).with_is_unread(true),
+            message("m2", "t1", &["inbox"], 3, "Re: Hello", 200).with_from(emailer("Bob", "bob@example.com")).with_is_flagged(true),
+            message("m3", "t2", &["archive"], 2, "Report", 300).with_from(emailer("", "carol@example.com")).with_headers(headers),
+            message("m4", "t3", &["inbox", "archive"], 4, "Lunch", 50).with_to(emailer("Ada", "ada@example.com")),
+        )
+    }
+
+    fn ids(args: &str) -> Result<Vec<String>,MethodError> {
+        let messages = messages();
+        let q = MessageQuery::new(&messages);
+        let args = GetMessageListRequestArgs::from_json(&Json::from_str(args).unwrap()).unwrap();
+        q.get_message_list(&args, "s1").map(|r| r.message_ids)
+    }
+
+    fn filtered(filter: &str) -> Vec<String> {
+        ids(&format!(r#"{{"filter":{}}}"#, filter)).unwrap()
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(filtered(r#"{"inMailboxes":["inbox"]}"#), vec!("m4", "m2", "m1"));
+        assert_eq!(filtered(r#"{"inMailboxes":["inbox","archive"]}"#), vec!("m4"));
+        assert_eq!(filtered(r#"{"notInMailboxes":["archive"]}"#), vec!("m2", "m1"));
+        assert_eq!(filtered(r#"{"after":"2017-01-02T00:00:00Z","before":"2017-01-04T00:00:00Z"}"#), vec!("m2", "m3"));
+        assert_eq!(filtered(r#"{"minSize":100,"maxSize":300}"#), vec!("m2", "m1"));
+        assert_eq!(filtered(r#"{"isUnread":true}"#), vec!("m1"));
+        assert_eq!(filtered(r#"{"threadIsFlagged":true}"#), vec!("m2", "m1"));
+        assert_eq!(filtered(r#"{"text":"ada"}"#), vec!("m4", "m1"));
+        assert_eq!(filtered(r#"{"from":"ada"}"#), vec!("m1"));
+        assert_eq!(filtered(r#"{"subject":"hello"}"#), vec!("m2", "m1"));
+        assert_eq!(filtered(r#"{"header":["x-tag"]}"#), vec!("m3"));
+        assert_eq!(filtered(r#"{"header":["X-Tag","urg"]}"#), vec!("m3"));
+        assert_eq!(filtered(r#"{"header":["X-Tag","calm"]}"#), Vec::<String>::new());
+        assert_eq!(filtered(r#"{"operator":"NOT","conditions":[{"inMailboxes":["inbox"]}]}"#), vec!("m3"));
+        assert_eq!(filtered(r#"{"operator":"OR","conditions":[{"isUnread":true},{"isFlagged":true}]}"#), vec!("m2", "m1"));
+        assert!(ids(r#"{"filter":{"header":[]}}"#).is_err());
+        assert!(ids(r#"{"filter":{"operator":"NAND","conditions":[]}}"#).is_err());
+    }
+
+    #[test]
+    fn sorting() {
+        assert_eq!(ids(r#"{"sort":["subject"]}"#).unwrap(), vec!("m1", "m4", "m2", "m3"));
+        assert_eq!(ids(r#"{"sort":["size desc"]}"#).unwrap(), vec!("m3", "m2", "m1", "m4"));
+        // the sender's name, or the email if there isn't one
+        assert_eq!(ids(r#"{"sort":["from"]}"#).unwrap(), vec!("m4", "m1", "m2", "m3"));
+        assert_eq!(ids(r#"{"sort":["threadIsFlagged desc","date"]}"#).unwrap(), vec!("m1", "m2", "m3", "m4"));
+        assert_eq!(ids(r#"{"sort":["location"]}"#), Err(MethodError::UnsupportedSort));
+    }
+
+    #[test]
+    fn collapsed_threads() {
+        let messages = messages();
+        let q = MessageQuery::new(&messages);
+        let args = GetMessageListRequestArgs::from_json(&Json::from_str(r#"{"collapseThreads":true,"anchor":"m2","limit":2}"#).unwrap()).unwrap();
+        let r = q.get_message_list(&args, "s1").unwrap();
+        assert_eq!(r.total, 3);
+        assert_eq!(r.position, 1);
+        assert_eq!(r.message_ids, vec!("m2", "m3"));
+        assert_eq!(r.thread_ids, vec!("t1", "t2"));
+
+        // m1 isn't in the collapsed list
+        let args = GetMessageListRequestArgs::from_json(&Json::from_str(r#"{"collapseThreads":true,"anchor":"m1"}"#).unwrap()).unwrap();
+        assert_eq!(q.get_message_list(&args, "s1"), Err(MethodError::AnchorNotFound));
+    }
+
+    fn items(ids: &[&str]) -> Vec<(String,String)> {
+        ids.iter().map(|id| (id.to_string(), format!("t{}", id))).collect()
+    }
+
+    fn updates(old: &[&str], new: &[&str], args: &str) -> Result<GetMessageListUpdatesResponseArgs,MethodError> {
+        let args = GetMessageListUpdatesRequestArgs::from_json(&Json::from_str(args).unwrap()).unwrap();
+        get_message_list_updates(&items(old), &items(new), &args, "s2")
+    }
+
+    // apply the removals then the additions, as a client would
+    fn replay(old: &[&str], r: &GetMessageListUpdatesResponseArgs) -> Vec<String> {
+        let mut list: Vec<String> = old.iter().map(|s| s.to_string()).filter(|m| !r.removed.iter().any(|x| x.message_id == *m)).collect();
+        for a in r.added.iter() {
+            list.insert(a.index as usize, a.message_id.clone());
+        }
+        list
+    }
+
+    #[test]
+    fn list_updates() {
+        let old = ["m1", "m2", "m3", "m4"];
+        let new = ["m2", "m5", "m3", "m1"];
+        let r = updates(&old, &new, r#"{"sinceState":"s1"}"#).unwrap();
+        assert_eq!(replay(&old, &r), new.to_vec());
+        assert_eq!(r.removed.iter().map(|x| x.message_id.as_str()).collect::<Vec<_>>(), vec!("m1", "m4"));
+        assert_eq!(r.added.iter().map(|x| (x.message_id.as_str(), x.index)).collect::<Vec<_>>(), vec!(("m5", 1), ("m1", 3)));
+        assert_eq!(r.added[0].thread_id, "tm5");
+        assert_eq!((r.old_state.as_str(), r.new_state.as_str(), r.total), ("s1", "s2", 4));
+
+        let r = updates(&old, &old, r#"{"sinceState":"s1"}"#).unwrap();
+        assert!(r.added.is_empty() && r.removed.is_empty());
+    }
+
+    #[test]
+    fn list_updates_up_to_message() {
+        let old = ["m1", "m2", "m3", "m4"];
+        let new = ["m2", "m5", "m3", "m1"];
+        // only the changes up to m5 in the new list, and as far as the old
+        // list goes since m5 isn't in it
+        let r = updates(&old, &new, r#"{"sinceState":"s1","uptoMessageId":"m5"}"#).unwrap();
+        assert_eq!(r.removed.iter().map(|x| x.message_id.as_str()).collect::<Vec<_>>(), vec!("m1", "m3", "m4"));
+        assert_eq!(r.added.iter().map(|x| (x.message_id.as_str(), x.index)).collect::<Vec<_>>(), vec!(("m5", 1)));
+        assert_eq!(r.upto_message_id, Present("m5".to_string()));
+
+        let r = updates(&old, &new, r#"{"sinceState":"s1","uptoMessageId":"m2"}"#).unwrap();
+        assert_eq!(r.removed.iter().map(|x| x.message_id.as_str()).collect::<Vec<_>>(), vec!("m1"));
+        assert!(r.added.is_empty());
+    }
+
+    #[test]
+    fn list_updates_max_changes() {
+        let old = ["m1", "m2", "m3", "m4"];
+        let new = ["m2", "m5", "m3", "m1"];
+        assert_eq!(updates(&old, &new, r#"{"sinceState":"s1","maxChanges":3}"#), Err(MethodError::TooManyChanges));
+        assert!(updates(&old, &new, r#"{"sinceState":"s1","maxChanges":4}"#).is_ok());
+    }
+}
@@ -2,6 +2,9 @@ use std::collections::BTreeMap;
 use rustc_serialize::json::{Json,ToJson};
 
 use parse::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
+use method::{MethodError, ResponseBatch};
 
 make_method_args_type!(ReportMessagesRequestArgs, "ReportMessagesRequestArgs",
     account_id:  Presence<String> => "accountId",
@@ -8,6 +8,8 @@ use rustc_serialize::json::{Json,ToJson};
 use parse::*;
 use parse::Presence::*;
 use record::Record;
+use reference;
+use reference::{ResultReference, ResolveReferences};
 
 use calendar::Calendar;
 use calendar_event::CalendarEvent;
@@ -15,11 +17,15 @@ use contact::Contact;
 use contact_group::ContactGroup;
 use mailbox::Mailbox;
 use message::Message;
+use thread::Thread;
 
 use message_list::*;
+use calendar_event_list::*;
+use contact_list::*;
 use message_import::*;
 use message_copy::*;
 use message_report::*;
+use search_snippet::*;
 
 use self::RequestMethod::*;
 use self::ResponseMethod::*;
@@ -71,10 +77,154 @@ make_record_method_args_type!(SetResponseArgs, "SetResponseArgs",
 );
 
 
-make_prop_type!(SetError, "SetError",
-    typ:         String         => "type",
-    description: Option<String> => "description"
-);
+#[derive(Clone, PartialEq, Debug)]
+pub enum SetErrorType {
+    Forbidden,
+    OverQuota,
+    TooLarge,
+    RateLimit,
+    NotFound,
+    InvalidPatch,
+    WillDestroy,
+    InvalidProperties(Vec<String>),
+    Singleton,
+    AlreadyExists,
+    StateMismatch,
+    BlobNotFound,
+    MailboxHasChild,
+    MailboxHasMessage,
+    Other(String), // types we don't know about, kept so they can be passed on
+}
+
+impl SetErrorType {
+    pub fn name(&self) -> &str {
+        match *self {
+            SetErrorType::Forbidden            => "forbidden",
+            SetErrorType::OverQuota            => "overQuota",
+            SetErrorType::TooLarge             => "tooLarge",
+            SetErrorType::RateLimit            => "rateLimit",
+            SetErrorType::NotFound             => "notFound",
+            SetErrorType::InvalidPatch         => "invalidPatch",
+            SetErrorType::WillDestroy          => "willDestroy",
+            SetErrorType::InvalidProperties(_) => "invalidProperties",
+            SetErrorType::Singleton            => "singleton",
+            SetErrorType::AlreadyExists        => "alreadyExists",
+            SetErrorType::StateMismatch        => "stateMismatch",
+            SetErrorType::BlobNotFound         => "blobNotFound",
+            SetErrorType::MailboxHasChild      => "mailboxHasChild",
+            SetErrorType::MailboxHasMessage    => "mailboxHasMessage",
+            SetErrorType::Other(ref t)         => t,
+        }
+    }
+
+    fn summary(&self) -> &str {
+        match *self {
+            SetErrorType::Forbidden            => "action would violate an ACL or other permission",
+            SetErrorType::OverQuota            => "action would exceed a server quota",
+            SetErrorType::TooLarge             => "action would result in an object exceeding a server size limit",
+            SetErrorType::RateLimit            => "too many objects of this type created recently",
+            SetErrorType::NotFound             => "record not found",
+            SetErrorType::InvalidPatch         => "invalid patch object",
+            SetErrorType::WillDestroy          => "record is also being destroyed",
+            SetErrorType::InvalidProperties(_) => "invalid record properties",
+            SetErrorType::Singleton            => "singleton record can't be created or destroyed",
+            SetErrorType::AlreadyExists        => "record already exists",
+            SetErrorType::StateMismatch        => "supplied state does not match current state",
+            SetErrorType::BlobNotFound         => "blob not found",
+            SetErrorType::MailboxHasChild      => "mailbox has child mailboxes",
+            SetErrorType::MailboxHasMessage    => "mailbox has messages",
+            SetErrorType::Other(_)             => "unknown error",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SetError {
+    pub typ:         SetErrorType,
+    pub description: Option<String>,
+}
+
+impl SetError {
+    pub fn new(typ: SetErrorType, description: Option<&str>) -> SetError {
+        SetError {
+            typ:         typ,
+            description: description.map(|d| d.to_string()),
+        }
+    }
+
+    pub fn invalid_properties(properties: Vec<String>) -> SetError {
+        SetError::new(SetErrorType::InvalidProperties(properties), None)
+    }
+}
+
+impl Error for SetError {
+    fn description(&self) -> &str {
+        self.typ.summary()
+    }
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self.typ {
+            SetErrorType::InvalidProperties(ref p) => format!("invalid record properties: {}", p.join(", ")),
+            SetErrorType::Other(ref t)             => format!("unknown error \"{}\"", t),
+            ref t                                  => t.summary().to_string(),
+        };
+        match self.description {
+            Some(ref d) => write!(f, "{} ({})", s, d),
+            None        => write!(f, "{}", s),
+        }
+    }
+}
+
+impl ToJson for SetError {
+    fn to_json(&self) -> Json {
+        let mut d = BTreeMap::<String,Json>::new();
+        self.typ.name().to_string().to_json_field(&mut d, "type");
+        if let SetErrorType::InvalidProperties(ref p) = self.typ {
+            p.to_json_field(&mut d, "properties");
+        }
+        if let Some(ref desc) = self.description {
+            desc.to_json_field(&mut d, "description");
+        }
+        Json::Object(d)
+    }
+}
+
+impl FromJson for SetError {
+    fn from_json(json: &Json) -> Result<SetError,ParseError> {
+        match *json {
+            Json::Object(ref o) => {
+                let typ: String = try!(FromJsonField::from_json_field(o, "type"));
+                let typ = match typ.as_ref() {
+                    "forbidden"         => SetErrorType::Forbidden,
+                    "overQuota"         => SetErrorType::OverQuota,
+                    "tooLarge"          => SetErrorType::TooLarge,
+                    "rateLimit"         => SetErrorType::RateLimit,
+                    "notFound"          => SetErrorType::NotFound,
+                    "invalidPatch"      => SetErrorType::InvalidPatch,
+                    "willDestroy"       => SetErrorType::WillDestroy,
+                    "invalidProperties" => {
+                        let p: Option<Vec<String>> = try!(FromJsonField::from_json_field(o, "properties"));
+                        SetErrorType::InvalidProperties(p.unwrap_or_default())
+                    },
+                    "singleton"         => SetErrorType::Singleton,
+                    "alreadyExists"     => SetErrorType::AlreadyExists,
+                    "stateMismatch"     => SetErrorType::StateMismatch,
+                    "blobNotFound"      => SetErrorType::BlobNotFound,
+                    "mailboxHasChild"   => SetErrorType::MailboxHasChild,
+                    "mailboxHasMessage" => SetErrorType::MailboxHasMessage,
+                    _                   => SetErrorType::Other(typ.clone()),
+                };
+                Ok(SetError {
+                    typ:         typ,
+                    description: try!(FromJsonField::from_json_field(o, "description")),
+                })
+            },
+            _ => Err(ParseError::InvalidJsonType("SetError".to_string())),
+        }
+    }
+}
 
 
 #[derive(Clone, PartialEq, Debug)]
@@ -117,6 +267,7 @@ pub enum MethodError {
     AccountNoContacts,
     AccountNoCalendars,
     UnsupportedSort,
+    InvalidResultReference(Presence<ErrorDescription>),
     InternalError(Presence<ErrorDescription>), // XXX not in spec
 }
 
@@ -142,6 +293,7 @@ impl Error for MethodError {
             MethodError::AccountNoContacts      => "account does not contain any contact data",
             MethodError::AccountNoCalendars     => "account does not contain any calendar data",
             MethodError::UnsupportedSort        => "unable to sort on requested properties",
+            MethodError::InvalidResultReference(_) => "result reference could not be resolved",
             MethodError::InternalError(_)       => "internal error",
         }
     }
@@ -152,6 +304,7 @@ impl fmt::Display for MethodError {
         write!(f, "{}", match *self {
             MethodError::UnknownMethod(Present(ref d)) => format!("unknown method ({})", d.0),
             MethodError::InvalidArguments(Present(ref d)) => format!("invalid arguments for method ({})", d.0),
+            MethodError::InvalidResultReference(Present(ref d)) => format!("result reference could not be resolved ({})", d.0),
             MethodError::InternalError(Present(ref d)) => format!("internal error ({})", d.0),
             ref e => e.description().to_string(),
         })
@@ -182,12 +335,14 @@ impl ToJson for MethodError {
             MethodError::AccountNoContacts      => "accountNoContacts",
             MethodError::AccountNoCalendars     => "accountNoCalendars",
             MethodError::UnsupportedSort        => "unsupportedSort",
+            MethodError::InvalidResultReference(_) => "invalidResultReference",
             MethodError::InternalError(_)       => "internalError",
         }.to_string().to_json_field(&mut d, "type");
 
         match *self {
             MethodError::UnknownMethod(ref desc)    |
             MethodError::InvalidArguments(ref desc) |
+            MethodError::InvalidResultReference(ref desc) |
             MethodError::InternalError(ref desc) =>
                 desc.to_json_field(&mut d, "description"),
             _ => (),
@@ -222,6 +377,7 @@ impl FromJson for MethodError {
                     "accountNoContacts"      => Ok(MethodError::AccountNoContacts),
                     "accountNoCalendars"     => Ok(MethodError::AccountNoCalendars),
                     "unsupportedSort"        => Ok(MethodError::UnsupportedSort),
+                    "invalidResultReference" => Ok(MethodError::InvalidResultReference(try!(FromJsonField::from_json_field(o, "description")))),
                     "internalError"          => Ok(MethodError::InternalError(try!(FromJsonField::from_json_field(o, "description")))),
 
                     _                        => Err(ParseError::InvalidStructure("MethodError".to_string())),
@@ -232,6 +388,13 @@ impl FromJson for MethodError {
     }
 }
 
+// errors have no arguments to resolve
+impl ResolveReferences for MethodError {
+    fn resolve_references(&self, _: &ResponseBatch) -> Result<MethodError,MethodError> {
+        Ok(self.clone())
+    }
+}
+
 
 pub trait ClientId {
     fn client_id(&self) -> String;
@@ -263,12 +426,20 @@ make_methods!(RequestMethod, "RequestMethod", RequestError,
     GetMessageUpdates,       GetUpdatesRequestArgs<Message>       => "getMessageUpdates",
     SetMessages,             SetRequestArgs<Message>              => "setMessages",
 
+    GetThreads,              GetRequestArgs<Thread>               => "getThreads",
+    GetThreadUpdates,        GetUpdatesRequestArgs<Thread>        => "getThreadUpdates",
+
     ImportMessages,          ImportMessagesRequestArgs            => "importMessages",
     CopyMessages,            CopyMessagesRequestArgs              => "copyMessages",
     ReportMessages,          ReportMessagesRequestArgs            => "reportMessages",
 
     GetMessageList,          GetMessageListRequestArgs            => "getMessageList",
     GetMessageListUpdates,   GetMessageListUpdatesRequestArgs     => "getMessageListUpdates",
+    GetSearchSnippets,       GetSearchSnippetsRequestArgs         => "getSearchSnippets",
+
+    GetCalendarEventList,    GetCalendarEventListRequestArgs      => "getCalendarEventList",
+
+    GetContactList,          GetContactListRequestArgs            => "getContactList",
 
     RequestError,            MethodError                          => "error"
 );
@@ -298,12 +469,20 @@ make_methods!(ResponseMethod, "ResponseMethod", ResponseError,
     MessageUpdates,       GetUpdatesResponseArgs<Message>       => "messageUpdates",
     MessagesSet,          SetResponseArgs<Message>              => "messagesSet",
 
+    Threads,              GetResponseArgs<Thread>               => "threads",
+    ThreadUpdates,        GetUpdatesResponseArgs<Thread>        => "threadUpdates",
+
     MessagesImported,     ImportMessagesResponseArgs            => "messagesImported",
     MessagesCopied,       CopyMessagesResponseArgs              => "messagesCopied",
     MessagesReported,     ReportMessagesResponseArgs            => "messagesReported",
 
-    MessageList,          GetMessageListUpdatesRequestArgs      => "messageList",
+    MessageList,          GetMessageListResponseArgs            => "messageList",
     MessageListUpdates,   GetMessageListUpdatesResponseArgs     => "messageListUpdates",
+    SearchSnippets,       GetSearchSnippetsResponseArgs         => "searchSnippets",
+
+    CalendarEventList,    GetCalendarEventListResponseArgs      => "calendarEventList",
+
+    ContactList,          GetContactListResponseArgs            => "contactList",
 
     ResponseError,        MethodError                           => "error"
 );
@@ -311,3 +490,61 @@ make_methods!(ResponseMethod, "ResponseMethod", ResponseError,
 
 make_batch!(RequestBatch,  RequestMethod);
 make_batch!(ResponseBatch, ResponseMethod);
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::{Json,ToJson};
+    use parse::FromJson;
+    use super::*;
+
+    #[test]
+    fn set_error_round_trip() {
+        let errors = vec!(
+            SetError::new(SetErrorType::NotFound, None),
+            SetError::new(SetErrorType::MailboxHasChild, Some("has children")),
+            SetError::invalid_properties(vec!("name".to_string(), "parentId".to_string())),
+            SetError::new(SetErrorType::Other("vendorThing".to_string()), Some("what")),
+        );
+        for e in errors.iter() {
+            assert_eq!(SetError::from_json(&e.to_json()).as_ref(), Ok(e));
+        }
+
+        assert_eq!(errors[2].to_json(), Json::from_str(r#"{"type":"invalidProperties","properties":["name","parentId"]}"#).unwrap());
+        assert_eq!(errors[3].to_json(), Json::from_str(r#"{"type":"vendorThing","description":"what"}"#).unwrap());
+
+        // a server may leave the property list out
+        let e = SetError::from_json(&Json::from_str(r#"{"type":"invalidProperties"}"#).unwrap()).unwrap();
+        assert_eq!(e, SetError::invalid_properties(vec!()));
+        assert!(SetError::from_json(&Json::from_str(r#"{"description":"x"}"#).unwrap()).is_err());
+    }
+
+    #[test]
+    fn strict_args_report_unknown_properties() {
+        let json = Json::from_str(r##"{
+            "#destroy": {"resultOf":"c1","name":"mailboxes","path":"/list/*/id"},
+            "#bogus": {"resultOf":"c1","name":"mailboxes","path":"/list"},
+            "bogus": 1,
+            "create": {"k1": {"name": "Inbox", "x-colour": "red"}}
+        }"##).unwrap();
+
+        // normally unknown properties are ignored or kept
+        let args = SetRequestArgs::<Mailbox>::from_json(&json).unwrap();
+        assert!(args._references.contains_key("destroy"));
+        assert_eq!(args.create.as_option().unwrap()["k1"].extra().len(), 1);
+
+        match from_json_strict::<SetRequestArgs<Mailbox>>(&json) {
+            Err(ParseError::Multiple(v)) => {
+                assert_eq!(v.len(), 3);
+                for f in ["#bogus", "bogus", "x-colour"].iter() {
+                    assert!(v.contains(&ParseError::UnknownField(f.to_string())), "{} not reported", f);
+                }
+            },
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // a single error isn't wrapped
+        let json = Json::from_str(r#"{"update": {"m1": {"sortOrder": "x"}}}"#).unwrap();
+        assert_eq!(from_json_strict::<SetRequestArgs<Mailbox>>(&json), Err(ParseError::InvalidJsonType("u64".to_string())));
+    }
+}
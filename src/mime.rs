@@ -0,0 +1,816 @@
+use std::collections::BTreeMap;
+use std::default::Default;
+use chrono::{DateTime,UTC};
+
+use message::{Message, Emailer, Attachment};
+use search_snippet::{html_to_text, escape_html};
+use types::Date;
+
+// RFC 5322/MIME message parsing. everything here is lenient: real-world mail
+// is full of broken structure, so we do the best we can and never fail.
+
+
+// max length of the message preview, in chars
+const PREVIEW_LENGTH: usize = 256;
+
+// how deep we'll go into nested multiparts and attached messages. anything
+// deeper is left as a single opaque part, so hostile nesting can't exhaust
+// the stack.
+const MAX_DEPTH: usize = 32;
+
+
+// a single MIME part. for multiparts, the subparts are in parts; for
+// everything else, body holds the content with the transfer encoding removed.
+// a multipart past MAX_DEPTH (or without a boundary) has its raw content in
+// body instead, and is treated as a leaf.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MimePart {
+    pub headers:            Vec<(String,String)>,
+    pub content_type:       String,
+    pub params:             BTreeMap<String,String>,
+    pub disposition:        Option<String>,
+    pub disposition_params: BTreeMap<String,String>,
+    pub body:               Vec<u8>,
+    pub parts:              Vec<MimePart>,
+}
+
+impl MimePart {
+    pub fn parse(raw: &[u8]) -> MimePart {
+        MimePart::parse_with_default(raw, "text/plain", 0)
+    }
+
+    fn parse_with_default(raw: &[u8], default_type: &str, depth: usize) -> MimePart {
+        let (head, body) = split_header_body(raw);
+
+        let mut part = MimePart::default();
+        part.headers = parse_headers(head);
+
+        let (ctype, params) = match part.header("Content-Type") {
+            Some(v) => parse_params(v),
+            None    => (default_type.to_string(), BTreeMap::new()),
+        };
+        part.content_type = match ctype.contains('/') {
+            true => ctype,
+            _    => default_type.to_string(),
+        };
+        part.params = params;
+
+        if let Some(v) = part.header("Content-Disposition").map(|v| v.to_string()) {
+            let (disp, params) = parse_params(&v);
+            part.disposition = Some(disp);
+            part.disposition_params = params;
+        }
+
+        let boundary = part.params.get("boundary").cloned();
+        match (part.content_type.starts_with("multipart/"), boundary) {
+            (true, Some(ref b)) if depth < MAX_DEPTH => {
+                let child_default = match part.content_type.as_ref() {
+                    "multipart/digest" => "message/rfc822",
+                    _                  => "text/plain",
+                };
+                part.parts = split_multipart(body, b).into_iter()
+                    .map(|p| MimePart::parse_with_default(p, child_default, depth + 1))
+                    .collect();
+            },
+            _ => {
+                let encoding = part.header("Content-Transfer-Encoding").map(|e| e.trim().to_lowercase());
+                part.body = match encoding.as_ref().map(|e| e.as_ref()) {
+                    Some("base64")           => decode_base64(body),
+                    Some("quoted-printable") => decode_quoted_printable(body),
+                    _                        => body.to_vec(),
+                };
+            },
+        }
+
+        part
+    }
+
+    // first value of the named header, case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter().find(|&&(ref k, _)| k.to_lowercase() == name).map(|&(_, ref v)| v.as_ref())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.content_type.starts_with("multipart/") && self.body.is_empty()
+    }
+
+    // the body converted to a string according to its charset
+    pub fn text(&self) -> String {
+        let charset = match self.params.get("charset") {
+            Some(c) => c.clone(),
+            None    => "us-ascii".to_string(),
+        };
+        decode_charset(&self.body, &charset)
+    }
+
+    pub fn filename(&self) -> Option<String> {
+        match self.disposition_params.get("filename") {
+            Some(f) => Some(f.clone()),
+            None    => self.params.get("name").cloned(),
+        }
+    }
+
+    pub fn content_id(&self) -> Option<String> {
+        self.header("Content-ID").map(|c| c.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+    }
+
+    // find a subpart by IMAP-style part number, eg "2.1"
+    pub fn part(&self, id: &str) -> Option<&MimePart> {
+        let mut part = self;
+        for n in id.split('.') {
+            let n = match n.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _              => return None,
+            };
+            part = match (part.is_multipart(), n) {
+                (true, n) => match part.parts.get(n-1) {
+                    Some(p) => p,
+                    None    => return None,
+                },
+                (false, 1) => part,
+                _ => return None,
+            };
+        }
+        Some(part)
+    }
+}
+
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).find(|&i| &haystack[i..i+needle.len()] == needle)
+}
+
+fn split_header_body(raw: &[u8]) -> (&[u8], &[u8]) {
+    if raw.starts_with(b"\r\n") {
+        return (&[], &raw[2..]);
+    }
+    if raw.starts_with(b"\n") {
+        return (&[], &raw[1..]);
+    }
+    let crlf = find_bytes(raw, b"\r\n\r\n").map(|i| (i, i+4));
+    let lf = find_bytes(raw, b"\n\n").map(|i| (i, i+2));
+    let split = match (crlf, lf) {
+        (Some(c), Some(l)) => if c.0 < l.0 { Some(c) } else { Some(l) },
+        (c, l)             => c.or(l),
+    };
+    match split {
+        Some((h, b)) => (&raw[..h], &raw[b..]),
+        None         => (raw, &[]),
+    }
+}
+
+// raw header bytes are usually ASCII, sometimes UTF-8, and sometimes
+// something else entirely
+fn bytes_to_string(b: &[u8]) -> String {
+    match String::from_utf8(b.to_vec()) {
+        Ok(s)  => s,
+        Err(_) => decode_charset(b, "iso-8859-1"),
+    }
+}
+
+// split a header block into unfolded (name, value) pairs
+pub fn parse_headers(head: &[u8]) -> Vec<(String,String)> {
+    let text = bytes_to_string(head);
+    let mut headers: Vec<(String,String)> = vec!();
+    for line in text.split('\n').map(|l| l.trim_end_matches('\r')) {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(&mut (_, ref mut v)) = headers.last_mut() {
+                v.push(' ');
+                v.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_string(), line[i+1..].trim().to_string()));
+        }
+    }
+    headers
+}
+
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delim = format!("--{}", boundary).into_bytes();
+    let mut parts = vec!();
+    let mut start: Option<usize> = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = match find_bytes(&body[pos..], b"\n") {
+            Some(i) => pos + i + 1,
+            None    => body.len(),
+        };
+        let line = &body[pos..end];
+        if line.starts_with(&delim) {
+            // the line break before the delimiter belongs to the delimiter
+            if let Some(s) = start {
+                let mut e = pos;
+                if e > s && body[e-1] == b'\n' { e -= 1 }
+                if e > s && body[e-1] == b'\r' { e -= 1 }
+                parts.push(&body[s..e]);
+            }
+            if line[delim.len()..].starts_with(b"--") {
+                return parts;
+            }
+            start = Some(end);
+        }
+        pos = end;
+    }
+    // no closing delimiter; take what we have
+    if let Some(s) = start {
+        parts.push(&body[s..]);
+    }
+    parts
+}
+
+// split a structured header value into its main value and its parameters,
+// handling quoting and RFC 2231 continuations and charsets
+pub fn parse_params(value: &str) -> (String,BTreeMap<String,String>) {
+    let mut pieces: Vec<String> = vec!();
+    let mut cur = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped     => { cur.push(c); escaped = false },
+            '\\' if in_quote => escaped = true,
+            '"'              => { in_quote = !in_quote; cur.push(c) },
+            ';' if !in_quote => pieces.push(::std::mem::replace(&mut cur, String::new())),
+            _                => cur.push(c),
+        }
+    }
+    pieces.push(cur);
+
+    let main = pieces[0].trim().to_lowercase();
+
+    // collect everything, then put RFC 2231 sections back together
+    let mut simple = BTreeMap::<String,String>::new();
+    let mut sections = BTreeMap::<String,BTreeMap<u32,(bool,String)>>::new();
+    for p in pieces[1..].iter() {
+        let (k, v) = match p.find('=') {
+            Some(i) => (p[..i].trim().to_lowercase(), p[i+1..].trim()),
+            None    => continue,
+        };
+        let v = match v.starts_with('"') && v.ends_with('"') && v.len() >= 2 {
+            true => v[1..v.len()-1].to_string(),
+            _    => v.to_string(),
+        };
+        let (name, encoded) = match k.ends_with('*') {
+            true => (k[..k.len()-1].to_string(), true),
+            _    => (k.clone(), false),
+        };
+        match name.rfind('*') {
+            Some(i) => match name[i+1..].parse::<u32>() {
+                Ok(n) => { sections.entry(name[..i].to_string()).or_insert(BTreeMap::new()).insert(n, (encoded, v)); },
+                Err(_) => { simple.insert(name, v); },
+            },
+            None if encoded => { sections.entry(name).or_insert(BTreeMap::new()).insert(0, (true, v)); },
+            None => { simple.insert(name, v); },
+        }
+    }
+
+    for (name, parts) in sections.into_iter() {
+        let mut charset = None;
+        let mut bytes = vec!();
+        for (n, (encoded, v)) in parts.into_iter() {
+            match encoded {
+                true => {
+                    let mut v: &str = &v;
+                    // the first section carries charset'language'
+                    if n == 0 {
+                        let bits: Vec<&str> = v.splitn(3, '\'').collect();
+                        if bits.len() == 3 {
+                            charset = Some(bits[0].to_string());
+                            v = bits[2];
+                        }
+                    }
+                    bytes.extend(decode_percent(v));
+                },
+                _ => bytes.extend(v.into_bytes()),
+            }
+        }
+        let value = match charset {
+            Some(ref c) if !c.is_empty() => decode_charset(&bytes, c),
+            _                            => bytes_to_string(&bytes),
+        };
+        simple.insert(name, value);
+    }
+
+    (main, simple)
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _           => None,
+    }
+}
+
+fn decode_percent(s: &str) -> Vec<u8> {
+    let b = s.as_bytes();
+    let mut out = vec!();
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' && i + 2 < b.len() {
+            if let (Some(h), Some(l)) = (hex_value(b[i+1]), hex_value(b[i+2])) {
+                out.push(h << 4 | l);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(b[i]);
+        i += 1;
+    }
+    out
+}
+
+pub fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut out = vec!();
+    let mut buf: u32 = 0;
+    let mut bits = 0;
+    for &c in data.iter() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+'        => 62,
+            b'/'        => 63,
+            b'='        => break,
+            _           => continue,
+        };
+        buf = buf << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+    out
+}
+
+fn decode_qp(data: &[u8], underscore_is_space: bool) -> Vec<u8> {
+    let mut out = vec!();
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                // soft line break
+                if data[i+1..].starts_with(b"\r\n") {
+                    i += 3;
+                    continue;
+                }
+                if data[i+1..].starts_with(b"\n") {
+                    i += 2;
+                    continue;
+                }
+                if i + 2 < data.len() {
+                    if let (Some(h), Some(l)) = (hex_value(data[i+1]), hex_value(data[i+2])) {
+                        out.push(h << 4 | l);
+                        i += 3;
+                        continue;
+                    }
+                }
+                out.push(b'=');
+            },
+            b'_' if underscore_is_space => out.push(b' '),
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+pub fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    decode_qp(data, false)
+}
+
+// the C1 range of windows-1252, which is where it differs from iso-8859-1
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{81}',   '\u{201a}', '\u{192}',  '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}',  '\u{2030}', '\u{160}',  '\u{2039}', '\u{152}',  '\u{8d}',   '\u{17d}',  '\u{8f}',
+    '\u{90}',   '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}',  '\u{2122}', '\u{161}',  '\u{203a}', '\u{153}',  '\u{9d}',   '\u{17e}',  '\u{178}',
+];
+
+// convert text in the named charset to a string. we only know UTF-8 (and so
+// ASCII), ISO-8859-1 and Windows-1252. anything else is decoded as UTF-8
+// with invalid sequences replaced by U+FFFD, so text in other legacy
+// charsets (ISO-8859-2, KOI8-R, Shift_JIS, ...) comes out damaged.
+pub fn decode_charset(data: &[u8], charset: &str) -> String {
+    match charset.trim().to_lowercase().as_ref() {
+        "iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "l1" =>
+            data.iter().map(|&b| b as char).collect(),
+        "windows-1252" | "cp1252" =>
+            data.iter().map(|&b| match b {
+                0x80..=0x9f => WINDOWS_1252[(b - 0x80) as usize],
+                b           => b as char,
+            }).collect(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+// decode a single RFC 2047 encoded word, if that's what this is
+fn decode_encoded_word(word: &str) -> Option<String> {
+    if !word.starts_with("=?") || !word.ends_with("?=") || word.len() < 8 {
+        return None;
+    }
+    let inner = &word[2..word.len()-2];
+    let bits: Vec<&str> = inner.splitn(3, '?').collect();
+    if bits.len() != 3 {
+        return None;
+    }
+    // charset may carry a language after a *
+    let charset = bits[0].split('*').next().unwrap_or("");
+    let data = match bits[1].to_lowercase().as_ref() {
+        "b" => decode_base64(bits[2].as_bytes()),
+        "q" => decode_qp(bits[2].as_bytes(), true),
+        _   => return None,
+    };
+    Some(decode_charset(&data, charset))
+}
+
+// decode all the encoded words in an unstructured header value. whitespace
+// between adjacent encoded words is dropped.
+pub fn decode_encoded_words(s: &str) -> String {
+    let mut out = String::new();
+    let mut pending_space = String::new();
+    let mut last_was_encoded = false;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let ws = rest.len() - rest.trim_start().len();
+        if ws > 0 {
+            pending_space.push_str(&rest[..ws]);
+            rest = &rest[ws..];
+            continue;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..end];
+        match decode_encoded_word(word) {
+            Some(d) => {
+                if !last_was_encoded {
+                    out.push_str(&pending_space);
+                }
+                out.push_str(&d);
+                last_was_encoded = true;
+            },
+            None => {
+                out.push_str(&pending_space);
+                out.push_str(word);
+                last_was_encoded = false;
+            },
+        }
+        pending_space.clear();
+        rest = &rest[end..];
+    }
+    out
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+enum AddressToken {
+    Word(String),
+    Quoted(String),
+    Comment(String),
+    Angle(String),
+    Special(char),
+}
+
+fn tokenize_addresses(s: &str) -> Vec<AddressToken> {
+    let mut tokens = vec!();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' | '\n' => (),
+            '"' => {
+                let mut q = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => if let Some(n) = chars.next() { q.push(n) },
+                        '"'  => break,
+                        c    => q.push(c),
+                    }
+                }
+                tokens.push(AddressToken::Quoted(q));
+            },
+            '(' => {
+                let mut depth = 1;
+                let mut q = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => if let Some(n) = chars.next() { q.push(n) },
+                        '('  => { depth += 1; q.push(c) },
+                        ')'  => { depth -= 1; if depth == 0 { break }; q.push(c) },
+                        c    => q.push(c),
+                    }
+                }
+                tokens.push(AddressToken::Comment(q));
+            },
+            '<' => {
+                let mut a = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '>' => break,
+                        c if c.is_whitespace() => (),
+                        c   => a.push(c),
+                    }
+                }
+                tokens.push(AddressToken::Angle(a));
+            },
+            ',' | ';' | ':' => tokens.push(AddressToken::Special(c)),
+            c => {
+                let mut w = String::new();
+                w.push(c);
+                while let Some(&n) = chars.peek() {
+                    match n {
+                        ' ' | '\t' | '\r' | '\n' | '"' | '(' | '<' | ',' | ';' | ':' => break,
+                        n => { w.push(n); chars.next(); },
+                    }
+                }
+                tokens.push(AddressToken::Word(w));
+            },
+        }
+    }
+    tokens
+}
+
+// parse an address list header into Emailers. group names are dropped and
+// the group members returned inline.
+pub fn parse_addresses(s: &str) -> Vec<Emailer> {
+    let mut out = vec!();
+    let mut phrase: Vec<String> = vec!();
+    let mut comment: Option<String> = None;
+    let mut addr: Option<String> = None;
+
+    {
+        let mut flush = |phrase: &mut Vec<String>, comment: &mut Option<String>, addr: &mut Option<String>| {
+            let mut e = Emailer::default();
+            match addr.take() {
+                Some(a) => {
+                    e.email = a;
+                    e.name = decode_encoded_words(&phrase.join(" "));
+                },
+                None => {
+                    e.email = phrase.join("");
+                    e.name = match *comment {
+                        Some(ref c) => decode_encoded_words(c),
+                        None        => "".to_string(),
+                    };
+                },
+            }
+            if e.name.is_empty() {
+                if let Some(ref c) = *comment {
+                    e.name = decode_encoded_words(c);
+                }
+            }
+            phrase.clear();
+            *comment = None;
+            if !e.email.is_empty() {
+                out.push(e);
+            }
+        };
+
+        for t in tokenize_addresses(s).into_iter() {
+            match t {
+                AddressToken::Word(w) | AddressToken::Quoted(w) => phrase.push(w),
+                AddressToken::Comment(c) => comment = Some(c),
+                AddressToken::Angle(a) => addr = Some(a),
+                AddressToken::Special(':') => { phrase.clear(); comment = None },
+                AddressToken::Special(_) => flush(&mut phrase, &mut comment, &mut addr),
+            }
+        }
+        flush(&mut phrase, &mut comment, &mut addr);
+    }
+
+    out
+}
+
+// parse an RFC 5322 date, tolerating trailing comments like "(PST)"
+pub fn parse_date(s: &str) -> Option<Date> {
+    let s = match s.find('(') {
+        Some(i) => s[..i].trim(),
+        None    => s.trim(),
+    };
+    match DateTime::parse_from_rfc2822(s) {
+        Ok(d)  => Some(Date(d.with_timezone(&UTC))),
+        Err(_) => None,
+    }
+}
+
+fn make_preview(text: &str) -> String {
+    let squashed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    squashed.chars().take(PREVIEW_LENGTH).collect()
+}
+
+fn text_to_html(text: &str) -> String {
+    escape_html(text).replace("\r\n", "<br>").replace("\n", "<br>")
+}
+
+
+// the bits of a message body we collect while walking the part tree
+#[derive(Default)]
+struct BodyParts {
+    text:        Option<String>,
+    html:        Option<String>,
+    attachments: Vec<Attachment>,
+    attached:    BTreeMap<String,Message>,
+}
+
+fn part_blob_id(blob_id: &str, part_id: &str) -> String {
+    format!("{}/{}", blob_id, part_id)
+}
+
+fn add_attachment(part: &MimePart, part_id: &str, blob_id: &str, depth: usize, b: &mut BodyParts) {
+    let mut a = Attachment::default();
+    a.blob_id = part_blob_id(blob_id, part_id);
+    a.typ = part.content_type.clone();
+    a.name = part.filename().unwrap_or("".to_string());
+    a.size = part.body.len() as u64;
+    a.cid = part.content_id();
+    a.is_inline = match part.disposition {
+        Some(ref d) => d == "inline",
+        None        => a.cid.is_some(),
+    };
+
+    if part.content_type == "message/rfc822" && depth < MAX_DEPTH {
+        let m = parse_message_at(&part.body, &a.blob_id, depth + 1);
+        b.attached.insert(a.blob_id.clone(), m);
+    }
+
+    b.attachments.push(a);
+}
+
+fn walk_part(part: &MimePart, part_id: &str, blob_id: &str, depth: usize, b: &mut BodyParts) {
+    if part.is_multipart() {
+        for (i, p) in part.parts.iter().enumerate() {
+            let id = match part_id.is_empty() {
+                true => format!("{}", i+1),
+                _    => format!("{}.{}", part_id, i+1),
+            };
+            walk_part(p, &id, blob_id, depth + 1, b);
+        }
+        return;
+    }
+
+    let is_attachment = part.disposition.as_ref().map(|d| d == "attachment").unwrap_or(false) || part.filename().is_some();
+    if !is_attachment {
+        match part.content_type.as_ref() {
+            "text/plain" if b.text.is_none() => { b.text = Some(part.text()); return },
+            "text/html"  if b.html.is_none() => { b.html = Some(part.text()); return },
+            _ => (),
+        }
+    }
+
+    add_attachment(part, part_id, blob_id, depth, b);
+}
+
+// build a Message from a raw RFC 5322 message. blob_id is the id of the raw
+// message; attachments get blob ids made from it and their part number.
+// only the properties that come from the message content are filled in.
+pub fn parse_message(raw: &[u8], blob_id: &str) -> Message {
+    parse_message_at(raw, blob_id, 0)
+}
+
+fn parse_message_at(raw: &[u8], blob_id: &str, depth: usize) -> Message {
+    let part = MimePart::parse_with_default(raw, "text/plain", depth);
+
+    let mut m = Message::default();
+    m.blob_id = blob_id.to_string();
+    m.size = raw.len() as u64;
+
+    for &(ref k, ref v) in part.headers.iter() {
+        let v = decode_encoded_words(v);
+        let mut merged = false;
+        if let Some(existing) = m.headers.get_mut(k) {
+            existing.push('\n');
+            existing.push_str(&v);
+            merged = true;
+        }
+        if !merged {
+            m.headers.insert(k.clone(), v);
+        }
+    }
+
+    let addresses = |name: &str| part.header(name).map(parse_addresses);
+    m.from = addresses("From");
+    m.to = addresses("To");
+    m.cc = addresses("Cc");
+    m.bcc = addresses("Bcc");
+    m.reply_to = addresses("Reply-To");
+    m.sender = addresses("Sender").and_then(|mut v| match v.is_empty() {
+        true => None,
+        _    => Some(v.remove(0)),
+    });
+
+    m.subject = part.header("Subject").map(decode_encoded_words).unwrap_or("".to_string());
+    if let Some(d) = part.header("Date").and_then(parse_date) {
+        m.date = d;
+    }
+
+    let mut b = BodyParts::default();
+    match part.is_multipart() {
+        true => walk_part(&part, "", blob_id, depth, &mut b),
+        _    => walk_part(&part, "1", blob_id, depth, &mut b),
+    }
+
+    // fill in whichever body is missing from the other
+    if b.text.is_none() {
+        b.text = b.html.as_ref().map(|h| html_to_text(h));
+    }
+    if b.html.is_none() {
+        b.html = b.text.as_ref().map(|t| text_to_html(t));
+    }
+
+    m.preview = b.text.as_ref().map(|t| make_preview(t)).unwrap_or("".to_string());
+    m.text_body = b.text;
+    m.html_body = b.html;
+    m.has_attachment = b.attachments.iter().any(|a| !a.is_inline);
+    m.attachments = match b.attachments.is_empty() {
+        true => None,
+        _    => Some(b.attachments),
+    };
+    m.attached_messages = match b.attached.is_empty() {
+        true => None,
+        _    => Some(b.attached),
+    };
+
+    m
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested_multiparts(n: usize) -> Vec<u8> {
+        let mut raw = "\r\ntext".to_string();
+        for i in 0..n {
+            raw = format!("Content-Type: multipart/mixed; boundary=b{}\r\n\r\n--b{}\r\n{}\r\n--b{}--\r\n", i, i, raw, i);
+        }
+        raw.into_bytes()
+    }
+
+    fn nested_messages(n: usize) -> Vec<u8> {
+        let mut raw = "Subject: inner\r\n\r\ntext".to_string();
+        for _ in 0..n {
+            raw = format!("Subject: outer\r\nContent-Type: message/rfc822\r\n\r\n{}", raw);
+        }
+        raw.into_bytes()
+    }
+
+    #[test]
+    fn multipart_structure() {
+        let raw = b"Subject: =?utf-8?q?caf=C3=A9?=\r\nContent-Type: multipart/mixed; boundary=x\r\n\r\n\
+                    --x\r\nContent-Type: text/plain; charset=iso-8859-1\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\nna=EFve\r\n\
+                    --x\r\nContent-Type: application/pdf; name=\"a.pdf\"\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERg==\r\n\
+                    --x--\r\n";
+        let m = parse_message(raw, "b1");
+        assert_eq!(m.subject, "café");
+        assert_eq!(m.text_body, Some("naïve".to_string()));
+        let a = m.attachments.unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].blob_id, "b1/2");
+        assert_eq!(a[0].name, "a.pdf");
+        assert_eq!(a[0].size, 4);
+    }
+
+    #[test]
+    fn charsets() {
+        assert_eq!(decode_charset(b"caf\xe9", "ISO-8859-1"), "café");
+        assert_eq!(decode_charset(b"\x93hi\x94", "windows-1252"), "\u{201c}hi\u{201d}");
+        assert_eq!(decode_charset("café".as_bytes(), "utf-8"), "café");
+        assert_eq!(decode_charset(b"caf\xe9", "iso-8859-2"), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn deep_multiparts_are_opaque() {
+        let part = MimePart::parse(&nested_multiparts(MAX_DEPTH));
+        assert_eq!(part.part(&vec!("1"; MAX_DEPTH).join(".")).map(|p| p.text()), Some("text".to_string()));
+
+        let raw = nested_multiparts(2000);
+        let m = parse_message(&raw, "b1");
+        let a = m.attachments.unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].typ, "multipart/mixed");
+        assert_eq!(a[0].blob_id, format!("b1/{}", vec!("1"; MAX_DEPTH).join(".")));
+    }
+
+    #[test]
+    fn deep_attached_messages_stop() {
+        let m = parse_message(&nested_messages(3), "b1");
+        let inner = &m.attached_messages.unwrap()["b1/1"];
+        assert_eq!(inner.subject, "outer");
+
+        let mut m = parse_message(&nested_messages(2000), "b1");
+        let mut depth = 0;
+        while let Some(mut attached) = m.attached_messages.take() {
+            let id = attached.keys().next().unwrap().clone();
+            m = attached.remove(&id).unwrap();
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_DEPTH);
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use chrono::UTC;
+
+use record;
+use message::{Message, Emailer, Attachment};
+use store::MemoryStore;
+use types::Date;
+
+// RFC 5322/MIME message generation, for turning a Message (usually a draft
+// created through setMessages) into something that can be stored and sent
+
+
+// longest line we'll generate, not counting the CRLF
+const LINE_LENGTH: usize = 76;
+
+// headers we generate ourselves, and so won't copy from Message.headers
+const GENERATED_HEADERS: [&'static str; 16] = [
+    "date", "from", "sender", "reply-to", "to", "cc", "bcc", "subject",
+    "message-id", "in-reply-to", "references", "mime-version",
+    "content-type", "content-transfer-encoding", "content-disposition", "content-id",
+];
+
+
+// where attachment content comes from and where the finished message goes
+pub trait BlobStore {
+    fn get_blob(&self, blob_id: &str) -> Option<Vec<u8>>;
+    fn put_blob(&mut self, data: &[u8]) -> String;
+}
+
+// where the message being replied to is found, by its record id
+pub trait MessageLookup {
+    fn get_message(&self, id: &str) -> Option<Message>;
+}
+
+impl MessageLookup for MemoryStore<Message> {
+    fn get_message(&self, id: &str) -> Option<Message> {
+        self.record(id).cloned()
+    }
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RenderError {
+    BlobNotFound(String),
+    InvalidHeader(String),
+    InvalidAddress(String),
+}
+
+impl Error for RenderError {
+    fn description(&self) -> &str {
+        match *self {
+            RenderError::BlobNotFound(_)   => "blob not found",
+            RenderError::InvalidHeader(_)  => "invalid header",
+            RenderError::InvalidAddress(_) => "invalid address",
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::BlobNotFound(ref b)   => write!(f, "blob \"{}\" not found", b),
+            RenderError::InvalidHeader(ref h)  => write!(f, "invalid header \"{}\"", h),
+            RenderError::InvalidAddress(ref a) => write!(f, "invalid address \"{}\"", a),
+        }
+    }
+}
+
+
+// what a message is being rendered for. messages to be sent leave out Bcc,
+// since those addresses belong only in the envelope; a draft copy keeps it
+// so it isn't lost.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderMode {
+    Send,
+    Draft,
+}
+
+impl Default for RenderMode {
+    fn default() -> RenderMode {
+        RenderMode::Send
+    }
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RenderedMessage {
+    pub blob_id: String,
+    pub size:    u64,
+    pub raw:     Vec<u8>,
+}
+
+
+const BASE64_CHARS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                _    => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+// base64 broken into lines for a body
+fn encode_base64_lines(data: &[u8]) -> String {
+    let b64 = encode_base64(data);
+    let lines: Vec<&str> = b64.as_bytes().chunks(LINE_LENGTH).map(|l| ::std::str::from_utf8(l).unwrap()).collect();
+    lines.join("\r\n")
+}
+
+pub fn encode_quoted_printable(text: &str) -> String {
+    let mut out = String::new();
+    for (n, line) in text.split('\n').enumerate() {
+        if n > 0 {
+            out.push_str("\r\n");
+        }
+        let line = line.trim_end_matches('\r');
+        let bytes = line.as_bytes();
+        let mut len = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            // trailing whitespace has to be encoded, or it might get eaten
+            let last = i == bytes.len() - 1;
+            let enc = match b {
+                b'=' => format!("={:02X}", b),
+                b' ' | b'\t' if last => format!("={:02X}", b),
+                33..=126 | b' ' | b'\t' => (b as char).to_string(),
+                _ => format!("={:02X}", b),
+            };
+            if len + enc.len() > LINE_LENGTH - 1 {
+                out.push_str("=\r\n");
+                len = 0;
+            }
+            out.push_str(&enc);
+            len += enc.len();
+        }
+    }
+    out
+}
+
+// a header value as one or more RFC 2047 encoded words, if it needs it
+pub fn encode_header_text(s: &str) -> String {
+    if s.chars().all(|c| c >= ' ' && c <= '~') && !s.contains("=?") {
+        return s.to_string();
+    }
+
+    // 45 octets encodes to 60 chars, which with the wrapping keeps each word
+    // within the 75 char limit
+    let mut words = vec!();
+    let mut chunk = String::new();
+    for c in s.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", encode_base64(chunk.as_bytes())));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", encode_base64(chunk.as_bytes())));
+    }
+    words.join(" ")
+}
+
+// a header value that goes into the message as it is. a line break in it
+// would start a new header, so it isn't allowed.
+fn raw_header(name: &str, value: &str) -> Result<(String,String),RenderError> {
+    match value.contains(|c| c == '\r' || c == '\n') {
+        true => Err(RenderError::InvalidHeader(name.to_string())),
+        _    => Ok((name.to_string(), value.to_string())),
+    }
+}
+
+// header names are printable ASCII other than the colon (RFC 5322 ftext)
+fn valid_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b >= 33 && b <= 126 && b != b':')
+}
+
+fn format_emailer(e: &Emailer) -> Result<String,RenderError> {
+    if e.email.contains(|c| c == '\r' || c == '\n' || c == '<' || c == '>') {
+        return Err(RenderError::InvalidAddress(e.email.clone()));
+    }
+    if e.name.is_empty() {
+        return Ok(e.email.clone());
+    }
+    let is_atoms = e.name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ' || "!#$%&'*+-/=?^_`{|}~".contains(c));
+    let is_ascii = e.name.chars().all(|c| c >= ' ' && c <= '~');
+    let name = match (is_atoms, is_ascii) {
+        (true, _) => e.name.clone(),
+        (_, true) => format!("\"{}\"", e.name.replace("\\", "\\\\").replace("\"", "\\\"")),
+        _         => encode_header_text(&e.name),
+    };
+    Ok(format!("{} <{}>", name, e.email))
+}
+
+fn format_emailers(v: &[Emailer]) -> Result<String,RenderError> {
+    let mut out = vec!();
+    for e in v.iter() {
+        out.push(try!(format_emailer(e)));
+    }
+    Ok(out.join(", "))
+}
+
+fn format_date(d: &Date) -> String {
+    d.format("%a, %d %b %Y %H:%M:%S +0000").to_string()
+}
+
+// fold a header onto multiple lines at whitespace where it gets too long
+fn fold_header(name: &str, value: &str) -> String {
+    let mut out = format!("{}:", name);
+    let mut len = out.len();
+    for word in value.split(' ').filter(|w| !w.is_empty()) {
+        if len + 1 + word.len() > LINE_LENGTH && len > name.len() + 1 {
+            out.push_str("\r\n");
+            len = 0;
+        }
+        out.push(' ');
+        out.push_str(word);
+        len += 1 + word.len();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+fn new_boundary() -> String {
+    format!("=_{}", record::new_id())
+}
+
+fn needs_encoding(text: &str) -> bool {
+    !text.is_ascii() || text.lines().any(|l| l.len() > LINE_LENGTH)
+}
+
+// the transfer encoding that describes data sent as it is. attached messages
+// go in this way, since base64 isn't allowed for them.
+fn identity_encoding(data: &[u8]) -> &'static str {
+    let binary = data.contains(&0) || data.split(|&b| b == b'\n').any(|l| l.len() > 998);
+    match (binary, data.is_ascii()) {
+        (true, _) => "binary",
+        (_, true) => "7bit",
+        _         => "8bit",
+    }
+}
+
+// a MIME entity: its headers and its already-encoded body
+struct Entity {
+    headers: Vec<(String,String)>,
+    body:    Vec<u8>,
+}
+
+impl Entity {
+    fn text(subtype: &str, text: &str) -> Entity {
+        let (cte, body) = match needs_encoding(text) {
+            true => ("quoted-printable", encode_quoted_printable(text)),
+            _    => ("7bit", text.replace("\r\n", "\n").replace("\n", "\r\n")),
+        };
+        Entity {
+            headers: vec!(
+                ("Content-Type".to_string(), format!("text/{}; charset=utf-8", subtype)),
+                ("Content-Transfer-Encoding".to_string(), cte.to_string()),
+            ),
+            body: body.into_bytes(),
+        }
+    }
+
+    fn multipart(subtype: &str, parts: Vec<Entity>) -> Entity {
+        let boundary = new_boundary();
+        let mut body = vec!();
+        for p in parts.into_iter() {
+            body.extend(format!("--{}\r\n", boundary).into_bytes());
+            body.extend(p.to_bytes());
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--", boundary).into_bytes());
+        Entity {
+            headers: vec!(("Content-Type".to_string(), format!("multipart/{}; boundary=\"{}\"", subtype, boundary))),
+            body: body,
+        }
+    }
+
+    fn attachment<B: BlobStore>(a: &Attachment, blobs: &B) -> Result<Entity,RenderError> {
+        let data = match blobs.get_blob(&a.blob_id) {
+            Some(d) => d,
+            None    => return Err(RenderError::BlobNotFound(a.blob_id.clone())),
+        };
+
+        let mut headers = vec!();
+        let disposition = match a.is_inline {
+            true => "inline",
+            _    => "attachment",
+        };
+        match a.name.is_empty() {
+            true => {
+                headers.push(try!(raw_header("Content-Type", &a.typ)));
+                headers.push(("Content-Disposition".to_string(), disposition.to_string()));
+            },
+            _ => {
+                let (ctparam, dparam) = match a.name.chars().all(|c| c >= ' ' && c <= '~' && c != '"' && c != '\\') {
+                    true => (format!("name=\"{}\"", a.name), format!("filename=\"{}\"", a.name)),
+                    _    => (format!("name=\"{}\"", encode_header_text(&a.name)), format!("filename*=utf-8''{}", encode_percent(&a.name))),
+                };
+                headers.push(try!(raw_header("Content-Type", &format!("{}; {}", a.typ, ctparam))));
+                headers.push(("Content-Disposition".to_string(), format!("{}; {}", disposition, dparam)));
+            },
+        }
+        if let Some(ref cid) = a.cid {
+            headers.push(try!(raw_header("Content-ID", &format!("<{}>", cid))));
+        }
+
+        let (cte, body) = match a.typ.to_lowercase().as_ref() {
+            "message/rfc822" => (identity_encoding(&data), data),
+            _                => ("base64", encode_base64_lines(&data).into_bytes()),
+        };
+        headers.push(("Content-Transfer-Encoding".to_string(), cte.to_string()));
+
+        Ok(Entity {
+            headers: headers,
+            body:    body,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        for &(ref k, ref v) in self.headers.iter() {
+            out.push_str(&fold_header(k, v));
+        }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        out.extend(&self.body);
+        out
+    }
+}
+
+// RFC 2231 percent-encoding for parameter values
+fn encode_percent(s: &str) -> String {
+    let mut out = String::new();
+    for &b in s.as_bytes().iter() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+// the domain for generated message ids, taken from the first from address
+fn message_id_domain(m: &Message) -> String {
+    let email = m.from.as_ref().and_then(|v| v.first()).map(|e| e.email.clone()).unwrap_or("".to_string());
+    match email.rfind('@') {
+        Some(i) if i + 1 < email.len() => email[i+1..].to_string(),
+        _ => "localhost".to_string(),
+    }
+}
+
+fn header<'a>(m: &'a Message, name: &str) -> Option<&'a String> {
+    let name = name.to_lowercase();
+    m.headers.iter().find(|&(k, _)| k.to_lowercase() == name).map(|(_, v)| v)
+}
+
+// build the body entity: text and html as alternatives, html and inline
+// attachments as related, and the whole lot mixed with any other attachments
+fn build_body<B: BlobStore>(m: &Message, blobs: &B) -> Result<Entity,RenderError> {
+    let text = m.text_body.as_ref().map(|t| Entity::text("plain", t));
+    let (inline, attached): (Vec<&Attachment>, Vec<&Attachment>) = match m.attachments {
+        Some(ref v) => v.iter().partition(|a| a.is_inline && a.cid.is_some() && m.html_body.is_some()),
+        None        => (vec!(), vec!()),
+    };
+
+    let html = match m.html_body {
+        Some(ref h) => {
+            let html = Entity::text("html", h);
+            match inline.is_empty() {
+                true => Some(html),
+                _ => {
+                    let mut parts = vec!(html);
+                    for a in inline.iter() {
+                        parts.push(try!(Entity::attachment(a, blobs)));
+                    }
+                    Some(Entity::multipart("related", parts))
+                },
+            }
+        },
+        None => None,
+    };
+
+    let body = match (text, html) {
+        (Some(t), Some(h)) => Entity::multipart("alternative", vec!(t, h)),
+        (Some(t), None)    => t,
+        (None, Some(h))    => h,
+        (None, None)       => Entity::text("plain", ""),
+    };
+
+    match attached.is_empty() {
+        true => Ok(body),
+        _ => {
+            let mut parts = vec!(body);
+            for a in attached.iter() {
+                parts.push(try!(Entity::attachment(a, blobs)));
+            }
+            Ok(Entity::multipart("mixed", parts))
+        },
+    }
+}
+
+// generate the raw message for m and store it. In-Reply-To and References
+// come from the message named by in_reply_to_message_id, looked up in
+// messages. the date and message id are filled in if the message doesn't
+// have them.
+pub fn render_message<B,L>(m: &Message, blobs: &mut B, messages: &L, mode: RenderMode) -> Result<RenderedMessage,RenderError>
+    where B: BlobStore, L: MessageLookup {
+    let mut headers = Vec::<(String,String)>::new();
+
+    let date = match *m.date == *Date::default() {
+        true => Date(UTC::now()),
+        _    => m.date.clone(),
+    };
+    headers.push(("Date".to_string(), format_date(&date)));
+
+    let bcc = match mode {
+        RenderMode::Draft => &m.bcc,
+        RenderMode::Send  => &None,
+    };
+    let addresses = [
+        ("From", &m.from), ("Reply-To", &m.reply_to),
+        ("To", &m.to), ("Cc", &m.cc), ("Bcc", bcc),
+    ];
+    for &(name, v) in addresses.iter() {
+        if let Some(ref v) = *v {
+            if !v.is_empty() {
+                headers.push((name.to_string(), try!(format_emailers(v))));
+            }
+        }
+    }
+    if let Some(ref s) = m.sender {
+        headers.push(("Sender".to_string(), try!(format_emailer(s))));
+    }
+
+    headers.push(("Subject".to_string(), encode_header_text(&m.subject)));
+
+    let message_id = match header(m, "Message-ID") {
+        Some(id) => id.clone(),
+        None     => format!("<{}@{}>", record::new_id(), message_id_domain(m)),
+    };
+    headers.push(try!(raw_header("Message-ID", &message_id)));
+
+    let parent = m.in_reply_to_message_id.as_ref().and_then(|id| messages.get_message(id));
+    match parent.as_ref().and_then(|p| header(p, "Message-ID").map(|id| (p, id))) {
+        Some((p, id)) => {
+            let references = match header(p, "References") {
+                Some(r) => format!("{} {}", r, id),
+                None    => id.clone(),
+            };
+            headers.push(try!(raw_header("In-Reply-To", id)));
+            headers.push(try!(raw_header("References", &references)));
+        },
+        None => {
+            for name in ["In-Reply-To", "References"].iter() {
+                if let Some(v) = header(m, name) {
+                    headers.push(try!(raw_header(name, v)));
+                }
+            }
+        },
+    }
+
+    // anything else the client set
+    let extra: BTreeMap<&String,&String> = m.headers.iter().filter(|&(k, _)| !GENERATED_HEADERS.contains(&k.to_lowercase().as_ref())).collect();
+    for (k, v) in extra.into_iter() {
+        if !valid_header_name(k) {
+            return Err(RenderError::InvalidHeader(k.clone()));
+        }
+        headers.push((k.clone(), encode_header_text(v)));
+    }
+
+    headers.push(("MIME-Version".to_string(), "1.0".to_string()));
+
+    let body = try!(build_body(m, blobs));
+    headers.extend(body.headers);
+
+    let entity = Entity {
+        headers: headers,
+        body:    body.body,
+    };
+    let raw = entity.to_bytes();
+
+    Ok(RenderedMessage {
+        blob_id: blobs.put_blob(&raw),
+        size:    raw.len() as u64,
+        raw:     raw,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use message::{Message, Emailer, Attachment};
+    use store::MemoryStore;
+    use super::*;
+
+    #[derive(Default)]
+    struct Blobs(BTreeMap<String,Vec<u8>>);
+
+    impl BlobStore for Blobs {
+        fn get_blob(&self, blob_id: &str) -> Option<Vec<u8>> {
+            self.0.get(blob_id).cloned()
+        }
+        fn put_blob(&mut self, data: &[u8]) -> String {
+            let id = format!("blob{}", self.0.len());
+            self.0.insert(id.clone(), data.to_vec());
+            id
+        }
+    }
+
+    fn emailer(email: &str) -> Emailer {
+        Emailer::default().with_email(email.to_string())
+    }
+
+    fn message() -> Message {
+        let mut m = Message::default();
+        m.from = Some(vec!(emailer("alice@example.com")));
+        m.to = Some(vec!(emailer("bob@example.com")));
+        m.bcc = Some(vec!(emailer("secret@example.com")));
+        m.subject = "hello".to_string();
+        m.text_body = Some("hi bob".to_string());
+        m
+    }
+
+    fn render(m: &Message, mode: RenderMode) -> String {
+        let r = render_message(m, &mut Blobs::default(), &MemoryStore::<Message>::default(), mode).unwrap();
+        String::from_utf8(r.raw).unwrap()
+    }
+
+    #[test]
+    fn bcc_left_out_when_sending() {
+        let raw = render(&message(), RenderMode::Send);
+        assert!(raw.contains("To: bob@example.com\r\n"));
+        assert!(!raw.contains("secret@example.com"));
+    }
+
+    #[test]
+    fn bcc_kept_in_drafts() {
+        let raw = render(&message(), RenderMode::Draft);
+        assert!(raw.contains("Bcc: secret@example.com\r\n"));
+    }
+
+    #[test]
+    fn attached_message_bytes_kept() {
+        let attached = b"Subject: caf\xe9\r\n\r\n\xff\xfe body\r\n".to_vec();
+        let mut blobs = Blobs::default();
+        blobs.0.insert("att".to_string(), attached.clone());
+        let mut m = message();
+        m.attachments = Some(vec!(Attachment::default()
+            .with_blob_id("att".to_string())
+            .with_typ("message/rfc822".to_string())));
+        let r = render_message(&m, &mut blobs, &MemoryStore::<Message>::default(), RenderMode::Send).unwrap();
+        assert!(r.raw.windows(attached.len()).any(|w| w == &attached[..]));
+        let raw = String::from_utf8_lossy(&r.raw);
+        assert!(raw.contains("Content-Type: message/rfc822\r\nContent-Disposition: attachment\r\nContent-Transfer-Encoding: 8bit\r\n"));
+    }
+
+    #[test]
+    fn reply_headers_from_parent_record() {
+        let mut parent = message().with_id("p1".to_string());
+        parent.headers.insert("Message-ID".to_string(), "<b@example.com>".to_string());
+        parent.headers.insert("References".to_string(), "<a@example.com>".to_string());
+        let mut messages = MemoryStore::<Message>::default();
+        messages.insert(parent);
+
+        let mut m = message();
+        m.in_reply_to_message_id = Some("p1".to_string());
+        let r = render_message(&m, &mut Blobs::default(), &messages, RenderMode::Send).unwrap();
+        let raw = String::from_utf8(r.raw).unwrap();
+        assert!(raw.contains("In-Reply-To: <b@example.com>\r\n"));
+        assert!(raw.contains("References: <a@example.com> <b@example.com>\r\n"));
+    }
+
+    #[test]
+    fn line_breaks_in_headers_rejected() {
+        let render = |m: &Message| render_message(m, &mut Blobs::default(), &MemoryStore::<Message>::default(), RenderMode::Send);
+        let injected = "\r\nBcc: victim@evil.example";
+
+        let mut m = message();
+        m.headers.insert("Message-ID".to_string(), format!("<a@b>{}", injected));
+        assert_eq!(render(&m), Err(RenderError::InvalidHeader("Message-ID".to_string())));
+
+        let mut m = message();
+        m.headers.insert("References".to_string(), format!("<a@b>{}", injected));
+        assert_eq!(render(&m), Err(RenderError::InvalidHeader("References".to_string())));
+
+        let mut m = message();
+        m.headers.insert(format!("X-Foo{}", injected), "bar".to_string());
+        assert_eq!(render(&m), Err(RenderError::InvalidHeader(format!("X-Foo{}", injected))));
+        let mut m = message();
+        m.headers.insert("X Foo:".to_string(), "bar".to_string());
+        assert!(render(&m).is_err());
+
+        let mut m = message();
+        m.to = Some(vec!(emailer(&format!("bob@example.com{}", injected))));
+        assert_eq!(render(&m), Err(RenderError::InvalidAddress(format!("bob@example.com{}", injected))));
+        let mut m = message();
+        m.to = Some(vec!(emailer("bob@example.com> <eve@example.com")));
+        assert!(render(&m).is_err());
+
+        let mut blobs = Blobs::default();
+        blobs.0.insert("att".to_string(), b"data".to_vec());
+        for &(typ, cid) in [("text/plain\r\nBcc: x@y", None), ("text/plain", Some("c\r\nBcc: x@y"))].iter() {
+            let mut m = message();
+            m.attachments = Some(vec!(Attachment::default()
+                .with_blob_id("att".to_string())
+                .with_typ(typ.to_string())
+                .with_cid(cid.map(|c| c.to_string()))));
+            assert!(render_message(&m, &mut blobs, &MemoryStore::<Message>::default(), RenderMode::Send).is_err());
+        }
+
+        // text that's encoded anyway is fine
+        let mut m = message();
+        m.subject = format!("hi{}", injected);
+        m.headers.insert("X-Note".to_string(), format!("a{}", injected));
+        m.to = Some(vec!(Emailer::default().with_name(format!("Bob{}", injected)).with_email("bob@example.com".to_string())));
+        let raw = String::from_utf8(render(&m).unwrap().raw).unwrap();
+        assert!(!raw.contains("\r\nBcc:"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::string::ToString;
 use std::collections::BTreeMap;
+use std::cell::Cell;
 use std::error::Error;
 use std::fmt;
 use std::{i32,i64,usize};
@@ -13,6 +14,8 @@ pub enum ParseError {
     InvalidStructure(String),
     MissingField(String),
     UnknownMethod(String),
+    UnknownField(String),
+    Multiple(Vec<ParseError>),
 }
 
 impl Error for ParseError {
@@ -22,6 +25,8 @@ impl Error for ParseError {
             ParseError::InvalidStructure(_) => "invalid value structure for conversion",
             ParseError::MissingField(_)     => "missing field",
             ParseError::UnknownMethod(_)    => "unknown method",
+            ParseError::UnknownField(_)     => "unknown field",
+            ParseError::Multiple(_)         => "multiple errors",
         }
     }
 }
@@ -33,6 +38,8 @@ impl fmt::Display for ParseError {
             ParseError::InvalidStructure(ref e) => format!("invalid value structure for conversion to {}", e),
             ParseError::MissingField(ref e)     => format!("missing field \"{}\"", e),
             ParseError::UnknownMethod(ref e)    => format!("unknown method \"{}\"", e),
+            ParseError::UnknownField(ref e)     => format!("unknown field \"{}\"", e),
+            ParseError::Multiple(ref v)         => v.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "),
         }.to_string())
     }
 }
@@ -64,6 +71,73 @@ pub trait FromJson: Sized {
 }
 
 
+thread_local!(static STRICT: Cell<bool> = Cell::new(false));
+
+pub fn is_strict() -> bool {
+    STRICT.with(|s| s.get())
+}
+
+// parse in strict mode, for checking input from clients. properties a type
+// doesn't know about are errors rather than being kept, and every error is
+// reported instead of just the first.
+pub fn from_json_strict<T>(json: &Json) -> Result<T,ParseError> where T: FromJson {
+    let was = STRICT.with(|s| s.replace(true));
+    let r = T::from_json(json);
+    STRICT.with(|s| s.set(was));
+    r
+}
+
+// collects the errors from parsing the parts of an object or array. normally
+// the first one is what gets reported; in strict mode they all are.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FieldErrors(Vec<ParseError>);
+
+impl FieldErrors {
+    pub fn new() -> FieldErrors {
+        FieldErrors(vec!())
+    }
+
+    // note a part's result, giving back its value if it parsed
+    pub fn check<T>(&mut self, r: Result<T,ParseError>) -> Option<T> {
+        match r {
+            Ok(v) => Some(v),
+            Err(ParseError::Multiple(v)) => { self.0.extend(v); None },
+            Err(e) => { self.0.push(e); None },
+        }
+    }
+
+    // in strict mode, properties in json that aren't known are errors
+    pub fn check_unknown(&mut self, json: &BTreeMap<String,Json>, known: &[&str]) {
+        if is_strict() {
+            self.0.extend(extra_properties(json, known).into_iter().map(|(k, _)| ParseError::UnknownField(k)));
+        }
+    }
+
+    // the unknown properties in json, to keep. in strict mode they're errors
+    // instead, and none are kept.
+    pub fn extra(&mut self, json: &BTreeMap<String,Json>, known: &[&str]) -> BTreeMap<String,Json> {
+        match is_strict() {
+            true => {
+                self.check_unknown(json, known);
+                BTreeMap::new()
+            },
+            _ => extra_properties(json, known),
+        }
+    }
+
+    pub fn finish(mut self) -> Result<(),ParseError> {
+        match self.0.len() {
+            0 => Ok(()),
+            1 => Err(self.0.remove(0)),
+            _ => match is_strict() {
+                true => Err(ParseError::Multiple(self.0)),
+                _    => Err(self.0.remove(0)),
+            },
+        }
+    }
+}
+
+
 // conversions for system types
 impl FromJson for String {
     fn from_json(json: &Json) -> Result<String,ParseError> {
@@ -128,11 +202,10 @@ impl<T> FromJson for Vec<T> where T: FromJson {
     fn from_json(json: &Json) -> Result<Vec<T>,ParseError> {
         match *json {
             Json::Array(ref a) => {
-                let (ok, mut err): (Vec<_>,Vec<_>)  = a.iter().map(|ref j| T::from_json(j)).partition(|ref r| match **r { Ok(_) => true, Err(_) => false });
-                match err.len() {
-                    0 => Ok(ok.into_iter().map(|r| r.ok().unwrap()).collect()),
-                    _ => Err(err.remove(0).err().unwrap()),
-                }
+                let mut errors = FieldErrors::new();
+                let v = a.iter().filter_map(|j| errors.check(T::from_json(j))).collect();
+                try!(errors.finish());
+                Ok(v)
             }
             _ => Err(ParseError::InvalidJsonType("Vec".to_string())),
         }
@@ -143,11 +216,14 @@ impl<T> FromJson for BTreeMap<String,T> where T: FromJson {
     fn from_json(json: &Json) -> Result<Self,ParseError> {
         match *json {
             Json::Object(ref o) => {
+                let mut errors = FieldErrors::new();
                 let mut m = BTreeMap::<String,T>::new();
                 for (k, v) in o.iter() {
-                    let vv = try!(T::from_json(v));
-                    m.insert(k.clone(), vv);
+                    if let Some(vv) = errors.check(T::from_json(v)) {
+                        m.insert(k.clone(), vv);
+                    }
                 }
+                try!(errors.finish());
                 Ok(m)
             },
             _ => Err(ParseError::InvalidJsonType("BTreeMap".to_string()))
@@ -219,6 +295,95 @@ impl<T> FromJsonField for Presence<Option<T>> where T: FromJson {
 }
 
 
+// properties of partial records are tri-state. absent means unchanged, and
+// a value replaces the old one. nullable (Option<T>) properties can also be
+// null, which clears them; null isn't a valid value for anything else.
+pub trait FromPartialField: Sized {
+    fn from_partial_field(json: &BTreeMap<String,Json>, field: &str) -> Result<Self,ParseError>;
+}
+
+impl<T> FromPartialField for Presence<T> where T: FromJson {
+    fn from_partial_field(json: &BTreeMap<String,Json>, field: &str) -> Result<Self,ParseError> {
+        match json.get(field) {
+            Some(v) => T::from_json(v).map(Present),
+            None    => Ok(Absent),
+        }
+    }
+}
+impl<T> FromPartialField for Presence<Option<T>> where T: FromJson {
+    fn from_partial_field(json: &BTreeMap<String,Json>, field: &str) -> Result<Self,ParseError> {
+        FromJsonField::from_json_field(json, field)
+    }
+}
+
+
+// turns a builder's property into the value for the thing being built.
+// required properties must have been given; nullable ones default to null.
+pub trait BuildField<T> {
+    fn build_field(self, field: &str) -> Result<T,ParseError>;
+}
+
+impl<T> BuildField<T> for Presence<T> where T: FromJson {
+    fn build_field(self, field: &str) -> Result<T,ParseError> {
+        match self {
+            Present(v) => Ok(v),
+            Absent     => Err(ParseError::MissingField(field.to_string())),
+        }
+    }
+}
+impl<T> BuildField<Option<T>> for Presence<Option<T>> where T: FromJson {
+    fn build_field(self, _: &str) -> Result<Option<T>,ParseError> {
+        match self {
+            Present(v) => Ok(v),
+            Absent     => Ok(None),
+        }
+    }
+}
+impl<T> BuildField<Presence<T>> for Presence<Presence<T>> where T: FromJson {
+    fn build_field(self, _: &str) -> Result<Presence<T>,ParseError> {
+        match self {
+            Present(v) => Ok(v),
+            Absent     => Ok(Absent),
+        }
+    }
+}
+
+
+// records and props keep properties they don't know about (vendor extensions
+// and the like) so they survive a parse and serialise. these are the ones in
+// json that aren't in known.
+pub fn extra_properties(json: &BTreeMap<String,Json>, known: &[&str]) -> BTreeMap<String,Json> {
+    json.iter()
+        .filter(|&(k, _)| !known.contains(&k.as_ref()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+// apply extra properties from a partial. as with known properties, null
+// clears them.
+pub fn update_extra_properties(extra: &mut BTreeMap<String,Json>, changes: &BTreeMap<String,Json>) {
+    for (k, v) in changes.iter() {
+        match *v {
+            Json::Null => { extra.remove(k); },
+            _          => { extra.insert(k.clone(), v.clone()); },
+        }
+    }
+}
+
+// the changes that turn the extra properties from into to, in the form
+// update_extra_properties takes
+pub fn diff_extra_properties(from: &BTreeMap<String,Json>, to: &BTreeMap<String,Json>) -> BTreeMap<String,Json> {
+    let mut changes: BTreeMap<String,Json> = to.iter()
+        .filter(|&(k, v)| from.get(k) != Some(v))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    for k in from.keys().filter(|k| !to.contains_key(*k)) {
+        changes.insert(k.clone(), Json::Null);
+    }
+    changes
+}
+
+
 pub trait ToJsonField {
     fn to_json_field(&self, json: &mut BTreeMap<String,Json>, field: &str);
 }
@@ -236,3 +401,34 @@ impl<T> ToJsonField for Presence<T> where T: ToJson {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::{Json,ToJson};
+    use super::*;
+
+    fn object(s: &str) -> BTreeMap<String,Json> {
+        Json::from_str(s).unwrap().as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn nullable_partial_field_round_trip() {
+        for &(json, ref p) in [(r#"{}"#, Absent), (r#"{"f":null}"#, Present(None)), (r#"{"f":"x"}"#, Present(Some("x".to_string())))].iter() {
+            let o = object(json);
+            let parsed: Presence<Option<String>> = FromPartialField::from_partial_field(&o, "f").unwrap();
+            assert_eq!(parsed, *p);
+            let mut out = BTreeMap::new();
+            parsed.to_json_field(&mut out, "f");
+            assert_eq!(Json::Object(out), o.to_json());
+        }
+    }
+
+    #[test]
+    fn null_partial_field_needs_option() {
+        let o = object(r#"{"f":null}"#);
+        let r: Result<Presence<String>,_> = FromPartialField::from_partial_field(&o, "f");
+        assert!(r.is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::cmp;
+use std::cmp::Ordering;
+
+use parse::Presence;
+use parse::Presence::*;
+use method::MethodError;
+
+// pieces shared by the list/query methods: sort comparators and paging
+
+
+// a single sort comparator, parsed from "property" or "property asc|desc"
+#[derive(Clone, PartialEq, Debug)]
+pub struct SortComparator {
+    pub property:  String,
+    pub ascending: bool,
+}
+
+impl SortComparator {
+    pub fn parse(s: &str) -> Result<SortComparator,MethodError> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.len() {
+            1 => Ok(SortComparator { property: parts[0].to_string(), ascending: true }),
+            2 => match parts[1] {
+                "asc"  => Ok(SortComparator { property: parts[0].to_string(), ascending: true }),
+                "desc" => Ok(SortComparator { property: parts[0].to_string(), ascending: false }),
+                _      => Err(MethodError::UnsupportedSort),
+            },
+            _ => Err(MethodError::UnsupportedSort),
+        }
+    }
+
+    pub fn parse_all(sort: &Vec<String>) -> Result<Vec<SortComparator>,MethodError> {
+        let mut out = vec!();
+        for s in sort.iter() {
+            out.push(try!(SortComparator::parse(s)));
+        }
+        Ok(out)
+    }
+
+    // apply the direction of this comparator to an ascending ordering
+    pub fn direct(&self, o: Ordering) -> Ordering {
+        match self.ascending {
+            true => o,
+            _    => o.reverse(),
+        }
+    }
+}
+
+
+// case-insensitive substring match, used by all the text filters
+pub fn text_matches(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+
+// work out the position of the first item to return, from either an explicit
+// position or an anchor id and offset. the anchor is looked up with find.
+pub fn start_position<F>(position: &Presence<u64>, anchor: &Presence<String>, anchor_offset: &Presence<i64>, find: F) -> Result<usize,MethodError>
+    where F: Fn(&str) -> Option<usize> {
+    match *anchor {
+        Present(ref a) => {
+            let index = match find(a) {
+                Some(i) => i as i64,
+                None    => return Err(MethodError::AnchorNotFound),
+            };
+            let offset = match *anchor_offset {
+                Present(o) => o,
+                Absent     => 0,
+            };
+            // an offset big enough to overflow is clamped; a negative start
+            // is the beginning of the list and a huge one is past its end
+            match index.saturating_sub(offset) {
+                p if p < 0 => Ok(0),
+                p          => Ok(cmp::min(p as u64, usize::max_value() as u64) as usize),
+            }
+        },
+        Absent => match *position {
+            Present(p) => Ok(p as usize),
+            Absent     => Ok(0),
+        },
+    }
+}
+
+// the window of a list starting at position, at most limit long
+pub fn page<'a,T>(items: &'a [T], position: usize, limit: &Presence<u64>) -> &'a [T] {
+    if position >= items.len() {
+        return &[];
+    }
+    let end = match *limit {
+        Present(l) => cmp::min(position.saturating_add(l as usize), items.len()),
+        Absent     => items.len(),
+    };
+    &items[position..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(id: &str) -> Option<usize> {
+        ["a", "b", "c", "d"].iter().position(|i| *i == id)
+    }
+
+    #[test]
+    fn start_from_anchor() {
+        let anchor = Present("c".to_string());
+        assert_eq!(start_position(&Absent, &anchor, &Absent, find).unwrap(), 2);
+        assert_eq!(start_position(&Absent, &anchor, &Present(1), find).unwrap(), 1);
+        assert_eq!(start_position(&Absent, &anchor, &Present(-1), find).unwrap(), 3);
+        assert_eq!(start_position(&Absent, &anchor, &Present(5), find).unwrap(), 0);
+        assert_eq!(start_position(&Absent, &Present("x".to_string()), &Absent, find), Err(MethodError::AnchorNotFound));
+        assert_eq!(start_position(&Present(3), &Absent, &Absent, find).unwrap(), 3);
+    }
+
+    #[test]
+    fn start_with_huge_anchor_offset() {
+        let anchor = Present("c".to_string());
+        assert_eq!(start_position(&Absent, &anchor, &Present(i64::max_value()), find).unwrap(), 0);
+        let p = start_position(&Absent, &anchor, &Present(i64::min_value()), find).unwrap();
+        assert!(page(&["a", "b", "c", "d"], p, &Absent).is_empty());
+    }
+}
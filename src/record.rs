@@ -2,12 +2,24 @@ use std::default::Default;
 use rustc_serialize::json::ToJson;
 use uuid::Uuid;
 use parse::{FromJson, Presence};
+use method::SetError;
 
-pub trait PartialRecord: Default + ToJson + FromJson {
+// what clients may do with a record property in a set call
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PropertyAccess {
+    // only the server sets these
+    ServerSet,
+    // can be given on create, but not changed after that
+    Immutable,
+    // clients can set these whenever they like
+    ClientWritable,
+}
+
+pub trait PartialRecord: Default + Clone + ToJson + FromJson {
     fn id(&self) -> Presence<String>;
 }
 
-pub trait Record: Default + ToJson + FromJson {
+pub trait Record: Default + Clone + ToJson + FromJson {
     type Partial: PartialRecord;
 
     fn id(&self) -> String;
@@ -15,8 +27,41 @@ pub trait Record: Default + ToJson + FromJson {
     fn updated_with(&self, p: &Self::Partial) -> Self;
     fn to_partial(&self) -> Self::Partial;
     fn to_filtered_partial(&self, properties: &Vec<String>) -> Self::Partial;
+
+    // the changes that turn this record into other: a partial with the id
+    // and the properties that differ, and the names of those properties
+    fn diff(&self, other: &Self) -> (Self::Partial, Vec<String>);
+
+    // how clients may set the named property, if the record has it
+    fn property_access(property: &str) -> Option<PropertyAccess>;
+
+    // properties in p that a client may not give when creating a record, or
+    // gave as null when they can't be. the id is always assigned by the server.
+    fn invalid_create_properties(p: &Self::Partial) -> Vec<String>;
+
+    // properties in p that a client may not give when updating this record,
+    // or gave as null when they can't be. server-set and immutable properties
+    // are allowed if they're unchanged.
+    fn invalid_update_properties(&self, p: &Self::Partial) -> Vec<String>;
 }
 
 pub fn new_id() -> String {
     Uuid::new_v4().hyphenated().to_string()
 }
+
+fn invalid_properties(invalid: Vec<String>) -> Result<(),SetError> {
+    match invalid.is_empty() {
+        true => Ok(()),
+        _    => Err(SetError::invalid_properties(invalid)),
+    }
+}
+
+// check a partial from a set call's create list
+pub fn validate_create<R: Record>(p: &R::Partial) -> Result<(),SetError> {
+    invalid_properties(R::invalid_create_properties(p))
+}
+
+// check a partial from a set call's update list against the record it updates
+pub fn validate_update<R: Record>(r: &R, p: &R::Partial) -> Result<(),SetError> {
+    invalid_properties(r.invalid_update_properties(p))
+}
@@ -0,0 +1,753 @@
+use std::collections::VecDeque;
+use chrono::{NaiveDate, NaiveDateTime, Datelike, Timelike, Duration};
+
+use parse::ParseError;
+use record::Record;
+use types::Date;
+use calendar_event::*;
+use tz::{to_local, from_local};
+
+// RFC 5545 RRULE expansion for Recurrence, and the occurrences of a
+// CalendarEvent built on top of that. all of the rule arithmetic is done in
+// the event's local time.
+
+
+// nothing recurs past this
+const MAX_YEAR: i32 = 9999;
+
+// how many periods in a row can come up empty before we decide the rule
+// can't produce any more. a daily rule for the 29th of February can go
+// nearly 3000 days between instances.
+const MAX_EMPTY_PERIODS: u32 = 10000;
+
+// the allowed ranges for the by-rule values, as (min, max, whether they can
+// be negative, counting from the end). zero is never allowed for the
+// signed ones.
+const BY_RANGES: [(&'static str, i32, i32, bool); 8] = [
+    ("bySecond",      0, 60,  false),
+    ("byMinute",      0, 59,  false),
+    ("byHour",        0, 23,  false),
+    ("byMonth",       1, 12,  false),
+    ("byDate",        1, 31,  true),
+    ("byYearDay",     1, 366, true),
+    ("byWeekNo",      1, 53,  true),
+    ("bySetPosition", 1, 366, true),
+];
+
+
+// a recurrence rule with the defaults filled in and the byDay values decoded
+#[derive(Clone, Debug)]
+struct Rule {
+    frequency:       Frequency,
+    interval:        i64,
+    wkst:            u32,
+    by_day:          Vec<(u32,i32)>,
+    by_date:         Vec<i32>,
+    by_month:        Vec<i32>,
+    by_year_day:     Vec<i32>,
+    by_week_no:      Vec<i32>,
+    by_hour:         Vec<i32>,
+    by_minute:       Vec<i32>,
+    by_second:       Vec<i32>,
+    by_set_position: Vec<i32>,
+    count:           Option<u64>,
+    until:           Option<NaiveDateTime>,
+}
+
+fn list(v: &Option<Vec<i32>>) -> Vec<i32> {
+    v.clone().unwrap_or(vec!())
+}
+
+// byDay values are a day of the week (0 = Sunday) plus seven times the
+// occurrence within the period, eg 1st Monday = 8, last Monday = -6
+fn decode_day(v: i32) -> (u32,i32) {
+    let day = ((v % 7) + 7) % 7;
+    (day as u32, (v - day) / 7)
+}
+
+fn weekday(d: &NaiveDate) -> u32 {
+    d.weekday().num_days_from_sunday()
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let next = match month {
+        12 => NaiveDate::from_ymd_opt(year + 1, 1, 1),
+        _  => NaiveDate::from_ymd_opt(year, month + 1, 1),
+    };
+    match next {
+        Some(n) => n.pred().day(),
+        None    => 31,
+    }
+}
+
+fn days_in_year(year: i32) -> u32 {
+    match NaiveDate::from_ymd_opt(year, 12, 31) {
+        Some(d) => d.ordinal(),
+        None    => 365,
+    }
+}
+
+// does n (1-based, or negative from the end) pick out i of len
+fn matches_index(v: i32, i: u32, len: u32) -> bool {
+    match v {
+        v if v > 0 => v as u32 == i,
+        v if v < 0 => len as i32 + 1 + v == i as i32,
+        _          => false,
+    }
+}
+
+// the start of the week containing d
+fn week_start(d: &NaiveDate, wkst: u32) -> NaiveDate {
+    *d - Duration::days(((weekday(d) + 7 - wkst) % 7) as i64)
+}
+
+// the start of week 1 of a year: the week containing at least four days of it
+fn first_week_start(year: i32, wkst: u32) -> NaiveDate {
+    week_start(&NaiveDate::from_ymd(year, 1, 4), wkst)
+}
+
+// the week-numbering year of d, its week number and the number of weeks in
+// that year
+fn week_no(d: &NaiveDate, wkst: u32) -> (i32,u32,u32) {
+    let ws = week_start(d, wkst);
+    let year = (ws + Duration::days(3)).year();
+    let first = first_week_start(year, wkst);
+    let weeks = (first_week_start(year + 1, wkst) - first).num_days() / 7;
+    (year, ((ws - first).num_days() / 7 + 1) as u32, weeks as u32)
+}
+
+impl Rule {
+    fn new(r: &Recurrence, start: &NaiveDateTime) -> Rule {
+        let mut rule = Rule {
+            frequency:       r.frequency.clone(),
+            interval:        match r.interval { Some(i) if i > 0 => i as i64, _ => 1 },
+            wkst:            match r.first_day_of_week { Some(d) if d >= 0 && d < 7 => d as u32, _ => 1 },
+            by_day:          list(&r.by_day).into_iter().map(decode_day).collect(),
+            by_date:         list(&r.by_date),
+            by_month:        list(&r.by_month),
+            by_year_day:     list(&r.by_year_day),
+            by_week_no:      list(&r.by_week_no),
+            by_hour:         list(&r.by_hour),
+            by_minute:       list(&r.by_minute),
+            by_second:       list(&r.by_second),
+            by_set_position: list(&r.by_set_position),
+            count:           r.count,
+            // until isn't optional in the type, so the default means no limit
+            until:           match r.until == LocalDate::default() {
+                true => None,
+                _    => Some(r.until.0),
+            },
+        };
+
+        // anything not given by a by-rule comes from the start
+        let start_day = (weekday(&start.date()), 0);
+        match rule.frequency {
+            Frequency::Yearly => {
+                if rule.by_week_no.is_empty() && rule.by_year_day.is_empty() && rule.by_date.is_empty() && rule.by_day.is_empty() {
+                    rule.by_date = vec!(start.day() as i32);
+                    if rule.by_month.is_empty() {
+                        rule.by_month = vec!(start.month() as i32);
+                    }
+                }
+                else if !rule.by_week_no.is_empty() && rule.by_year_day.is_empty() && rule.by_date.is_empty() && rule.by_day.is_empty() {
+                    rule.by_day = vec!(start_day);
+                }
+            },
+            Frequency::Monthly => {
+                if rule.by_date.is_empty() && rule.by_day.is_empty() {
+                    rule.by_date = vec!(start.day() as i32);
+                }
+            },
+            Frequency::Weekly => {
+                if rule.by_day.is_empty() {
+                    rule.by_day = vec!(start_day);
+                }
+            },
+            _ => (),
+        }
+
+        rule
+    }
+
+    fn is_sub_daily(&self) -> bool {
+        match self.frequency {
+            Frequency::Hourly | Frequency::Minutely | Frequency::Secondly => true,
+            _ => false,
+        }
+    }
+
+    // the start of the period containing dt
+    fn period_start(&self, dt: &NaiveDateTime) -> NaiveDateTime {
+        let d = dt.date();
+        match self.frequency {
+            Frequency::Yearly   => NaiveDate::from_ymd(d.year(), 1, 1).and_hms(0, 0, 0),
+            Frequency::Monthly  => NaiveDate::from_ymd(d.year(), d.month(), 1).and_hms(0, 0, 0),
+            Frequency::Weekly   => week_start(&d, self.wkst).and_hms(0, 0, 0),
+            Frequency::Daily    => d.and_hms(0, 0, 0),
+            Frequency::Hourly   => d.and_hms(dt.hour(), 0, 0),
+            Frequency::Minutely => d.and_hms(dt.hour(), dt.minute(), 0),
+            Frequency::Secondly => d.and_hms(dt.hour(), dt.minute(), dt.second()),
+        }
+    }
+
+    // length of a sub-daily step, in seconds
+    fn step(&self) -> i64 {
+        self.interval * match self.frequency {
+            Frequency::Hourly   => 3600,
+            Frequency::Minutely => 60,
+            _                   => 1,
+        }
+    }
+
+    // the start of the period after p, or None if we've run out of calendar
+    fn next_period(&self, p: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let next = match self.frequency {
+            Frequency::Yearly => {
+                let year = p.year() as i64 + self.interval;
+                match year > MAX_YEAR as i64 {
+                    true => None,
+                    _    => NaiveDate::from_ymd_opt(year as i32, 1, 1).map(|d| d.and_hms(0, 0, 0)),
+                }
+            },
+            Frequency::Monthly => {
+                let months = p.year() as i64 * 12 + p.month0() as i64 + self.interval;
+                match months / 12 > MAX_YEAR as i64 {
+                    true => None,
+                    _    => NaiveDate::from_ymd_opt((months / 12) as i32, (months % 12) as u32 + 1, 1).map(|d| d.and_hms(0, 0, 0)),
+                }
+            },
+            Frequency::Weekly => p.checked_add(Duration::days(7 * self.interval)),
+            Frequency::Daily  => p.checked_add(Duration::days(self.interval)),
+            _                 => p.checked_add(Duration::seconds(self.step())),
+        };
+        next.and_then(|n| match n.year() > MAX_YEAR {
+            true => None,
+            _    => Some(n),
+        })
+    }
+
+    // for sub-daily rules, the first period at or after boundary
+    fn period_at(&self, p: &NaiveDateTime, boundary: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let diff = (*boundary - *p).num_seconds();
+        let steps = (diff + self.step() - 1) / self.step();
+        p.checked_add(Duration::seconds(steps.max(1) * self.step()))
+    }
+
+    // the candidate days for the period starting at p
+    fn period_days(&self, p: &NaiveDateTime) -> Vec<NaiveDate> {
+        let d = p.date();
+        let (first, n) = match self.frequency {
+            Frequency::Yearly if !self.by_week_no.is_empty() => {
+                // weeks can spill over either end of the year
+                (d - Duration::days(6), days_in_year(d.year()) as i64 + 12)
+            },
+            Frequency::Yearly  => (d, days_in_year(d.year()) as i64),
+            Frequency::Monthly => (d, days_in_month(d.year(), d.month()) as i64),
+            Frequency::Weekly  => (d, 7),
+            _                  => (d, 1),
+        };
+        (0..n).filter_map(|i| first.checked_add(Duration::days(i))).collect()
+    }
+
+    fn day_matches(&self, d: &NaiveDate, p: &NaiveDateTime) -> bool {
+        if !self.by_month.is_empty() && !self.by_month.contains(&(d.month() as i32)) {
+            return false;
+        }
+
+        if let Frequency::Yearly = self.frequency {
+            if !self.by_week_no.is_empty() {
+                let (year, week, weeks) = week_no(d, self.wkst);
+                if year != p.year() || !self.by_week_no.iter().any(|&v| matches_index(v, week, weeks)) {
+                    return false;
+                }
+            }
+        }
+
+        let year_day_applies = match self.frequency {
+            Frequency::Daily | Frequency::Weekly | Frequency::Monthly => false,
+            _ => true,
+        };
+        if year_day_applies && !self.by_year_day.is_empty() {
+            let len = days_in_year(d.year());
+            if !self.by_year_day.iter().any(|&v| matches_index(v, d.ordinal(), len)) {
+                return false;
+            }
+        }
+
+        let date_applies = match self.frequency {
+            Frequency::Weekly => false,
+            _                 => true,
+        };
+        if date_applies && !self.by_date.is_empty() {
+            let len = days_in_month(d.year(), d.month());
+            if !self.by_date.iter().any(|&v| matches_index(v, d.day(), len)) {
+                return false;
+            }
+        }
+
+        if !self.by_day.is_empty() {
+            // the nth weekday counts within the month or year, for the rules
+            // where that means something
+            let (index, len) = match self.frequency {
+                Frequency::Monthly => (d.day(), days_in_month(d.year(), d.month())),
+                Frequency::Yearly if !self.by_month.is_empty() => (d.day(), days_in_month(d.year(), d.month())),
+                Frequency::Yearly if self.by_week_no.is_empty() => (d.ordinal(), days_in_year(d.year())),
+                _ => (0, 0),
+            };
+            let day = weekday(d);
+            let found = self.by_day.iter().any(|&(wd, n)| {
+                wd == day && match (n, len) {
+                    (0, _) | (_, 0) => true,
+                    (n, _) if n > 0 => (index - 1) / 7 + 1 == n as u32,
+                    (n, _)          => (len - index) / 7 + 1 == (-n) as u32,
+                }
+            });
+            if !found {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // the values for one time field: the period's own value for the
+    // frequencies that fix it (limited by the rule), otherwise the rule's
+    // values or the start's
+    fn time_values(&self, by: &[i32], fixed: Option<u32>, default: u32, max: i32) -> Vec<u32> {
+        let mut v: Vec<u32> = match fixed {
+            Some(f) => match by.is_empty() || by.contains(&(f as i32)) {
+                true => vec!(f),
+                _    => vec!(),
+            },
+            None => match by.is_empty() {
+                true => vec!(default),
+                _    => by.iter().filter(|&&b| b >= 0 && b <= max).map(|&b| b as u32).collect(),
+            },
+        };
+        v.sort();
+        v.dedup();
+        v
+    }
+
+    // all the instances of the period starting at p, in order
+    fn instances(&self, p: &NaiveDateTime, start: &NaiveDateTime) -> Vec<NaiveDateTime> {
+        let (fix_hour, fix_minute, fix_second) = match self.frequency {
+            Frequency::Hourly   => (Some(p.hour()), None, None),
+            Frequency::Minutely => (Some(p.hour()), Some(p.minute()), None),
+            Frequency::Secondly => (Some(p.hour()), Some(p.minute()), Some(p.second())),
+            _                   => (None, None, None),
+        };
+        let hours = self.time_values(&self.by_hour, fix_hour, start.hour(), 23);
+        let minutes = self.time_values(&self.by_minute, fix_minute, start.minute(), 59);
+        let seconds = self.time_values(&self.by_second, fix_second, start.second(), 59);
+
+        let mut out = vec!();
+        for d in self.period_days(p).iter().filter(|d| self.day_matches(d, p)) {
+            for &h in hours.iter() {
+                for &m in minutes.iter() {
+                    for &s in seconds.iter() {
+                        out.push(d.and_hms(h, m, s));
+                    }
+                }
+            }
+        }
+
+        if self.by_set_position.is_empty() {
+            return out;
+        }
+        let len = out.len() as u32;
+        let mut picked: Vec<NaiveDateTime> = (1..len + 1)
+            .filter(|&i| self.by_set_position.iter().any(|&v| matches_index(v, i, len)))
+            .map(|i| out[(i - 1) as usize])
+            .collect();
+        picked.sort();
+        picked
+    }
+
+    // for sub-daily rules, when a period comes up empty because its day,
+    // hour or minute is excluded, the first later period worth looking at
+    fn skip_empty(&self, p: &NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.day_matches(&p.date(), p) {
+            return self.period_at(p, &p.date().succ().and_hms(0, 0, 0));
+        }
+        let hour_excluded = !self.by_hour.is_empty() && !self.by_hour.contains(&(p.hour() as i32));
+        let minute_excluded = !self.by_minute.is_empty() && !self.by_minute.contains(&(p.minute() as i32));
+        match self.frequency {
+            Frequency::Minutely | Frequency::Secondly if hour_excluded => {
+                self.period_at(p, &(p.date().and_hms(p.hour(), 0, 0) + Duration::hours(1)))
+            },
+            Frequency::Secondly if minute_excluded => {
+                self.period_at(p, &(p.date().and_hms(p.hour(), p.minute(), 0) + Duration::minutes(1)))
+            },
+            _ => self.next_period(p),
+        }
+    }
+}
+
+
+// the start times generated by a recurrence rule, in order. the first is
+// always the start itself, as RFC 5545 requires, and it counts towards the
+// rule's count.
+pub struct RecurrenceIter {
+    rule:    Rule,
+    start:   NaiveDateTime,
+    period:  Option<NaiveDateTime>,
+    pending: VecDeque<NaiveDateTime>,
+    emitted: u64,
+    empty:   u32,
+}
+
+impl Recurrence {
+    // check the by-rule values are all in range. a value that can never
+    // match would have expansion searching to the end of time for it.
+    pub fn validate(&self) -> Result<(),ParseError> {
+        let values = [
+            &self.by_second, &self.by_minute, &self.by_hour, &self.by_month,
+            &self.by_date, &self.by_year_day, &self.by_week_no, &self.by_set_position,
+        ];
+        for (v, &(name, min, max, signed)) in values.iter().zip(BY_RANGES.iter()) {
+            let ok = list(v).iter().all(|&n| match signed {
+                true => n.abs() >= min && n.abs() <= max,
+                _    => n >= min && n <= max,
+            });
+            if !ok {
+                return Err(ParseError::InvalidStructure(format!("Recurrence {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self, start: &NaiveDateTime) -> RecurrenceIter {
+        let rule = Rule::new(self, start);
+        let period = rule.period_start(start);
+        RecurrenceIter {
+            rule:    rule,
+            start:   start.clone(),
+            period:  Some(period),
+            pending: VecDeque::new(),
+            emitted: 0,
+            empty:   0,
+        }
+    }
+}
+
+impl Iterator for RecurrenceIter {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<NaiveDateTime> {
+        if let Some(c) = self.rule.count {
+            if self.emitted >= c {
+                return None;
+            }
+        }
+
+        if self.emitted == 0 {
+            self.emitted = 1;
+            return Some(self.start.clone());
+        }
+
+        loop {
+            while let Some(dt) = self.pending.pop_front() {
+                if dt <= self.start {
+                    continue;
+                }
+                if let Some(until) = self.rule.until {
+                    if dt > until {
+                        self.period = None;
+                        self.pending.clear();
+                        return None;
+                    }
+                }
+                self.emitted += 1;
+                return Some(dt);
+            }
+
+            let p = match self.period {
+                Some(p) => p,
+                None    => return None,
+            };
+
+            // periods can start a few days before their first instance, but
+            // never by as much as a week
+            if let Some(until) = self.rule.until {
+                if p - Duration::days(7) > until {
+                    self.period = None;
+                    return None;
+                }
+            }
+
+            let instances = self.rule.instances(&p, &self.start);
+            self.empty = match instances.is_empty() {
+                true => self.empty + 1,
+                _    => 0,
+            };
+            if self.empty > MAX_EMPTY_PERIODS {
+                self.period = None;
+                return None;
+            }
+            self.period = match instances.is_empty() && self.rule.is_sub_daily() {
+                true => self.rule.skip_empty(&p),
+                _    => self.rule.next_period(&p),
+            };
+            self.pending.extend(instances);
+        }
+    }
+}
+
+
+// a single occurrence of an event. recurrence_id is the local start time the
+// recurrence generated, which is what exceptions are keyed on; event has the
+// actual start and end and any overrides applied, and no recurrence.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Occurrence {
+    pub recurrence_id: LocalDate,
+    pub event:         CalendarEvent,
+}
+
+pub struct Occurrences<'a> {
+    event:      &'a CalendarEvent,
+    rule:       Option<RecurrenceIter>,
+    next_rule:  Option<NaiveDateTime>,
+    inclusions: VecDeque<NaiveDateTime>,
+    last:       Option<NaiveDateTime>,
+    window:     Option<(Date,Date)>,
+}
+
+impl<'a> Occurrences<'a> {
+    fn new(e: &'a CalendarEvent, window: Option<(Date,Date)>) -> Occurrences<'a> {
+        let start = to_local(&e.start_time_zone, &e.start);
+        let mut rule = match e.recurrence {
+            Some(ref r) => Some(r.iter(&start)),
+            None        => None,
+        };
+        let next_rule = match rule {
+            Some(ref mut r) => r.next(),
+            None            => Some(start),
+        };
+
+        let mut inclusions: Vec<NaiveDateTime> = match e.inclusions {
+            Some(ref v) => v.iter().map(|d| d.0).collect(),
+            None        => vec!(),
+        };
+        inclusions.sort();
+
+        Occurrences {
+            event:      e,
+            rule:       rule,
+            next_rule:  next_rule,
+            inclusions: inclusions.into_iter().collect(),
+            last:       None,
+            window:     window,
+        }
+    }
+
+    // the next recurrence id, merging the rule and the inclusions
+    fn next_id(&mut self) -> Option<NaiveDateTime> {
+        let from_rule = match (self.next_rule, self.inclusions.front()) {
+            (Some(r), Some(i)) => r <= *i,
+            (Some(_), None)    => true,
+            (None, Some(_))    => false,
+            (None, None)       => return None,
+        };
+        match from_rule {
+            true => {
+                let id = self.next_rule;
+                self.next_rule = match self.rule {
+                    Some(ref mut r) => r.next(),
+                    None            => None,
+                };
+                id
+            },
+            _ => self.inclusions.pop_front(),
+        }
+    }
+
+}
+
+impl<'a> Iterator for Occurrences<'a> {
+    type Item = Occurrence;
+
+    fn next(&mut self) -> Option<Occurrence> {
+        loop {
+            let id = match self.next_id() {
+                Some(id) => id,
+                None     => return None,
+            };
+            if self.last == Some(id) {
+                continue;
+            }
+            self.last = Some(id);
+
+            // overrides that move an occurrence from past the end of the
+            // window back into it aren't picked up
+            if let Some((_, ref before)) = self.window {
+                if *from_local(&self.event.start_time_zone, &id) >= **before {
+                    return None;
+                }
+            }
+
+            let o = match self.event.occurrence(&LocalDate(id)) {
+                Some(o) => o,
+                None    => continue,
+            };
+            match self.window {
+                Some((ref after, ref before)) => {
+                    let (s, e) = (*o.event.start, *o.event.end);
+                    let overlaps = match s == e {
+                        true => s >= **after && s < **before,
+                        _    => s < **before && e > **after,
+                    };
+                    if overlaps {
+                        return Some(o);
+                    }
+                },
+                None => return Some(o),
+            }
+        }
+    }
+}
+
+impl CalendarEvent {
+    // the occurrence with the given recurrence id, whether or not the
+    // recurrence would actually generate it. None if it's been removed.
+    pub fn occurrence(&self, recurrence_id: &LocalDate) -> Option<Occurrence> {
+        let exception = match self.exceptions {
+            Some(ref x) => x.get(recurrence_id),
+            None        => None,
+        };
+
+        let mut e = self.clone();
+        let duration = *e.end - *e.start;
+        e.start = from_local(&self.start_time_zone, recurrence_id);
+        e.end = Date(*e.start + duration);
+        e.recurrence = None;
+        e.inclusions = None;
+        e.exceptions = None;
+
+        match exception {
+            Some(&None)        => None,
+            Some(&Some(ref p)) => Some(Occurrence { recurrence_id: recurrence_id.clone(), event: e.updated_with(p) }),
+            None               => Some(Occurrence { recurrence_id: recurrence_id.clone(), event: e }),
+        }
+    }
+
+    // every occurrence of the event, in recurrence order. this may not end.
+    pub fn occurrences<'a>(&'a self) -> Occurrences<'a> {
+        Occurrences::new(self, None)
+    }
+
+    // the occurrences that overlap the window [after, before)
+    pub fn occurrences_between<'a>(&'a self, after: &Date, before: &Date) -> Occurrences<'a> {
+        Occurrences::new(self, Some((after.clone(), before.clone())))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use chrono::{NaiveDate, NaiveDateTime};
+    use parse::FromJson;
+    use calendar_event::*;
+    use tz::{TimeZoneName, from_local};
+
+    fn recurrence(json: &str) -> Result<Recurrence,::parse::ParseError> {
+        Recurrence::from_json(&Json::from_str(json).unwrap())
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0)
+    }
+
+    fn rule(json: &str, start: NaiveDateTime, n: usize) -> Vec<NaiveDateTime> {
+        recurrence(json).unwrap().iter(&start).take(n).collect()
+    }
+
+    #[test]
+    fn last_sunday_of_the_month() {
+        // -1SU
+        assert_eq!(rule(r#"{"frequency":"monthly","byDay":[-7],"until":"1970-01-01T00:00:00"}"#, at(2017, 1, 29, 9, 0), 4),
+                   vec!(at(2017, 1, 29, 9, 0), at(2017, 2, 26, 9, 0), at(2017, 3, 26, 9, 0), at(2017, 4, 30, 9, 0)));
+    }
+
+    #[test]
+    fn nth_weekday_of_the_year() {
+        // the fourth Thursday in November
+        assert_eq!(rule(r#"{"frequency":"yearly","byMonth":[11],"byDay":[32],"until":"1970-01-01T00:00:00"}"#, at(2017, 11, 23, 12, 0), 3),
+                   vec!(at(2017, 11, 23, 12, 0), at(2018, 11, 22, 12, 0), at(2019, 11, 28, 12, 0)));
+    }
+
+    #[test]
+    fn by_set_position() {
+        // the last weekday of the month
+        assert_eq!(rule(r#"{"frequency":"monthly","byDay":[1,2,3,4,5],"bySetPosition":[-1],"until":"1970-01-01T00:00:00"}"#, at(2017, 1, 31, 9, 0), 5),
+                   vec!(at(2017, 1, 31, 9, 0), at(2017, 2, 28, 9, 0), at(2017, 3, 31, 9, 0), at(2017, 4, 28, 9, 0), at(2017, 5, 31, 9, 0)));
+
+        // the second and second-last days of the month that are Mondays or Fridays
+        assert_eq!(rule(r#"{"frequency":"monthly","byDay":[1,5],"bySetPosition":[2,-2],"until":"1970-01-01T00:00:00"}"#, at(2017, 9, 4, 9, 0), 4),
+                   vec!(at(2017, 9, 4, 9, 0), at(2017, 9, 25, 9, 0), at(2017, 10, 6, 9, 0), at(2017, 10, 27, 9, 0)));
+    }
+
+    #[test]
+    fn count_interval_and_until() {
+        assert_eq!(rule(r#"{"frequency":"weekly","interval":2,"count":3,"until":"1970-01-01T00:00:00"}"#, at(2017, 1, 2, 9, 0), 10),
+                   vec!(at(2017, 1, 2, 9, 0), at(2017, 1, 16, 9, 0), at(2017, 1, 30, 9, 0)));
+        assert_eq!(rule(r#"{"frequency":"daily","until":"2017-01-04T09:00:00"}"#, at(2017, 1, 2, 9, 0), 10),
+                   vec!(at(2017, 1, 2, 9, 0), at(2017, 1, 3, 9, 0), at(2017, 1, 4, 9, 0)));
+    }
+
+    #[test]
+    fn occurrences_keep_local_time_over_dst() {
+        let tz = TimeZoneName::new("America/New_York");
+        let mut e = CalendarEvent::default();
+        e.start = from_local(&tz, &at(2017, 3, 10, 9, 0));
+        e.end = from_local(&tz, &at(2017, 3, 10, 10, 0));
+        e.start_time_zone = tz.clone();
+        e.recurrence = Some(recurrence(r#"{"frequency":"daily","count":3,"until":"1970-01-01T00:00:00"}"#).unwrap());
+
+        let starts: Vec<NaiveDateTime> = e.occurrences().map(|o| o.event.start.naive_utc()).collect();
+        assert_eq!(starts, vec!(at(2017, 3, 10, 14, 0), at(2017, 3, 11, 14, 0), at(2017, 3, 12, 13, 0)));
+    }
+
+    #[test]
+    fn out_of_range_by_values_rejected() {
+        let bad = [
+            r#""byMinute":[60]"#, r#""bySecond":[61]"#, r#""byHour":[24]"#,
+            r#""byMonth":[0]"#, r#""byMonth":[13]"#, r#""byDate":[0]"#,
+            r#""byDate":[-32]"#, r#""byYearDay":[367]"#, r#""byWeekNo":[54]"#,
+            r#""bySetPosition":[0]"#,
+        ];
+        for b in bad.iter() {
+            let json = format!(r#"{{"frequency":"hourly","until":"1970-01-01T00:00:00",{}}}"#, b);
+            assert!(recurrence(&json).is_err(), "{} accepted", b);
+        }
+
+        let good = r#"{"frequency":"yearly","until":"1970-01-01T00:00:00","bySecond":[60],"byDate":[-31],"byYearDay":[-366],"byWeekNo":[53],"bySetPosition":[-1]}"#;
+        assert!(recurrence(good).is_ok());
+    }
+
+    #[test]
+    fn impossible_rule_gives_up() {
+        // built directly, so it isn't validated
+        let mut r = Recurrence::default();
+        r.frequency = Frequency::Secondly;
+        r.by_second = Some(vec!(60));
+        assert_eq!(r.iter(&at(2024, 1, 1, 9, 0)).nth(1), None);
+
+        r.frequency = Frequency::Daily;
+        r.by_second = None;
+        r.by_month = Some(vec!(2));
+        r.by_date = Some(vec!(30));
+        assert_eq!(r.iter(&at(2024, 1, 1, 9, 0)).nth(1), None);
+    }
+
+    #[test]
+    fn leap_day_rule_survives_the_cap() {
+        let mut r = Recurrence::default();
+        r.frequency = Frequency::Daily;
+        r.by_month = Some(vec!(2));
+        r.by_date = Some(vec!(29));
+        let v: Vec<NaiveDateTime> = r.iter(&at(2096, 2, 29, 9, 0)).take(2).collect();
+        assert_eq!(v, vec!(at(2096, 2, 29, 9, 0), at(2104, 2, 29, 9, 0)));
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::*;
+use parse::Presence::*;
+use method::{MethodError, ErrorDescription, ResponseBatch, ClientId};
+
+// result references: an argument named "#foo" whose value is a ResultReference
+// stands in for argument "foo", and gets its value from the response to an
+// earlier method call in the same batch
+
+make_prop_type!(ResultReference, ResultReferenceBuilder, "ResultReference",
+    result_of: String => "resultOf" (set_result_of, with_result_of),
+    name:      String => "name"     (set_name, with_name),
+    path:      String => "path"     (set_path, with_path)
+);
+
+
+// trait for method arguments that may contain result references
+pub trait ResolveReferences: Sized {
+    fn resolve_references(&self, responses: &ResponseBatch) -> Result<Self,MethodError>;
+}
+
+
+fn invalid_reference(desc: String) -> MethodError {
+    MethodError::InvalidResultReference(Present(ErrorDescription(desc)))
+}
+
+// unescape a single JSON pointer reference token (RFC 6901 s4)
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+fn evaluate_tokens(json: &Json, tokens: &[String]) -> Result<Json,MethodError> {
+    let (token, rest) = match tokens.split_first() {
+        Some((t, r)) => (t, r),
+        None         => return Ok(json.clone()),
+    };
+
+    match *json {
+        Json::Object(ref o) => {
+            match o.get(token) {
+                Some(v) => evaluate_tokens(v, rest),
+                None    => Err(invalid_reference(format!("property \"{}\" not found", token))),
+            }
+        },
+
+        // "*" maps the rest of the path over every item of the array, and
+        // flattens any arrays that come back
+        Json::Array(ref a) if token == "*" => {
+            let mut out = vec!();
+            for v in a.iter() {
+                match try!(evaluate_tokens(v, rest)) {
+                    Json::Array(vv) => out.extend(vv),
+                    vv              => out.push(vv),
+                }
+            }
+            Ok(Json::Array(out))
+        },
+
+        Json::Array(ref a) => {
+            match token.parse::<usize>() {
+                Ok(n) if n < a.len() => evaluate_tokens(&a[n], rest),
+                _ => Err(invalid_reference(format!("invalid array index \"{}\"", token))),
+            }
+        },
+
+        _ => Err(invalid_reference(format!("can't descend into \"{}\"", token))),
+    }
+}
+
+// evaluate a JSON pointer against a JSON value
+pub fn evaluate_pointer(json: &Json, path: &str) -> Result<Json,MethodError> {
+    if path.is_empty() {
+        return Ok(json.clone());
+    }
+    if !path.starts_with('/') {
+        return Err(invalid_reference(format!("invalid path \"{}\"", path)));
+    }
+    let tokens: Vec<String> = path[1..].split('/').map(unescape_token).collect();
+    evaluate_tokens(json, &tokens)
+}
+
+impl ResultReference {
+    pub fn new(result_of: &str, name: &str, path: &str) -> ResultReference {
+        ResultReference {
+            result_of: result_of.to_string(),
+            name:      name.to_string(),
+            path:      path.to_string(),
+            _extra:    BTreeMap::new(),
+        }
+    }
+
+    // find the response this reference points to and pull out the value
+    pub fn evaluate(&self, responses: &ResponseBatch) -> Result<Json,MethodError> {
+        let response = responses.0.iter().find(|r| r.client_id() == self.result_of);
+        match response {
+            Some(r) if r.name() == self.name =>
+                evaluate_pointer(&r.args_to_json(), &self.path),
+            Some(r) =>
+                Err(invalid_reference(format!("response \"{}\" is \"{}\", not \"{}\"", self.result_of, r.name(), self.name))),
+            None =>
+                Err(invalid_reference(format!("no response with client id \"{}\"", self.result_of))),
+        }
+    }
+}
+
+// resolve a set of references (keyed by the property they stand in for) into
+// a JSON object that the real argument values can be parsed from
+pub fn resolve(references: &BTreeMap<String,ResultReference>, responses: &ResponseBatch) -> Result<BTreeMap<String,Json>,MethodError> {
+    let mut values = BTreeMap::<String,Json>::new();
+    for (prop, r) in references.iter() {
+        values.insert(prop.clone(), try!(r.evaluate(responses)));
+    }
+    Ok(values)
+}
+
+// parse a resolved value into an argument field
+pub fn resolved_field<T>(values: &BTreeMap<String,Json>, field: &str) -> Result<T,MethodError> where T: FromJsonField {
+    match T::from_json_field(values, field) {
+        Ok(v)  => Ok(v),
+        Err(e) => Err(invalid_reference(format!("resolved value for \"{}\" is unusable: {}", field, e))),
+    }
+}
+
+// pull all the "#foo" references out of an arguments object. references for
+// properties that aren't in props are ignored, like any other unknown
+// property. a property given both as a value and a reference is an error.
+pub fn references_from_json(json: &BTreeMap<String,Json>, props: &[&str], argsname: &str) -> Result<BTreeMap<String,ResultReference>,ParseError> {
+    let mut refs = BTreeMap::<String,ResultReference>::new();
+    for (k, v) in json.iter() {
+        if !k.starts_with('#') {
+            continue;
+        }
+        let prop = &k[1..];
+        if !props.contains(&prop) {
+            continue;
+        }
+        if json.contains_key(prop) {
+            return Err(ParseError::InvalidStructure(argsname.to_string()));
+        }
+        refs.insert(prop.to_string(), try!(ResultReference::from_json(v)));
+    }
+    Ok(refs)
+}
+
+pub fn references_to_json(refs: &BTreeMap<String,ResultReference>, json: &mut BTreeMap<String,Json>) {
+    for (prop, r) in refs.iter() {
+        json.insert(format!("#{}", prop), r.to_json());
+    }
+}
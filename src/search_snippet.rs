@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::*;
+use parse::Presence::*;
+use reference;
+use reference::{ResultReference, ResolveReferences};
+use method::{MethodError, ResponseBatch};
+use message::Message;
+use message_list::*;
+
+
+make_prop_type!(SearchSnippet, SearchSnippetBuilder, "SearchSnippet",
+    message_id: String         => "messageId" (set_message_id, with_message_id),
+    subject:    Option<String> => "subject"   (set_subject, with_subject),
+    preview:    Option<String> => "preview"   (set_preview, with_preview)
+);
+
+make_method_args_type!(GetSearchSnippetsRequestArgs, "GetSearchSnippetsRequestArgs",
+    account_id:  Presence<String> => "accountId",
+    message_ids: Vec<String>      => "messageIds",
+    filter:      Presence<Filter> => "filter"
+);
+
+make_method_args_type!(GetSearchSnippetsResponseArgs, "GetSearchSnippetsResponseArgs",
+    account_id: String              => "accountId",
+    filter:     Option<Filter>      => "filter",
+    list:       Vec<SearchSnippet>  => "list",
+    not_found:  Option<Vec<String>> => "notFound"
+);
+
+
+// max size of a preview, in octets
+const PREVIEW_SIZE: usize = 255;
+
+// how much text to show before the first match in a preview, in chars
+const PREVIEW_LEAD: usize = 40;
+
+
+// the search terms from a filter that apply to the subject and to the body.
+// terms under a NOT operator are skipped, since they can't have matched.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SearchTerms {
+    pub subject: Vec<String>,
+    pub body:    Vec<String>,
+}
+
+impl SearchTerms {
+    pub fn from_condition(c: &FilterCondition) -> SearchTerms {
+        let mut terms = SearchTerms::default();
+        terms.add_condition(c);
+        terms
+    }
+
+    pub fn from_filter(f: &Filter) -> SearchTerms {
+        let mut terms = SearchTerms::default();
+        terms.add_filter(f);
+        terms
+    }
+
+    fn add_condition(&mut self, c: &FilterCondition) {
+        if let Present(ref t) = c.text {
+            self.subject.push(t.clone());
+            self.body.push(t.clone());
+        }
+        if let Present(ref t) = c.subject {
+            self.subject.push(t.clone());
+        }
+        if let Present(ref t) = c.body {
+            self.body.push(t.clone());
+        }
+    }
+
+    fn add_filter(&mut self, f: &Filter) {
+        match *f {
+            Filter::Operator(ref o) if o.operator == "NOT" => (),
+            Filter::Operator(ref o) => for ff in o.conditions.iter() { self.add_filter(ff) },
+            Filter::Condition(ref c) => self.add_condition(c),
+        }
+    }
+}
+
+
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&'  => out.push_str("&amp;"),
+            '<'  => out.push_str("&lt;"),
+            '>'  => out.push_str("&gt;"),
+            '"'  => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c    => out.push(c),
+        }
+    }
+    out
+}
+
+// very rough conversion of HTML to plain text: drop the tags, decode the
+// common entities and squash the whitespace
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<'             => { in_tag = true; text.push(' ') },
+            '>' if in_tag   => in_tag = false,
+            _   if in_tag   => (),
+            c               => text.push(c),
+        }
+    }
+    let text = text.replace("&nbsp;", " ").replace("&lt;", "<").replace("&gt;", ">")
+                   .replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// find all the (case-insensitive) occurrences of the terms in the text, as
+// non-overlapping byte ranges into the text, in order
+fn find_matches(text: &str, terms: &[String]) -> Vec<(usize,usize)> {
+    // lowercase the text a char at a time, remembering where each lowered
+    // char came from so matches can be mapped back
+    let mut lowered = String::with_capacity(text.len());
+    let mut starts = BTreeMap::<usize,usize>::new();
+    let mut ends = BTreeMap::<usize,usize>::new();
+    for (i, c) in text.char_indices() {
+        starts.insert(lowered.len(), i);
+        lowered.extend(c.to_lowercase());
+        ends.insert(lowered.len(), i + c.len_utf8());
+    }
+
+    let mut ranges = vec!();
+    for term in terms.iter().map(|t| t.to_lowercase()).filter(|t| !t.is_empty()) {
+        let mut from = 0;
+        while let Some(p) = lowered[from..].find(&term) {
+            let (s, e) = (from + p, from + p + term.len());
+            if let (Some(&ts), Some(&te)) = (starts.get(&s), ends.get(&e)) {
+                ranges.push((ts, te));
+            }
+            from = e;
+        }
+    }
+
+    // drop overlaps, preferring the earliest and then longest match
+    ranges.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    let mut out: Vec<(usize,usize)> = vec!();
+    for r in ranges.into_iter() {
+        match out.last() {
+            Some(&(_, e)) if r.0 < e => (),
+            _ => out.push(r),
+        }
+    }
+    out
+}
+
+// escape text and wrap the matched ranges in <mark> tags
+fn highlight(text: &str, matches: &[(usize,usize)]) -> String {
+    let mut out = String::new();
+    let mut pos = 0;
+    for &(s, e) in matches.iter() {
+        out.push_str(&escape_html(&text[pos..s]));
+        out.push_str("<mark>");
+        out.push_str(&escape_html(&text[s..e]));
+        out.push_str("</mark>");
+        pos = e;
+    }
+    out.push_str(&escape_html(&text[pos..]));
+    out
+}
+
+// like highlight, but only a window of the text around the first match, and
+// no more than PREVIEW_SIZE octets
+fn highlight_preview(text: &str, matches: &[(usize,usize)]) -> String {
+    let first = match matches.first() {
+        Some(&(s, _)) => s,
+        None          => 0,
+    };
+
+    // back up a little from the first match, to the start of a word if we can
+    let mut start = text[..first].char_indices().rev().nth(PREVIEW_LEAD - 1).map(|(i, _)| i).unwrap_or(0);
+    if start > 0 {
+        if let Some((sp, c)) = text[start..first].char_indices().find(|&(_, c)| c.is_whitespace()) {
+            start = start + sp + c.len_utf8();
+        }
+    }
+
+    let mut out = String::new();
+    let mut pos = start;
+    let mut end = text.len();
+    for &(s, e) in matches.iter() {
+        let before = escape_html(&text[pos..s]);
+        let mark = format!("<mark>{}</mark>", escape_html(&text[s..e]));
+        if out.len() + before.len() + mark.len() > PREVIEW_SIZE {
+            end = s;
+            break;
+        }
+        out.push_str(&before);
+        out.push_str(&mark);
+        pos = e;
+    }
+
+    // fill any remaining room with plain text, up to the next match that
+    // didn't fit
+    for c in text[pos..end].chars() {
+        let esc = escape_html(&c.to_string());
+        if out.len() + esc.len() > PREVIEW_SIZE {
+            break;
+        }
+        out.push_str(&esc);
+    }
+    out
+}
+
+// build the snippet for a single message. subject and preview are only
+// filled in if the relevant terms actually matched.
+pub fn search_snippet(m: &Message, terms: &SearchTerms) -> SearchSnippet {
+    let mut snippet = SearchSnippet::default();
+    snippet.message_id = m.id.clone();
+
+    let subject_matches = find_matches(&m.subject, &terms.subject);
+    if !subject_matches.is_empty() {
+        snippet.subject = Some(highlight(&m.subject, &subject_matches));
+    }
+
+    let bodies = vec!(
+        m.text_body.clone(),
+        m.html_body.as_ref().map(|h| html_to_text(h)),
+    );
+    for body in bodies.into_iter().filter_map(|b| b) {
+        let body_matches = find_matches(&body, &terms.body);
+        if !body_matches.is_empty() {
+            snippet.preview = Some(highlight_preview(&body, &body_matches));
+            break;
+        }
+    }
+
+    snippet
+}
+
+// answer a getSearchSnippets call, looking up messages with find
+pub fn get_search_snippets<'a,F>(args: &GetSearchSnippetsRequestArgs, find: F) -> GetSearchSnippetsResponseArgs
+    where F: Fn(&str) -> Option<&'a Message> {
+    let terms = match args.filter {
+        Present(ref f) => SearchTerms::from_filter(f),
+        Absent         => SearchTerms::default(),
+    };
+
+    let mut response = GetSearchSnippetsResponseArgs::default();
+    response.account_id = match args.account_id {
+        Present(ref a) => a.clone(),
+        Absent         => "".to_string(),
+    };
+    response.filter = args.filter.as_option().cloned();
+
+    let mut not_found = vec!();
+    for id in args.message_ids.iter() {
+        match find(id) {
+            Some(m) => response.list.push(search_snippet(m, &terms)),
+            None    => not_found.push(id.clone()),
+        }
+    }
+    response.not_found = match not_found.is_empty() {
+        true => None,
+        _    => Some(not_found),
+    };
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use message::Message;
+    use super::*;
+
+    fn preview(body: &str, term: &str) -> Option<String> {
+        let mut m = Message::default();
+        m.text_body = Some(body.to_string());
+        let mut terms = SearchTerms::default();
+        terms.body = vec!(term.to_string());
+        search_snippet(&m, &terms).preview
+    }
+
+    #[test]
+    fn preview_backs_up_to_multibyte_whitespace() {
+        let body = format!("{}\u{a0}{}needle", "a".repeat(60), "b".repeat(10));
+        assert_eq!(preview(&body, "needle"), Some("bbbbbbbbbb<mark>needle</mark>".to_string()));
+
+        let body = format!("{}\u{3000}{}needle", "a".repeat(60), "b".repeat(10));
+        assert_eq!(preview(&body, "needle"), Some("bbbbbbbbbb<mark>needle</mark>".to_string()));
+    }
+
+    #[test]
+    fn preview_of_non_ascii_text() {
+        let body = format!("{} café naïve needle ünïcode", "é".repeat(80));
+        let p = preview(&body, "needle").unwrap();
+        assert!(p.contains("<mark>needle</mark> ünïcode"));
+        assert!(p.len() <= PREVIEW_SIZE);
+    }
+}
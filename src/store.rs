@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::default::Default;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::Presence::*;
+use record;
+use record::Record;
+use method::*;
+use dispatch::RecordHandler;
+
+
+// a simple in-memory record store. every change to a single record advances
+// the state by one, and the log of changes is kept so getUpdates can answer
+// from any state since the last compaction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MemoryStore<R: Record> {
+    records:   BTreeMap<String,R>,
+    state:     u64,
+    min_state: u64,
+    changes:   Vec<Change>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Change {
+    state:     u64,
+    id:        String,
+    destroyed: bool,
+}
+
+impl<R: Record> Default for MemoryStore<R> {
+    fn default() -> MemoryStore<R> {
+        MemoryStore {
+            records:   BTreeMap::new(),
+            state:     0,
+            min_state: 0,
+            changes:   vec!(),
+        }
+    }
+}
+
+impl<R: Record> MemoryStore<R> {
+    pub fn state(&self) -> String {
+        self.state.to_string()
+    }
+
+    pub fn record(&self, id: &str) -> Option<&R> {
+        self.records.get(id)
+    }
+
+    pub fn records<'a>(&'a self) -> Values<'a,String,R> {
+        self.records.values()
+    }
+
+    fn log_change(&mut self, id: String, destroyed: bool) {
+        self.state += 1;
+        self.changes.push(Change { state: self.state, id: id, destroyed: destroyed });
+    }
+
+    // add or replace a record directly, bypassing set
+    pub fn insert(&mut self, record: R) {
+        let id = record.id();
+        self.records.insert(id.clone(), record);
+        self.log_change(id, false);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<R> {
+        let r = self.records.remove(id);
+        if r.is_some() {
+            self.log_change(id.to_string(), true);
+        }
+        r
+    }
+
+    // drop all but the newest keep changes from the log. getUpdates from a
+    // state older than what remains will get cannotCalculateChanges.
+    pub fn compact(&mut self, keep: usize) {
+        if self.changes.len() <= keep {
+            return;
+        }
+        let drop = self.changes.len() - keep;
+        self.changes.drain(0..drop);
+        self.min_state = match self.changes.first() {
+            Some(c) => c.state - 1,
+            None    => self.state,
+        };
+    }
+
+    pub fn get(&self, args: &GetRequestArgs<R>) -> Result<GetResponseArgs<R>,MethodError> {
+        let mut response = GetResponseArgs::<R>::default();
+        response.state = self.state();
+
+        if let Present(ref since) = args.since_state {
+            if *since == response.state {
+                response.list = Some(vec!());
+                return Ok(response);
+            }
+        }
+
+        let partial = |r: &R| match args.properties {
+            Present(ref props) => r.to_filtered_partial(props),
+            Absent             => r.to_partial(),
+        };
+
+        match args.ids {
+            Present(ref ids) => {
+                let mut list = vec!();
+                let mut not_found = vec!();
+                for id in ids.iter() {
+                    match self.records.get(id) {
+                        Some(r) => list.push(partial(r)),
+                        None    => not_found.push(id.clone()),
+                    }
+                }
+                response.list = Some(list);
+                response.not_found = match not_found.is_empty() {
+                    true => None,
+                    _    => Some(not_found),
+                };
+            },
+            Absent => {
+                response.list = Some(self.records.values().map(partial).collect());
+            },
+        }
+
+        Ok(response)
+    }
+
+    pub fn get_updates(&self, args: &GetUpdatesRequestArgs<R>) -> Result<GetUpdatesResponseArgs<R>,MethodError> {
+        let since = match args.since_state.parse::<u64>() {
+            Ok(n) if n >= self.min_state && n <= self.state => n,
+            _ => return Err(MethodError::CannotCalculateChanges),
+        };
+        let max = match args.max_changes {
+            Present(0) => return Err(MethodError::InvalidArguments(Present(ErrorDescription("maxChanges must be greater than 0".to_string())))),
+            Present(n) => n as usize,
+            Absent     => usize::max_value(),
+        };
+
+        // walk the log, remembering the final fate of each record, until we
+        // run out of changes or room
+        let mut seen = BTreeMap::<String,bool>::new();
+        let mut new_state = since;
+        let mut has_more = false;
+        for c in self.changes.iter().filter(|c| c.state > since) {
+            if seen.len() == max && !seen.contains_key(&c.id) {
+                has_more = true;
+                break;
+            }
+            seen.insert(c.id.clone(), c.destroyed);
+            new_state = c.state;
+        }
+
+        let mut response = GetUpdatesResponseArgs::<R>::default();
+        response.old_state = since.to_string();
+        response.new_state = new_state.to_string();
+        response.has_more_updates = has_more;
+        for (id, destroyed) in seen.into_iter() {
+            match destroyed {
+                true => response.removed.push(id),
+                _    => response.changed.push(id),
+            }
+        }
+        Ok(response)
+    }
+
+    pub fn set(&mut self, args: &SetRequestArgs<R>) -> Result<SetResponseArgs<R>,MethodError> {
+        if let Present(ref s) = args.if_in_state {
+            if *s != self.state() {
+                return Err(MethodError::StateMismatch);
+            }
+        }
+
+        let mut response = SetResponseArgs::<R>::default();
+        response.old_state = Some(self.state());
+
+        if let Present(ref create) = args.create {
+            for (cid, p) in create.iter() {
+                if let Err(e) = record::validate_create::<R>(p) {
+                    response.not_created.insert(cid.clone(), e);
+                    continue;
+                }
+                let r = R::default().updated_with(p);
+                response.created.insert(cid.clone(), r.to_filtered_partial(&server_set_properties(&r, p)));
+                self.insert(r);
+            }
+        }
+
+        if let Present(ref update) = args.update {
+            for (id, p) in update.iter() {
+                let r = match self.records.get(id) {
+                    Some(r) => match record::validate_update(r, p) {
+                        Ok(_)  => r.updated_with(p),
+                        Err(e) => {
+                            response.not_updated.insert(id.clone(), e);
+                            continue;
+                        },
+                    },
+                    None    => {
+                        response.not_updated.insert(id.clone(), SetError::new(SetErrorType::NotFound, None));
+                        continue;
+                    },
+                };
+                self.insert(r);
+                response.updated.push(id.clone());
+            }
+        }
+
+        if let Present(ref destroy) = args.destroy {
+            for id in destroy.iter() {
+                match self.remove(id) {
+                    Some(_) => response.destroyed.push(id.clone()),
+                    None    => { response.not_destroyed.insert(id.clone(), SetError::new(SetErrorType::NotFound, None)); },
+                }
+            }
+        }
+
+        response.new_state = self.state();
+        Ok(response)
+    }
+}
+
+// the properties of a newly created record that the client didn't give,
+// because the server sets them or they took a default. these go back in the
+// created response along with the id.
+fn server_set_properties<R: Record>(r: &R, p: &R::Partial) -> Vec<String> {
+    let given = match p.to_json() {
+        Json::Object(o) => o,
+        _               => BTreeMap::new(),
+    };
+    match r.to_json() {
+        Json::Object(o) => o.into_iter()
+            .map(|(k, _)| k)
+            .filter(|k| R::property_access(k).is_some() && !given.contains_key(k))
+            .collect(),
+        _ => vec!(),
+    }
+}
+
+impl<R: Record> RecordHandler<R> for MemoryStore<R> {
+    fn get(&mut self, args: &GetRequestArgs<R>) -> Result<GetResponseArgs<R>,MethodError> {
+        MemoryStore::get(self, args)
+    }
+
+    fn get_updates(&mut self, args: &GetUpdatesRequestArgs<R>) -> Result<GetUpdatesResponseArgs<R>,MethodError> {
+        MemoryStore::get_updates(self, args)
+    }
+
+    fn set(&mut self, args: &SetRequestArgs<R>) -> Result<SetResponseArgs<R>,MethodError> {
+        MemoryStore::set(self, args)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::{Json,ToJson};
+    use mailbox::{Mailbox, PartialMailbox};
+    use thread::{Thread, PartialThread};
+    use method::SetRequestArgs;
+    use parse::FromJson;
+    use super::*;
+
+    fn create<R: Record>(store: &mut MemoryStore<R>, p: R::Partial) -> Json {
+        let mut args = SetRequestArgs::<R>::default();
+        let mut create = BTreeMap::new();
+        create.insert("c1".to_string(), p);
+        args.create = Present(create);
+        let response = store.set(&args).unwrap();
+        response.created["c1"].to_json()
+    }
+
+    #[test]
+    fn created_has_server_set_properties() {
+        let mut store = MemoryStore::<Mailbox>::default();
+        let mut p = PartialMailbox::default();
+        p.set_name("Inbox".to_string());
+        let created = create(&mut store, p);
+        let created = created.as_object().unwrap();
+
+        let id = created["id"].as_string().unwrap();
+        assert!(store.record(id).is_some());
+        assert_eq!(created.get("totalMessages"), Some(&Json::U64(0)));
+        assert_eq!(created.get("mayRename"), Some(&Json::Boolean(false)));
+        // defaulted, since the client left it out
+        assert_eq!(created.get("sortOrder"), Some(&Json::U64(0)));
+        // the client knows what it gave
+        assert!(created.get("name").is_none());
+    }
+
+    #[test]
+    fn created_thread_has_message_ids() {
+        let mut store = MemoryStore::<Thread>::default();
+        let created = create(&mut store, PartialThread::default());
+        assert_eq!(created.as_object().unwrap().get("messageIds"), Some(&Json::Array(vec!())));
+    }
+
+    #[test]
+    fn null_for_non_nullable_property_is_invalid() {
+        let mut store = MemoryStore::<Mailbox>::default();
+        let mut p = PartialMailbox::default();
+        p.set_name("Inbox".to_string());
+        let created = create(&mut store, p);
+        let id = created.as_object().unwrap()["id"].as_string().unwrap().to_string();
+
+        let json = Json::from_str(&format!(r#"{{
+            "create": {{"c1": {{"name": null}}, "c2": {{"name": "Sent"}}}},
+            "update": {{"{}": {{"sortOrder": null, "parentId": null}}}}
+        }}"#, id)).unwrap();
+        let args = SetRequestArgs::<Mailbox>::from_json(&json).unwrap();
+        let response = store.set(&args).unwrap();
+        assert_eq!(response.not_created.get("c1"), Some(&SetError::invalid_properties(vec!("name".to_string()))));
+        assert!(response.created.contains_key("c2"));
+        assert_eq!(response.not_updated.get(&id), Some(&SetError::invalid_properties(vec!("sortOrder".to_string()))));
+    }
+}
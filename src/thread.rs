@@ -0,0 +1,14 @@
+use std::collections::BTreeMap;
+use std::string::ToString;
+use std::default::Default;
+use rustc_serialize::json::{Json,ToJson};
+
+use parse::*;
+use parse::Presence::*;
+use record;
+use record::{Record, PartialRecord};
+
+
+make_record_type!(Thread, PartialThread, "Thread",
+    message_ids: Vec<String> => "messageIds" (set_message_ids, with_message_ids) [server]
+);
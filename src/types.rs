@@ -9,11 +9,11 @@ use parse::*;
 
 // subtypes shared across record types
 
-make_prop_type!(File, "File",
-    blob_id:  String         => "blobId",
-    typ:      Option<String> => "type",
-    name:     Option<String> => "name",
-    size:     Option<u64>    => "size"
+make_prop_type!(File, FileBuilder, "File",
+    blob_id: String         => "blobId" (set_blob_id, with_blob_id),
+    typ:     Option<String> => "type"   (set_typ, with_typ),
+    name:    Option<String> => "name"   (set_name, with_name),
+    size:    Option<u64>    => "size"   (set_size, with_size)
 );
 
 
@@ -0,0 +1,711 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::rc::Rc;
+use rustc_serialize::json::{Json,ToJson};
+use chrono::{DateTime, UTC, NaiveDate, NaiveDateTime, Datelike, Timelike, Duration};
+
+use parse::*;
+use types::Date;
+
+// Olson time zones, read from the system zoneinfo database (TZif files, RFC
+// 8536), and conversion of event times between UTC and local wall time.
+// without a database, the common zones still work from a built-in table of
+// their current rules, though times before the last rule change may be off.
+
+
+const ZONEINFO_DIR: &'static str = "/usr/share/zoneinfo";
+
+// zones that are always UTC
+const UTC_ZONES: [&'static str; 4] = ["UTC", "Etc/UTC", "GMT", "Etc/GMT"];
+
+// the POSIX TZ rules of the zones we know without a zoneinfo database, as
+// found in the footers of their TZif files
+const BUILTIN_ZONES: [(&'static str, &'static str); 67] = [
+    ("America/New_York",               "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Detroit",                "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Toronto",                "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Chicago",                "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Winnipeg",               "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Denver",                 "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Edmonton",               "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix",                "MST7"),
+    ("America/Los_Angeles",            "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Vancouver",              "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Anchorage",              "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Halifax",                "AST4ADT,M3.2.0,M11.1.0"),
+    ("America/St_Johns",               "NST3:30NDT,M3.2.0,M11.1.0"),
+    ("America/Mexico_City",            "CST6"),
+    ("America/Bogota",                 "<-05>5"),
+    ("America/Lima",                   "<-05>5"),
+    ("America/Caracas",                "<-04>4"),
+    ("America/Santiago",               "<-04>4<-03>,M9.1.6/24,M4.1.6/24"),
+    ("America/Sao_Paulo",              "<-03>3"),
+    ("America/Argentina/Buenos_Aires", "<-03>3"),
+    ("Pacific/Honolulu",               "HST10"),
+    ("Pacific/Auckland",               "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+    ("Pacific/Fiji",                   "<+12>-12"),
+    ("Atlantic/Reykjavik",             "GMT0"),
+    ("Europe/London",                  "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Dublin",                  "GMT0IST,M3.5.0/1,M10.5.0"),
+    ("Europe/Lisbon",                  "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/Amsterdam",               "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Berlin",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Brussels",                "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Budapest",                "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Copenhagen",              "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Madrid",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Oslo",                    "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Paris",                   "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Prague",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Rome",                    "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Stockholm",               "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Vienna",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Warsaw",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Zurich",                  "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Athens",                  "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Bucharest",               "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Helsinki",                "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Kiev",                    "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Kyiv",                    "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Istanbul",                "<+03>-3"),
+    ("Europe/Moscow",                  "MSK-3"),
+    ("Africa/Cairo",                   "EET-2EEST,M4.5.5/0,M10.5.4/24"),
+    ("Africa/Johannesburg",            "SAST-2"),
+    ("Africa/Lagos",                   "WAT-1"),
+    ("Africa/Nairobi",                 "EAT-3"),
+    ("Asia/Jerusalem",                 "IST-2IDT,M3.4.4/26,M10.5.0"),
+    ("Asia/Dubai",                     "<+04>-4"),
+    ("Asia/Karachi",                   "PKT-5"),
+    ("Asia/Kolkata",                   "IST-5:30"),
+    ("Asia/Dhaka",                     "<+06>-6"),
+    ("Asia/Bangkok",                   "<+07>-7"),
+    ("Asia/Jakarta",                   "WIB-7"),
+    ("Asia/Shanghai",                  "CST-8"),
+    ("Asia/Hong_Kong",                 "HKT-8"),
+    ("Asia/Singapore",                 "<+08>-8"),
+    ("Asia/Seoul",                     "KST-9"),
+    ("Asia/Tokyo",                     "JST-9"),
+    ("Australia/Perth",                "AWST-8"),
+    ("Australia/Brisbane",             "AEST-10"),
+    ("Australia/Sydney",               "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+];
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct LocalTimeType {
+    pub(crate) offset: i64,
+    pub(crate) is_dst: bool,
+    pub(crate) abbrev: String,
+}
+
+// a day in a POSIX TZ rule
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum RuleDay {
+    // day 1-365, never counting Feb 29
+    Julian(u32),
+    // day 0-365, counting Feb 29
+    Ordinal(u32),
+    // month, week 1-5 (5 meaning last), day of week (0 = Sunday)
+    MonthWeekDay(u32, u32, u32),
+}
+
+// the DST rule for times after the last transition, from the TZif footer
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct PosixRule {
+    pub(crate) std:        LocalTimeType,
+    pub(crate) dst:        Option<LocalTimeType>,
+    pub(crate) start:      RuleDay,
+    pub(crate) start_time: i64,
+    pub(crate) end:        RuleDay,
+    pub(crate) end_time:   i64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimeZone {
+    pub(crate) name:        String,
+    pub(crate) transitions: Vec<(i64,usize)>,
+    pub(crate) types:       Vec<LocalTimeType>,
+    pub(crate) rule:        Option<PosixRule>,
+}
+
+
+fn be(b: &[u8]) -> i64 {
+    let n = b.iter().fold(0u64, |n, &b| n << 8 | b as u64);
+    match b.len() {
+        4 => n as u32 as i32 as i64,
+        _ => n as i64,
+    }
+}
+
+// the pieces of a POSIX TZ string, eg "EST5EDT,M3.2.0,M11.1.0"
+struct PosixParser<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl<'a> PosixParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.i).cloned()
+    }
+
+    fn name(&mut self) -> Result<String,()> {
+        let start = self.i;
+        let name = match self.peek() {
+            Some(b'<') => {
+                while self.peek().map(|c| c != b'>').unwrap_or(false) {
+                    self.i += 1;
+                }
+                self.i += 1;
+                &self.s[start+1..self.i-1]
+            },
+            _ => {
+                while self.peek().map(|c| (c as char).is_ascii_alphabetic()).unwrap_or(false) {
+                    self.i += 1;
+                }
+                &self.s[start..self.i]
+            },
+        };
+        match name.len() >= 3 {
+            true => Ok(String::from_utf8_lossy(name).into_owned()),
+            _    => Err(()),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64,()> {
+        let start = self.i;
+        while self.peek().map(|c| (c as char).is_ascii_digit()).unwrap_or(false) {
+            self.i += 1;
+        }
+        ::std::str::from_utf8(&self.s[start..self.i]).map_err(|_| ()).and_then(|n| n.parse().map_err(|_| ()))
+    }
+
+    // [+-]hh[:mm[:ss]], in seconds
+    fn time(&mut self) -> Result<i64,()> {
+        let sign = match self.peek() {
+            Some(b'-') => { self.i += 1; -1 },
+            Some(b'+') => { self.i += 1; 1 },
+            _          => 1,
+        };
+        let mut secs = try!(self.number()) * 3600;
+        for &mul in [60, 1].iter() {
+            if self.peek() != Some(b':') {
+                break;
+            }
+            self.i += 1;
+            secs += try!(self.number()) * mul;
+        }
+        Ok(sign * secs)
+    }
+
+    // a rule day with its optional /time, which defaults to 02:00
+    fn rule(&mut self) -> Result<(RuleDay,i64),()> {
+        let day = match self.peek() {
+            Some(b'J') => {
+                self.i += 1;
+                RuleDay::Julian(try!(self.number()) as u32)
+            },
+            Some(b'M') => {
+                self.i += 1;
+                let m = try!(self.number()) as u32;
+                self.i += 1;
+                let w = try!(self.number()) as u32;
+                self.i += 1;
+                let d = try!(self.number()) as u32;
+                RuleDay::MonthWeekDay(m, w, d)
+            },
+            _ => RuleDay::Ordinal(try!(self.number()) as u32),
+        };
+        let time = match self.peek() {
+            Some(b'/') => {
+                self.i += 1;
+                try!(self.time())
+            },
+            _ => 7200,
+        };
+        Ok((day, time))
+    }
+
+    fn parse(s: &str) -> Option<PosixRule> {
+        PosixParser { s: s.as_bytes(), i: 0 }.posix_rule().ok()
+    }
+
+    fn posix_rule(&mut self) -> Result<PosixRule,()> {
+        let std_abbrev = try!(self.name());
+        // POSIX offsets are west of Greenwich
+        let std_offset = -try!(self.time());
+        let std = LocalTimeType { offset: std_offset, is_dst: false, abbrev: std_abbrev };
+
+        if self.peek().is_none() {
+            return Ok(PosixRule {
+                std:        std,
+                dst:        None,
+                start:      RuleDay::Julian(1),
+                start_time: 0,
+                end:        RuleDay::Julian(1),
+                end_time:   0,
+            });
+        }
+
+        let dst_abbrev = try!(self.name());
+        let dst_offset = match self.peek() {
+            Some(b',') | None => std_offset + 3600,
+            _                 => -try!(self.time()),
+        };
+        if self.peek() != Some(b',') {
+            return Err(());
+        }
+        self.i += 1;
+        let (start, start_time) = try!(self.rule());
+        if self.peek() != Some(b',') {
+            return Err(());
+        }
+        self.i += 1;
+        let (end, end_time) = try!(self.rule());
+
+        Ok(PosixRule {
+            std:        std,
+            dst:        Some(LocalTimeType { offset: dst_offset, is_dst: true, abbrev: dst_abbrev }),
+            start:      start,
+            start_time: start_time,
+            end:        end,
+            end_time:   end_time,
+        })
+    }
+}
+
+impl RuleDay {
+    // the date this rule picks out in a year
+    pub(crate) fn date(&self, year: i32) -> NaiveDate {
+        match *self {
+            RuleDay::Julian(n) => {
+                let jan1 = NaiveDate::from_ymd(year, 1, 1);
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                let n = n.max(1).min(365) as i64;
+                jan1 + Duration::days(match leap && n >= 60 { true => n, _ => n - 1 })
+            },
+            RuleDay::Ordinal(n) => NaiveDate::from_ymd(year, 1, 1) + Duration::days(n.min(365) as i64),
+            RuleDay::MonthWeekDay(m, w, d) => {
+                let m = m.max(1).min(12);
+                let first = NaiveDate::from_ymd(year, m, 1);
+                let offset = (d + 7 - first.weekday().num_days_from_sunday()) % 7;
+                let mut day = first + Duration::days((offset + (w.max(1) - 1) * 7) as i64);
+                while day.month() != m {
+                    day = day - Duration::days(7);
+                }
+                day
+            },
+        }
+    }
+}
+
+impl PosixRule {
+    fn local_type(&self, t: i64) -> &LocalTimeType {
+        let dst = match self.dst {
+            Some(ref dst) => dst,
+            None          => return &self.std,
+        };
+
+        // transitions are given in the local time in force before them
+        let year = NaiveDateTime::from_timestamp(t + self.std.offset, 0).year();
+        let start = self.start.date(year).and_hms(0, 0, 0).timestamp() + self.start_time - self.std.offset;
+        let end = self.end.date(year).and_hms(0, 0, 0).timestamp() + self.end_time - dst.offset;
+        let in_dst = match start < end {
+            true => t >= start && t < end,
+            // southern hemisphere: DST spans the new year
+            _    => !(t >= end && t < start),
+        };
+        match in_dst {
+            true => dst,
+            _    => &self.std,
+        }
+    }
+}
+
+impl TimeZone {
+    pub fn fixed(name: &str, offset: i64) -> TimeZone {
+        TimeZone {
+            name:        name.to_string(),
+            transitions: vec!(),
+            types:       vec!(LocalTimeType { offset: offset, is_dst: false, abbrev: name.to_string() }),
+            rule:        None,
+        }
+    }
+
+    // a zone that follows a POSIX TZ rule at all times
+    pub fn from_posix(name: &str, rule: &str) -> Option<TimeZone> {
+        PosixParser::parse(rule).map(|rule| TimeZone {
+            name:        name.to_string(),
+            transitions: vec!(),
+            types:       vec!(rule.std.clone()),
+            rule:        Some(rule),
+        })
+    }
+
+    // parse the contents of a TZif file
+    pub fn parse(name: &str, data: &[u8]) -> Option<TimeZone> {
+        if data.len() < 44 || &data[..4] != b"TZif" {
+            return None;
+        }
+        let version = data[4];
+
+        // the counts, then the size of the data block they describe
+        let counts = |h: &[u8]| -> Vec<usize> { (0..6).map(|i| be(&h[20+i*4..24+i*4]) as usize).collect() };
+        let block_len = |c: &[usize], t: usize| c[3] * t + c[3] + c[4] * 6 + c[5] + c[2] * (t + 4) + c[1] + c[0];
+
+        let c1 = counts(&data[..44]);
+        let v1_len = block_len(&c1, 4);
+
+        // use the 64-bit data from version 2 on
+        let (c, block, time_size) = match version >= b'2' && data.len() >= 44 + v1_len + 44 {
+            true => {
+                let h2 = &data[44+v1_len..];
+                if &h2[..4] != b"TZif" {
+                    return None;
+                }
+                (counts(&h2[..44]), &h2[44..], 8)
+            },
+            _ => (c1, &data[44..], 4),
+        };
+        let (isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt) = (c[0], c[1], c[2], c[3], c[4], c[5]);
+        let len = block_len(&c, time_size);
+        if block.len() < len || typecnt == 0 {
+            return None;
+        }
+        let _ = (isutcnt, isstdcnt, leapcnt);
+
+        let times = &block[..timecnt*time_size];
+        let indices = &block[timecnt*time_size..timecnt*(time_size+1)];
+        let infos = &block[timecnt*(time_size+1)..timecnt*(time_size+1)+typecnt*6];
+        let chars = &block[timecnt*(time_size+1)+typecnt*6..timecnt*(time_size+1)+typecnt*6+charcnt];
+
+        let types: Vec<LocalTimeType> = infos.chunks(6).map(|i| {
+            let abbrev = chars.get(i[5] as usize..).unwrap_or(&[]);
+            let end = abbrev.iter().position(|&c| c == 0).unwrap_or(abbrev.len());
+            LocalTimeType {
+                offset: be(&i[..4]),
+                is_dst: i[4] != 0,
+                abbrev: String::from_utf8_lossy(&abbrev[..end]).into_owned(),
+            }
+        }).collect();
+
+        let transitions: Vec<(i64,usize)> = times.chunks(time_size).zip(indices.iter())
+            .map(|(t, &i)| (be(t), (i as usize).min(typecnt - 1)))
+            .collect();
+
+        // the footer is a POSIX TZ string between newlines
+        let rule = match time_size {
+            8 => {
+                let footer = &block[len..];
+                match footer.first() {
+                    Some(&b'\n') => {
+                        let end = footer[1..].iter().position(|&c| c == b'\n').unwrap_or(footer.len() - 1);
+                        PosixParser::parse(&String::from_utf8_lossy(&footer[1..end+1]))
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+
+        Some(TimeZone {
+            name:        name.to_string(),
+            transitions: transitions,
+            types:       types,
+            rule:        rule,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn local_type(&self, t: i64) -> &LocalTimeType {
+        match self.transitions.last() {
+            Some(&(last, _)) if t >= last && self.rule.is_some() => return self.rule.as_ref().unwrap().local_type(t),
+            None if self.rule.is_some() => return self.rule.as_ref().unwrap().local_type(t),
+            _ => (),
+        }
+        match self.transitions.binary_search_by(|&(tt, _)| tt.cmp(&t)) {
+            Ok(i)  => &self.types[self.transitions[i].1],
+            Err(0) => &self.types[0],
+            Err(i) => &self.types[self.transitions[i-1].1],
+        }
+    }
+
+    // the offset from UTC, in seconds, at a UTC time in seconds
+    pub fn offset_at(&self, t: i64) -> i64 {
+        self.local_type(t).offset
+    }
+
+    pub fn to_local(&self, d: &DateTime<UTC>) -> NaiveDateTime {
+        d.naive_utc() + Duration::seconds(self.offset_at(d.timestamp()))
+    }
+
+    // local times that happen twice (when the clocks go back) are taken as
+    // the first; ones that don't happen at all (when they go forward) use
+    // the offset from before the change, as RFC 5545 says
+    pub fn from_local(&self, local: &NaiveDateTime) -> DateTime<UTC> {
+        let l = local.timestamp();
+        let before = self.offset_at(l - 86400);
+        let after = self.offset_at(l + 86400);
+        let valid: Vec<i64> = [before, after].iter()
+            .map(|&off| l - off)
+            .filter(|&u| self.offset_at(u) == l - u)
+            .collect();
+        let u = valid.into_iter().min().unwrap_or(l - before);
+        DateTime::<UTC>::from_utc(NaiveDateTime::from_timestamp(u, local.nanosecond()), UTC)
+    }
+}
+
+
+fn zoneinfo_dir() -> PathBuf {
+    match env::var("TZDIR") {
+        Ok(d) => PathBuf::from(d),
+        _     => PathBuf::from(ZONEINFO_DIR),
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('/') && !name.split('/').any(|p| p.is_empty() || p == "." || p == "..")
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c))
+}
+
+fn load(name: &str) -> Option<TimeZone> {
+    if !valid_name(name) {
+        return None;
+    }
+    let mut data = vec!();
+    let path = zoneinfo_dir().join(name);
+    let zone = match File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+        Ok(_) => TimeZone::parse(name, &data),
+        _     => None,
+    };
+    zone.or_else(|| builtin(name))
+}
+
+// a zone from the built-in tables, for when the database doesn't have it
+fn builtin(name: &str) -> Option<TimeZone> {
+    if UTC_ZONES.contains(&name) {
+        return Some(TimeZone::fixed(name, 0));
+    }
+    BUILTIN_ZONES.iter().find(|&&(n, _)| n == name).and_then(|&(_, rule)| TimeZone::from_posix(name, rule))
+}
+
+thread_local!(static ZONES: RefCell<BTreeMap<String,Option<Rc<TimeZone>>>> = RefCell::new(BTreeMap::new()));
+
+// look up a zone by its Olson name. zones are loaded once and cached.
+pub fn zone(name: &str) -> Option<Rc<TimeZone>> {
+    ZONES.with(|zones| {
+        zones.borrow_mut().entry(name.to_string()).or_insert_with(|| load(name).map(Rc::new)).clone()
+    })
+}
+
+
+// the name of a zone we know about, for the time zone properties of events
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TimeZoneName(String);
+
+impl TimeZoneName {
+    pub fn new(name: &str) -> Option<TimeZoneName> {
+        zone(name).map(|_| TimeZoneName(name.to_string()))
+    }
+
+    pub fn utc() -> TimeZoneName {
+        TimeZoneName("Etc/UTC".to_string())
+    }
+
+    pub fn is_utc(&self) -> bool {
+        UTC_ZONES.contains(&self.0.as_ref())
+    }
+
+    pub fn zone(&self) -> Rc<TimeZone> {
+        zone(&self.0).unwrap_or_else(|| Rc::new(TimeZone::fixed(&self.0, 0)))
+    }
+}
+
+impl Deref for TimeZoneName {
+    type Target = str;
+    fn deref<'a>(&'a self) -> &'a str {
+        &self.0
+    }
+}
+
+impl ToJson for TimeZoneName {
+    fn to_json(&self) -> Json {
+        Json::String(self.0.clone())
+    }
+}
+
+impl FromJson for TimeZoneName {
+    fn from_json(json: &Json) -> Result<TimeZoneName,ParseError> {
+        match *json {
+            Json::String(ref v) => match TimeZoneName::new(v) {
+                Some(tz) => Ok(tz),
+                None     => Err(ParseError::InvalidStructure("TimeZoneName".to_string())),
+            },
+            _ => Err(ParseError::InvalidJsonType("TimeZoneName".to_string())),
+        }
+    }
+}
+
+
+// conversions between event times and local wall time in an event's zone.
+// events without a zone are floating, and their local time is taken to be
+// the same as UTC.
+pub fn to_local(tz: &Option<TimeZoneName>, d: &Date) -> NaiveDateTime {
+    match *tz {
+        Some(ref tz) => tz.zone().to_local(d),
+        None         => d.naive_utc(),
+    }
+}
+
+pub fn from_local(tz: &Option<TimeZoneName>, local: &NaiveDateTime) -> Date {
+    match *tz {
+        Some(ref tz) => Date(tz.zone().from_local(local)),
+        None         => Date(DateTime::<UTC>::from_utc(local.clone(), UTC)),
+    }
+}
+
+// an event time, with floating times taken to be in the zone tz. zone is
+// the event's own zone for the time.
+pub fn pin_floating(d: &Date, zone: &Option<TimeZoneName>, tz: &Option<TimeZoneName>) -> Date {
+    match *zone {
+        Some(_) => d.clone(),
+        None    => from_local(tz, &d.naive_utc()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::*;
+
+    fn new_york() -> TimeZone {
+        builtin("America/New_York").unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, mi: u32) -> DateTime<UTC> {
+        DateTime::<UTC>::from_utc(NaiveDate::from_ymd(y, m, d).and_hms(h, mi, 0), UTC)
+    }
+
+    fn local(y: i32, m: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, mi, 0)
+    }
+
+    // a version 2 TZif file with the given transitions and footer
+    fn tzif(transitions: &[(i64,u8)], types: &[(i32,bool,&str)], footer: &str) -> Vec<u8> {
+        let header = |timecnt: usize, typecnt: usize, charcnt: usize| -> Vec<u8> {
+            let mut h = b"TZif2".to_vec();
+            h.extend(vec!(0; 15));
+            for &n in [0, 0, 0, timecnt, typecnt, charcnt].iter() {
+                h.extend((n as u32).to_be_bytes().iter());
+            }
+            h
+        };
+        let chars: Vec<u8> = types.iter().flat_map(|&(_, _, a)| a.bytes().chain(Some(0))).collect();
+
+        // an empty version 1 block, which the version 2 data replaces
+        let mut data = header(0, 1, 1);
+        data.extend(vec!(0; 7));
+
+        data.extend(header(transitions.len(), types.len(), chars.len()));
+        for &(t, _) in transitions.iter() {
+            data.extend(t.to_be_bytes().iter());
+        }
+        data.extend(transitions.iter().map(|&(_, i)| i));
+        let mut pos = 0;
+        for &(off, dst, abbrev) in types.iter() {
+            data.extend(off.to_be_bytes().iter());
+            data.push(dst as u8);
+            data.push(pos as u8);
+            pos += abbrev.len() + 1;
+        }
+        data.extend(chars);
+        data.extend(format!("\n{}\n", footer).into_bytes());
+        data
+    }
+
+    #[test]
+    fn posix_rules() {
+        let r = PosixParser::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(r.std.offset, 36000);
+        assert_eq!(r.dst.unwrap().offset, 39600);
+        assert_eq!(r.start, RuleDay::MonthWeekDay(10, 1, 0));
+        assert_eq!(r.end_time, 3 * 3600);
+
+        let r = PosixParser::parse("<-03>3").unwrap();
+        assert_eq!((r.std.abbrev.as_ref(), r.std.offset, r.dst), ("-03", -10800, None));
+        assert_eq!(PosixParser::parse("NST3:30NDT,M3.2.0,M11.1.0").unwrap().std.offset, -12600);
+
+        assert_eq!(PosixParser::parse("EST5EDT,M3.2.0"), None);
+        assert_eq!(PosixParser::parse("X5"), None);
+    }
+
+    #[test]
+    fn builtin_zones_all_parse() {
+        for &(name, _) in BUILTIN_ZONES.iter() {
+            assert!(builtin(name).is_some(), "{}", name);
+        }
+        assert!(builtin("Mars/Olympus_Mons").is_none());
+        assert_eq!(builtin("Etc/UTC").unwrap().offset_at(0), 0);
+    }
+
+    #[test]
+    fn dst_gap() {
+        // 02:30 doesn't happen on 2017-03-12; it takes the offset from before
+        let tz = new_york();
+        let u = tz.from_local(&local(2017, 3, 12, 2, 30));
+        assert_eq!(u, utc(2017, 3, 12, 7, 30));
+        assert_eq!(tz.to_local(&u), local(2017, 3, 12, 3, 30));
+    }
+
+    #[test]
+    fn dst_overlap() {
+        // 01:30 happens twice on 2017-11-05; it's taken as the first
+        let tz = new_york();
+        assert_eq!(tz.from_local(&local(2017, 11, 5, 1, 30)), utc(2017, 11, 5, 5, 30));
+        assert_eq!(tz.to_local(&utc(2017, 11, 5, 6, 30)), local(2017, 11, 5, 1, 30));
+        assert_eq!(tz.from_local(&local(2017, 11, 5, 2, 30)), utc(2017, 11, 5, 7, 30));
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        let tz = builtin("Australia/Sydney").unwrap();
+        assert_eq!(tz.offset_at(utc(2017, 1, 15, 0, 0).timestamp()), 11 * 3600);
+        assert_eq!(tz.offset_at(utc(2017, 7, 15, 0, 0).timestamp()), 10 * 3600);
+        // clocks go back at 03:00 on 2017-04-02
+        assert_eq!(tz.from_local(&local(2017, 4, 2, 2, 30)), utc(2017, 4, 1, 15, 30));
+    }
+
+    #[test]
+    fn tzif_transitions_then_footer() {
+        // a made-up zone that was +01:00 until 2000, then follows a rule
+        let data = tzif(&[(-1000000000, 0), (946684800, 1)],
+                        &[(3600, false, "OLD"), (-18000, false, "EST"), (-14400, true, "EDT")],
+                        "EST5EDT,M3.2.0,M11.1.0");
+        let tz = TimeZone::parse("Test/Zone", &data).unwrap();
+        assert_eq!(tz.transitions.len(), 2);
+        assert_eq!(tz.offset_at(0), 3600);
+        assert_eq!(tz.local_type(0).abbrev, "OLD");
+        assert_eq!(tz.offset_at(utc(2017, 1, 15, 0, 0).timestamp()), -18000);
+        assert_eq!(tz.offset_at(utc(2017, 7, 15, 0, 0).timestamp()), -14400);
+
+        assert_eq!(TimeZone::parse("Test/Zone", &data[..50]), None);
+        assert_eq!(TimeZone::parse("Test/Zone", b"not a tzif file at all, just some text here"), None);
+    }
+
+    #[test]
+    fn zone_names() {
+        assert!(!valid_name("../etc/passwd"));
+        assert!(!valid_name("/etc/passwd"));
+        assert!(valid_name("America/Argentina/Buenos_Aires"));
+        assert!(TimeZoneName::new("America/New_York").is_some());
+        assert!(TimeZoneName::new("Nowhere/Special").is_none());
+    }
+}
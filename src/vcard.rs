@@ -0,0 +1,534 @@
+use std::collections::BTreeMap;
+
+use parse::ParseError;
+use record;
+use types::File;
+use contact::*;
+use contact_group::ContactGroup;
+use content_line::*;
+
+// vCard 3.0 (RFC 2426) and 4.0 (RFC 6350) import and export for contacts
+// and contact groups
+
+
+const PRODID: &'static str = "-//jmap-rs//EN";
+
+// Apple clients write year-less dates in 3.0 with this placeholder year
+const OMIT_YEAR: &'static str = "1604";
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum VCardVersion {
+    V3,
+    V4,
+}
+
+
+// ids are often given as urn:uuid: URIs
+fn strip_urn(s: &str) -> String {
+    match s.get(..9) {
+        Some(p) if p.eq_ignore_ascii_case("urn:uuid:") => s[9..].to_string(),
+        _ => s.to_string(),
+    }
+}
+
+fn strip_scheme(s: &str, scheme: &str) -> String {
+    match s.get(..scheme.len()) {
+        Some(p) if p.eq_ignore_ascii_case(scheme) => s[scheme.len()..].to_string(),
+        _ => s.to_string(),
+    }
+}
+
+// the unescaped fields of a structured value like N or ADR
+fn fields(v: &str, n: usize) -> Vec<String> {
+    let mut f: Vec<String> = split_escaped(v, ';').iter().map(|s| unescape_text(s)).collect();
+    f.resize(n, "".to_string());
+    f
+}
+
+fn types(cl: &ContentLine) -> Vec<String> {
+    cl.param_values("TYPE").iter().flat_map(|t| t.split(',')).map(|t| t.trim().to_lowercase()).collect()
+}
+
+fn is_pref(cl: &ContentLine) -> bool {
+    cl.param("PREF").is_some() || types(cl).iter().any(|t| t == "pref")
+}
+
+// BDAY and ANNIVERSARY in any of the forms we see: 19960415, 1996-04-15,
+// --0415, --04-15, ---15, and with a time on the end
+fn parse_option_date(cl: &ContentLine) -> Option<OptionDate> {
+    let v = cl.value.split('T').next().unwrap_or("").trim();
+    let (y, md) = match v.starts_with("--") {
+        true => ("", &v[2..]),
+        _ => match v.contains('-') {
+            true  => (v.get(..4).unwrap_or(""), v.get(5..).unwrap_or("")),
+            _     => (v.get(..4).unwrap_or(""), v.get(4..).unwrap_or("")),
+        },
+    };
+    // anything but digits here isn't a date, and slicing it by byte could
+    // split a character
+    let md = md.replace("-", "");
+    let (m, d) = match md.len() {
+        _ if !md.bytes().all(|b| b.is_ascii_digit()) => ("", ""),
+        4 => (&md[..2], &md[2..]),
+        2 if v.starts_with("---") => ("", &md[..]),
+        2 => (&md[..], ""),
+        _ => ("", ""),
+    };
+
+    let y = match cl.param("X-APPLE-OMIT-YEAR") {
+        Some(omit) if omit == y => None,
+        _ => y.parse::<u16>().ok().and_then(|y| match y { 0 => None, y => Some(y) }),
+    };
+    let m = m.parse::<u8>().ok().and_then(|m| match m { 1..=12 => Some(m), _ => None });
+    let d = d.parse::<u8>().ok().and_then(|d| match d { 1..=31 => Some(d), _ => None });
+    match (y, m, d) {
+        (None, None, None) => None,
+        (y, m, d)          => Some(OptionDate { y: y, m: m, d: d }),
+    }
+}
+
+// the value and any extra parameters for an OptionDate in the given version
+fn format_option_date(d: &OptionDate, version: VCardVersion) -> Option<(String,Option<(&'static str,&'static str)>)> {
+    let m = d.m.map(|m| format!("{:02}", m));
+    let dd = d.d.map(|d| format!("{:02}", d));
+    match (version, d.y, m, dd) {
+        (_, None, None, None) => None,
+        (VCardVersion::V4, Some(y), Some(m), Some(d)) => Some((format!("{:04}{}{}", y, m, d), None)),
+        (VCardVersion::V4, Some(y), Some(m), None)    => Some((format!("{:04}-{}", y, m), None)),
+        (VCardVersion::V4, Some(y), None, _)          => Some((format!("{:04}", y), None)),
+        (VCardVersion::V4, None, Some(m), Some(d))    => Some((format!("--{}{}", m, d), None)),
+        (VCardVersion::V4, None, Some(m), None)       => Some((format!("--{}", m), None)),
+        (VCardVersion::V4, None, None, Some(d))       => Some((format!("---{}", d), None)),
+        (VCardVersion::V3, Some(y), m, d) => Some((format!("{:04}-{}-{}", y, m.unwrap_or("01".to_string()), d.unwrap_or("01".to_string())), None)),
+        (VCardVersion::V3, None, m, d) => {
+            Some((format!("{}-{}-{}", OMIT_YEAR, m.unwrap_or("01".to_string()), d.unwrap_or("01".to_string())),
+                  Some(("X-APPLE-OMIT-YEAR", OMIT_YEAR))))
+        },
+    }
+}
+
+
+fn email_type(t: &[String]) -> EmailType {
+    match t {
+        t if t.iter().any(|t| t == "work") => EmailType::Work,
+        t if t.iter().any(|t| t == "home" || t == "personal") => EmailType::Personal,
+        _ => EmailType::Other,
+    }
+}
+
+fn phone_type(t: &[String]) -> PhoneType {
+    match t {
+        t if t.iter().any(|t| t == "fax") => PhoneType::Fax,
+        t if t.iter().any(|t| t == "pager") => PhoneType::Pager,
+        t if t.iter().any(|t| t == "cell" || t == "mobile" || t == "iphone") => PhoneType::Mobile,
+        t if t.iter().any(|t| t == "work") => PhoneType::Work,
+        t if t.iter().any(|t| t == "home") => PhoneType::Home,
+        _ => PhoneType::Other,
+    }
+}
+
+fn address_type(t: &[String]) -> AddressType {
+    match t {
+        t if t.iter().any(|t| t == "billing" || t == "x-billing") => AddressType::Billing,
+        t if t.iter().any(|t| t == "postal" || t == "x-postal") => AddressType::Postal,
+        t if t.iter().any(|t| t == "work") => AddressType::Work,
+        t if t.iter().any(|t| t == "home") => AddressType::Home,
+        _ => AddressType::Other,
+    }
+}
+
+fn info<T: ContactType>(typ: T, value: String, label: Option<String>, is_default: bool) -> ContactInformation<T> {
+    ContactInformation {
+        typ:        typ,
+        value:      value,
+        label:      label,
+        is_default: is_default,
+        ..Default::default()
+    }
+}
+
+fn parse_contact(c: &Component) -> Contact {
+    let mut contact = Contact::default();
+    contact.id = match c.text("UID") {
+        Some(uid) => strip_urn(&uid),
+        None      => record::new_id(),
+    };
+
+    // Apple-style labels: itemN.X-ABLABEL alongside itemN.EMAIL etc
+    let labels: BTreeMap<&String,String> = c.properties("X-ABLABEL").iter()
+        .filter_map(|l| l.group.as_ref().map(|g| (g, unescape_text(&l.value))))
+        .collect();
+    let label = |cl: &ContentLine| cl.group.as_ref().and_then(|g| labels.get(g).cloned());
+
+    match c.property("N") {
+        Some(n) => {
+            let f = fields(&n.value, 5);
+            contact.last_name = f[0].clone();
+            contact.first_name = f[1].clone();
+            contact.prefix = f[3].clone();
+            contact.suffix = f[4].clone();
+        },
+        None => contact.first_name = c.text("FN").unwrap_or("".to_string()),
+    }
+    contact.nickname = c.text("NICKNAME").unwrap_or("".to_string());
+    if let Some(org) = c.property("ORG") {
+        let f = fields(&org.value, 2);
+        contact.company = f[0].clone();
+        contact.department = f[1].clone();
+    }
+    contact.job_title = c.text("TITLE").unwrap_or("".to_string());
+    if let Some(d) = c.property("BDAY").and_then(parse_option_date) {
+        contact.birthday = d;
+    }
+    if let Some(d) = c.property("ANNIVERSARY").or(c.property("X-ANNIVERSARY")).and_then(parse_option_date) {
+        contact.anniversary = d;
+    }
+
+    for cl in c.properties("EMAIL") {
+        contact.emails.push(info(email_type(&types(cl)), unescape_text(&cl.value), label(cl), is_pref(cl)));
+    }
+    for cl in c.properties("TEL") {
+        contact.phones.push(info(phone_type(&types(cl)), strip_scheme(&unescape_text(&cl.value), "tel:"), label(cl), is_pref(cl)));
+    }
+    for cl in c.properties("URL") {
+        contact.online.push(info(OnlineType::Uri, unescape_text(&cl.value), label(cl), is_pref(cl)));
+    }
+    for cl in c.properties("IMPP") {
+        contact.online.push(info(OnlineType::Username, unescape_text(&cl.value), label(cl), is_pref(cl)));
+    }
+
+    for cl in c.properties("ADR") {
+        let f = fields(&cl.value, 7);
+        let mut a = Address::default();
+        a.typ = address_type(&types(cl));
+        a.label = cl.param("LABEL").map(|l| l.to_string()).or(label(cl));
+        // post office box and extended address go in with the street
+        a.street = f[..3].iter().filter(|s| !s.is_empty()).cloned().collect::<Vec<_>>().join("\n");
+        a.locality = f[3].clone();
+        a.region = f[4].clone();
+        a.postcode = f[5].clone();
+        a.country = f[6].clone();
+        a.is_default = is_pref(cl).to_string();
+        contact.addresses.push(a);
+    }
+
+    contact.notes = c.text("NOTE").unwrap_or("".to_string());
+
+    // inline photo data would need to go into a blob store; only links are kept
+    if let Some(photo) = c.property("PHOTO") {
+        let inline = photo.param("ENCODING").is_some() || photo.value.starts_with("data:");
+        if !inline {
+            let mut f = File::default();
+            f.blob_id = photo.value.clone();
+            f.typ = photo.param("MEDIATYPE").map(|t| t.to_string());
+            contact.avatar = Some(f);
+        }
+    }
+
+    contact
+}
+
+fn is_group(c: &Component) -> bool {
+    c.property("KIND").or(c.property("X-ADDRESSBOOKSERVER-KIND"))
+     .map(|k| k.value.eq_ignore_ascii_case("group"))
+     .unwrap_or(false)
+}
+
+fn parse_group(c: &Component) -> ContactGroup {
+    let mut g = ContactGroup::default();
+    g.id = match c.text("UID") {
+        Some(uid) => strip_urn(&uid),
+        None      => record::new_id(),
+    };
+    g.name = c.text("FN").unwrap_or("".to_string());
+    let mut members = c.properties("MEMBER");
+    members.extend(c.properties("X-ADDRESSBOOKSERVER-MEMBER"));
+    g.contact_ids = members.iter().map(|m| strip_urn(&m.value)).collect();
+    g
+}
+
+// read all the cards in a vCard document, sorting them into contacts and
+// groups
+pub fn parse_vcards(text: &str) -> Result<(Vec<Contact>,Vec<ContactGroup>),ParseError> {
+    let mut contacts = vec!();
+    let mut groups = vec!();
+    for c in try!(parse_components(text)).iter() {
+        if c.name != "VCARD" {
+            return Err(ParseError::InvalidStructure("VCARD".to_string()));
+        }
+        match is_group(c) {
+            true => groups.push(parse_group(c)),
+            _    => contacts.push(parse_contact(c)),
+        }
+    }
+    Ok((contacts, groups))
+}
+
+
+fn new_card(version: VCardVersion, id: &str) -> Component {
+    let mut c = Component::new("VCARD");
+    c.add("VERSION", match version {
+        VCardVersion::V3 => "3.0",
+        VCardVersion::V4 => "4.0",
+    });
+    c.add("PRODID", PRODID);
+    c.add_text("UID", id);
+    c
+}
+
+fn join_fields(f: &[&String]) -> String {
+    f.iter().map(|s| escape_text(s)).collect::<Vec<_>>().join(";")
+}
+
+// the display name: the full name if there is one, or failing that
+// something else that identifies the contact
+fn full_name(c: &Contact) -> String {
+    let name = [&c.prefix, &c.first_name, &c.last_name, &c.suffix].iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.as_ref())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    match name.is_empty() {
+        false => name,
+        _ if !c.nickname.is_empty() => c.nickname.clone(),
+        _ if !c.company.is_empty()  => c.company.clone(),
+        _ => c.emails.first().map(|e| e.value.clone()).unwrap_or("".to_string()),
+    }
+}
+
+// add a typed property, with its preference and label. labels go in an
+// itemN group with an X-ABLABEL, which is what everyone understands.
+fn add_info(card: &mut Component, version: VCardVersion, name: &str, value: &str, typ: Option<&str>, label: &Option<String>, is_default: bool, item: &mut usize) {
+    let mut cl = ContentLine::new(name, value);
+    let mut t: Vec<String> = typ.iter().map(|t| t.to_string()).collect();
+    if is_default {
+        match version {
+            VCardVersion::V3 => t.push("pref".to_string()),
+            VCardVersion::V4 => cl = cl.with_param("PREF", "1"),
+        }
+    }
+    if !t.is_empty() {
+        cl.params.push(("TYPE".to_string(), t));
+    }
+    if let Some(ref l) = *label {
+        *item += 1;
+        let group = format!("item{}", item);
+        cl.group = Some(group.clone());
+        card.properties.push(cl);
+        let mut lcl = ContentLine::new("X-ABLABEL", &escape_text(l));
+        lcl.group = Some(group);
+        card.properties.push(lcl);
+    }
+    else {
+        card.properties.push(cl);
+    }
+}
+
+fn add_date(card: &mut Component, version: VCardVersion, name: &str, d: &OptionDate) {
+    if let Some((v, param)) = format_option_date(d, version) {
+        let mut cl = ContentLine::new(name, &v);
+        if let Some((pn, pv)) = param {
+            cl = cl.with_param(pn, pv);
+        }
+        card.properties.push(cl);
+    }
+}
+
+pub fn contact_to_vcard(c: &Contact, version: VCardVersion) -> String {
+    let mut card = new_card(version, &c.id);
+    let mut item = 0;
+
+    card.add_text("FN", &full_name(c));
+    card.add("N", &join_fields(&[&c.last_name, &c.first_name, &"".to_string(), &c.prefix, &c.suffix]));
+    if !c.nickname.is_empty() {
+        card.add_text("NICKNAME", &c.nickname);
+    }
+    if !c.company.is_empty() || !c.department.is_empty() {
+        card.add("ORG", &join_fields(&[&c.company, &c.department]));
+    }
+    if !c.job_title.is_empty() {
+        card.add_text("TITLE", &c.job_title);
+    }
+
+    add_date(&mut card, version, "BDAY", &c.birthday);
+    add_date(&mut card, version, match version {
+        VCardVersion::V3 => "X-ANNIVERSARY",
+        VCardVersion::V4 => "ANNIVERSARY",
+    }, &c.anniversary);
+
+    for e in c.emails.iter() {
+        let typ = match e.typ {
+            EmailType::Personal => Some("home"),
+            EmailType::Work     => Some("work"),
+            EmailType::Other    => None,
+        };
+        add_info(&mut card, version, "EMAIL", &escape_text(&e.value), typ, &e.label, e.is_default, &mut item);
+    }
+    for p in c.phones.iter() {
+        let typ = match p.typ {
+            PhoneType::Home   => Some("home"),
+            PhoneType::Work   => Some("work"),
+            PhoneType::Mobile => Some("cell"),
+            PhoneType::Fax    => Some("fax"),
+            PhoneType::Pager  => Some("pager"),
+            PhoneType::Other  => None,
+        };
+        add_info(&mut card, version, "TEL", &escape_text(&p.value), typ, &p.label, p.is_default, &mut item);
+    }
+    for o in c.online.iter() {
+        let name = match o.typ {
+            OnlineType::Uri => "URL",
+            _               => "IMPP",
+        };
+        add_info(&mut card, version, name, &escape_text(&o.value), None, &o.label, o.is_default, &mut item);
+    }
+
+    for a in c.addresses.iter() {
+        let typ = match a.typ {
+            AddressType::Home    => Some("home"),
+            AddressType::Work    => Some("work"),
+            AddressType::Billing => Some("x-billing"),
+            AddressType::Postal  => Some("postal"),
+            AddressType::Other   => None,
+        };
+        let empty = "".to_string();
+        let value = join_fields(&[&empty, &empty, &a.street, &a.locality, &a.region, &a.postcode, &a.country]);
+        // 4.0 has a parameter for the label; 3.0 gets the Apple-style one
+        let (label, param) = match version {
+            VCardVersion::V4 => (None, a.label.clone()),
+            VCardVersion::V3 => (a.label.clone(), None),
+        };
+        let before = card.properties.len();
+        add_info(&mut card, version, "ADR", &value, typ, &label, a.is_default == "true", &mut item);
+        if let Some(l) = param {
+            card.properties[before].params.push(("LABEL".to_string(), vec!(l)));
+        }
+    }
+
+    if !c.notes.is_empty() {
+        card.add_text("NOTE", &c.notes);
+    }
+
+    if let Some(ref f) = c.avatar {
+        let mut cl = ContentLine::new("PHOTO", &f.blob_id);
+        match version {
+            VCardVersion::V3 => cl = cl.with_param("VALUE", "uri"),
+            VCardVersion::V4 => if let Some(ref t) = f.typ {
+                cl = cl.with_param("MEDIATYPE", t);
+            },
+        }
+        card.properties.push(cl);
+    }
+
+    card.to_string()
+}
+
+pub fn group_to_vcard(g: &ContactGroup, version: VCardVersion) -> String {
+    let mut card = new_card(version, &g.id);
+    let (kind, member) = match version {
+        VCardVersion::V3 => ("X-ADDRESSBOOKSERVER-KIND", "X-ADDRESSBOOKSERVER-MEMBER"),
+        VCardVersion::V4 => ("KIND", "MEMBER"),
+    };
+    card.add(kind, "group");
+    card.add_text("FN", &g.name);
+    if version == VCardVersion::V3 {
+        card.add("N", &join_fields(&[&g.name]));
+    }
+    for id in g.contact_ids.iter() {
+        card.add(member, &format!("urn:uuid:{}", id));
+    }
+    card.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use content_line::ContentLine;
+    use contact::{OptionDate, Address};
+    use super::*;
+
+    fn date(line: &str) -> Option<OptionDate> {
+        parse_option_date(&ContentLine::parse(line).unwrap())
+    }
+
+    fn ymd(y: Option<u16>, m: Option<u8>, d: Option<u8>) -> Option<OptionDate> {
+        Some(OptionDate { y: y, m: m, d: d })
+    }
+
+    #[test]
+    fn full_dates() {
+        assert_eq!(date("BDAY:19960415"), ymd(Some(1996), Some(4), Some(15)));
+        assert_eq!(date("BDAY:1996-04-15T120000Z"), ymd(Some(1996), Some(4), Some(15)));
+    }
+
+    #[test]
+    fn year_less_dates() {
+        assert_eq!(date("BDAY:--0415"), ymd(None, Some(4), Some(15)));
+        assert_eq!(date("BDAY:--04-15"), ymd(None, Some(4), Some(15)));
+        assert_eq!(date("BDAY:--04"), ymd(None, Some(4), None));
+        assert_eq!(date("BDAY:---15"), ymd(None, None, Some(15)));
+    }
+
+    #[test]
+    fn apple_omitted_year() {
+        assert_eq!(date("BDAY;X-APPLE-OMIT-YEAR=1604:1604-04-15"), ymd(None, Some(4), Some(15)));
+        assert_eq!(date("BDAY;X-APPLE-OMIT-YEAR=1604:1990-04-15"), ymd(Some(1990), Some(4), Some(15)));
+
+        let (v, param) = format_option_date(&OptionDate { y: None, m: Some(4), d: Some(15) }, VCardVersion::V3).unwrap();
+        assert_eq!(v, "1604-04-15");
+        assert_eq!(param, Some(("X-APPLE-OMIT-YEAR", "1604")));
+    }
+
+    #[test]
+    fn round_trip() {
+        let card = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:urn:uuid:c1\r\nFN:Dr Ada Lovelace\r\n\
+                    N:Lovelace;Ada;;Dr;\r\nNICKNAME:Ada\r\nORG:Analytical;Engines\r\nTITLE:Programmer\r\n\
+                    BDAY:--1210\r\nANNIVERSARY:18350708\r\n\
+                    EMAIL;TYPE=work;PREF=1:ada@example.com\r\nitem1.EMAIL:ada@example.org\r\nitem1.X-ABLABEL:old\r\n\
+                    TEL;TYPE=cell:+44 20 7946 0000\r\n\
+                    ADR;TYPE=home;LABEL=\"12 St James's Square^nLondon\":;;12 St James's Square;London;;SW1Y 4JH;UK\r\n\
+                    NOTE:counts\\, and\\nnotes\r\nEND:VCARD\r\n";
+        let (contacts, _) = parse_vcards(card).unwrap();
+        let c = &contacts[0];
+        assert_eq!((c.id.as_ref(), c.first_name.as_ref(), c.last_name.as_ref(), c.prefix.as_ref()), ("c1", "Ada", "Lovelace", "Dr"));
+        assert_eq!((c.company.as_ref(), c.department.as_ref()), ("Analytical", "Engines"));
+        assert_eq!(c.anniversary, OptionDate { y: Some(1835), m: Some(7), d: Some(8) });
+        assert_eq!(c.emails.len(), 2);
+        assert_eq!(c.emails[1].label, Some("old".to_string()));
+        assert_eq!(c.addresses[0].label, Some("12 St James's Square\nLondon".to_string()));
+        assert_eq!(c.notes, "counts, and\nnotes");
+
+        for &version in [VCardVersion::V3, VCardVersion::V4].iter() {
+            let (again, _) = parse_vcards(&contact_to_vcard(c, version)).unwrap();
+            assert_eq!(again[0], *c, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn garbage_dates() {
+        assert_eq!(date("BDAY:--aéb"), None);
+        assert_eq!(date("BDAY:--ééé"), None);
+        assert_eq!(date("BDAY:1996-é4"), ymd(Some(1996), None, None));
+        assert_eq!(date("BDAY:tomorrow"), None);
+    }
+
+    #[test]
+    fn labels_cannot_inject_lines() {
+        let cards = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:c1\r\nFN:A\r\nBDAY:--0415\r\nEND:VCARD\r\n";
+        let (mut contacts, _) = parse_vcards(cards).unwrap();
+        assert_eq!(Some(contacts[0].birthday.clone()), ymd(None, Some(4), Some(15)));
+
+        let mut a = Address::default();
+        a.street = "1 Main St".to_string();
+        a.label = Some("Home\"\r\nEMAIL:evil@example.com".to_string());
+        contacts[0].addresses.push(a);
+        let out = contact_to_vcard(&contacts[0], VCardVersion::V4);
+        assert!(!out.contains("\r\nEMAIL"));
+        assert!(out.contains("\r\nBDAY:--0415\r\n"));
+        let (again, _) = parse_vcards(&out).unwrap();
+        assert_eq!(again[0].addresses[0].label, Some("Home\"\nEMAIL:evil@example.com".to_string()));
+        assert!(again[0].emails.is_empty());
+        assert_eq!(again[0].birthday, contacts[0].birthday);
+    }
+}